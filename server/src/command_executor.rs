@@ -1,28 +1,125 @@
 /// Responsible for running commands, per-client, with retries.
 /// Scaling to large numbers of clients is not an explicit goal of this architecture.
 /// It is intended to robustly support multiple tabs open displaying shble output for a single user.
-use crate::byte_trie::ByteTrie;
-use crate::encoding;
-use crate::parsers::IndexFilter;
+use crate::encoding::{self, OutputEncoding};
+use crate::parsers::{FieldSeparator, IndexFilters};
 use crate::transformers;
 use actix::dev::{MessageResponse, ResponseChannel};
 use actix::prelude::*;
 use actix_web;
 use actix_web::web;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use futures::Stream;
 use regex::bytes::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::io::{self, BufReader, Read};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
 use std::num::Wrapping;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
 use std::process::{Child, Command, ExitStatus, Stdio};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use ulid::Ulid;
 
+/// Puts `fd` into non-blocking mode so `check_children`'s per-tick partial reads return
+/// immediately with whatever is already buffered instead of blocking the actor thread until more
+/// output (or EOF) arrives.
+fn set_nonblocking(fd: std::os::unix::io::RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL, 0) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Drains whatever is immediately available from `reader` into `buf` without blocking. A
+/// `WouldBlock` error just means there's nothing new this tick, which is the expected steady
+/// state for a still-running child between reads, not a failure.
+fn read_available<R: Read>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<()> {
+    let mut chunk = [0u8; 8192];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Opens a pseudo-terminal pair via the POSIX `posix_openpt`/`grantpt`/`unlockpt`/`ptsname` calls
+/// (the same libc surface `set_nonblocking` already reaches for), so an interactive `run` can
+/// attach a child's stdin/stdout/stderr to the slave side while the actor reads/writes the master
+/// side. Returns `(master, slave)`.
+fn open_pty() -> io::Result<(File, File)> {
+    let master_fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+    if master_fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::grantpt(master_fd) } < 0 || unsafe { libc::unlockpt(master_fd) } < 0 {
+        let error = io::Error::last_os_error();
+        unsafe { libc::close(master_fd) };
+        return Err(error);
+    }
+    let slave_name = unsafe {
+        let name_ptr = libc::ptsname(master_fd);
+        if name_ptr.is_null() {
+            let error = io::Error::last_os_error();
+            libc::close(master_fd);
+            return Err(error);
+        }
+        CStr::from_ptr(name_ptr).to_owned()
+    };
+    let slave_path = slave_name.to_str().map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    let master = unsafe { File::from_raw_fd(master_fd) };
+    let slave = OpenOptions::new().read(true).write(true).custom_flags(libc::O_NOCTTY).open(slave_path)?;
+    Ok((master, slave))
+}
+
+/// Issues the `TIOCSWINSZ` ioctl so programs attached to a PTY (e.g. `top`, `vim`) learn about a
+/// client-side terminal resize instead of continuing to render for whatever size they started at.
+fn resize_pty(fd: RawFd, rows: u16, cols: u16) -> io::Result<()> {
+    let winsize = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &winsize) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// How long `cancel`'s initial SIGTERM gets to end a command gracefully before `check_children`
+/// escalates to SIGKILL.
+const CANCELLATION_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Sends `signal` to `pid` directly, rather than via `Child::kill` (which only ever sends
+/// SIGKILL), so `cancel` can ask for a graceful SIGTERM first. A process that's already gone
+/// (`ESRCH`) isn't an error here - that's `check_children`'s `try_wait` to notice, same as
+/// `Child::kill` already treats "already exited" as a non-error.
+fn send_signal(pid: u32, signal: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } < 0 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::ESRCH) {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 enum CommandStatus {
-    Idle,
     Canceled {
         id: usize,
         command: String,
@@ -31,6 +128,9 @@ enum CommandStatus {
         id: usize,
         child: Child,
         command: String,
+        /// When `check_children` should give up waiting for the SIGTERM sent by `cancel` to end
+        /// things gracefully and escalate to SIGKILL.
+        deadline: Instant,
     },
     CancellationFailed {
         id: usize,
@@ -40,6 +140,21 @@ enum CommandStatus {
         id: usize,
         child: Child,
         command: String,
+        /// Raw stdout/stderr bytes read so far, accumulated across ticks rather than read once
+        /// at exit. Kept in full (not just the unflushed tail) so the eventual `Finished` variant
+        /// still has everything, exactly as before this buffer existed.
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+        /// How many `encoding::stdout_chunks`/`stderr_chunks` entries have already been sent for
+        /// this run, so each tick's re-encode of the (growing) buffer only emits the new ones via
+        /// `resume_from` instead of resending everything already on the wire.
+        stdout_chunks_sent: usize,
+        stderr_chunks_sent: usize,
+        /// The PTY master, for an interactive run that attached the child's stdin/stdout/stderr
+        /// to a PTY slave instead of piping them. Merged PTY output is read into `stdout` the same
+        /// as a piped run's; `stderr` stays empty, since a PTY has no separate error stream.
+        /// `None` for an ordinary piped run.
+        pty: Option<File>,
     },
     Failed {
         id: usize,
@@ -54,40 +169,207 @@ enum CommandStatus {
     },
 }
 
+/// How many already-sent SSE events `ServerConnection::recent_events` keeps around for
+/// `replay_from_ring_buffer` - large enough to smooth over a typical reconnect blip without
+/// holding a whole run's output a second time (that's what `CommandStatus::Finished`'s
+/// `stdout`/`stderr` are for, and `replay_since` already falls back to those).
+const RECENT_EVENTS_CAPACITY: usize = 256;
+
+/// One concurrently-running command, keyed by its `id` in `ServerConnection::commands`. Each
+/// command gets its own `line_options`/`row_options` so a client can filter several interleaved
+/// streams independently instead of one filter pair applying to whatever command happens to be
+/// current.
+struct CommandEntry {
+    status: CommandStatus,
+    line_options: transformers::Options,
+    row_options: transformers::Options,
+}
+
+impl CommandEntry {
+    fn new(status: CommandStatus) -> Self {
+        CommandEntry {
+            status,
+            line_options: transformers::Options::default(),
+            row_options: transformers::Options::default(),
+        }
+    }
+}
+
 struct ServerConnection {
     last_active: Instant,
-    line_options: transformers::Options,
+    /// Every command the client has started that hasn't been reaped yet, keyed by the `id` `run`
+    /// allocated for it - a client can have several running (or recently finished) at once, and
+    /// every emitted chunk is tagged with the `id` of the command it came from so the client can
+    /// demultiplex the interleaved stream.
+    commands: HashMap<usize, CommandEntry>,
+    content_defined_chunks: bool,
+    encryption_key: Option<[u8; 32]>,
+    framed_chunks: bool,
+    max_chunk_size: usize,
+    max_output_size: usize,
+    output_encoding: OutputEncoding,
+    /// Encoded chunks waiting for room in `sender`'s bounded channel - see `flush_pending`. Queued
+    /// here instead of dropped so a slow client applies backpressure to the command instead of
+    /// silently losing output.
+    pending: VecDeque<web::Bytes>,
     receiver: Option<mpsc::Receiver<web::Bytes>>,
-    row_options: transformers::Options,
+    /// The last `RECENT_EVENTS_CAPACITY` SSE events sent to this client across every command it
+    /// has run, oldest first, each tagged with the monotonic sequence number it was sent under -
+    /// used to resume a dropped connection without recomputing transforms/encoding from scratch.
+    /// Shared across concurrent commands (rather than reset per-run) so a reconnect mid-stream
+    /// can still replay the interleaved output of whatever was in flight. See
+    /// `replay_from_ring_buffer`/`replay_from_seq`.
+    recent_events: VecDeque<(u64, web::Bytes)>,
+    /// The sequence number the next `send_and_record` call will tag its event with. Monotonic for
+    /// the lifetime of the connection, not per-command, so `last_seq` unambiguously identifies a
+    /// position across interleaved commands.
+    next_seq: u64,
     sender: mpsc::Sender<web::Bytes>,
-    status: CommandStatus,
 }
 
 impl ServerConnection {
     fn new(sender: mpsc::Sender<web::Bytes>, receiver: mpsc::Receiver<web::Bytes>) -> Self {
         ServerConnection {
             last_active: Instant::now(),
-            line_options: transformers::Options::default(),
+            commands: HashMap::new(),
+            content_defined_chunks: false,
+            encryption_key: None,
+            framed_chunks: false,
+            max_chunk_size: encoding::default_max_chunk_size(),
+            max_output_size: encoding::default_max_output_size(),
+            output_encoding: OutputEncoding::default(),
+            pending: VecDeque::new(),
             receiver: Some(receiver),
-            row_options: transformers::Options::default(),
+            recent_events: VecDeque::with_capacity(RECENT_EVENTS_CAPACITY),
+            next_seq: 0,
             sender,
-            status: CommandStatus::Idle,
         }
     }
 }
 
+/// Drains `pending` into `sender` in order, for as long as the bounded channel has room. Leaves
+/// whatever doesn't fit queued for a later call instead of dropping it - the channel being
+/// momentarily full just means the client's consuming slower than the command is producing, not
+/// that the output is unwanted. Only a `Closed` channel (the client is truly gone) drops the
+/// backlog, since nothing will ever drain it from here on.
+fn flush_pending(sender: &mut mpsc::Sender<web::Bytes>, pending: &mut VecDeque<web::Bytes>, client_id: &Ulid) {
+    while let Some(bytes) = pending.front() {
+        match sender.try_send(bytes.clone()) {
+            Ok(()) => {
+                pending.pop_front();
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => break,
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                log::error!("Dropping buffered output for a client that's gone: client_id: {}", client_id);
+                pending.clear();
+                break;
+            }
+        }
+    }
+}
+
+/// Sends `bytes` over `sender` and records it in `recent_events` under the next sequence number,
+/// evicting the oldest buffered event if that would push the buffer past `RECENT_EVENTS_CAPACITY`.
+/// A free function, rather than a method taking `&mut ServerConnection`, so callers already
+/// holding a `ref`-bound borrow of a `CommandEntry`'s `status` (see `replay_since`/
+/// `process_output`) can still reach the disjoint `sender`/`pending`/`recent_events`/`next_seq`
+/// fields.
+fn send_and_record(
+    sender: &mut mpsc::Sender<web::Bytes>,
+    pending: &mut VecDeque<web::Bytes>,
+    recent_events: &mut VecDeque<(u64, web::Bytes)>,
+    next_seq: &mut u64,
+    client_id: &Ulid,
+    bytes: web::Bytes,
+) {
+    if recent_events.len() >= RECENT_EVENTS_CAPACITY {
+        recent_events.pop_front();
+    }
+    recent_events.push_back((*next_seq, bytes.clone()));
+    *next_seq += 1;
+
+    pending.push_back(bytes);
+    flush_pending(sender, pending, client_id);
+}
+
+/// Extracts the value of an SSE event's leading `id: ` line, as produced by `ChunkWriter`'s
+/// per-chunk header or `status_message`/`truncated_message` - used to find where `last_event_id`
+/// sits in `ServerConnection::recent_events`.
+fn event_id_str(bytes: &[u8]) -> Option<&str> {
+    std::str::from_utf8(bytes).ok()?.lines().next()?.strip_prefix("id: ")
+}
+
 pub struct UnconnectedError {}
 
+/// How often `CheckChildren`'s otherwise-immediately-requeued loop actually re-runs the idle
+/// sweep. `check_children` needs to run every tick to keep up with running children, but scanning
+/// every client's `last_active` that often is wasted work.
+const REAP_IDLE_INTERVAL: Duration = Duration::from_secs(30);
+
 pub struct CommandExecutor {
     next_id: Wrapping<usize>,
     clients: HashMap<Ulid, ServerConnection>,
+    /// How long a client can go without a request (a setter, a `Listen` reconnect, etc.) before
+    /// `reap_idle` tears it down - a closed browser tab otherwise leaves its connection, and any
+    /// `Running` child, alive forever.
+    idle_ttl: Duration,
+    last_reaped: Instant,
 }
 
 impl CommandExecutor {
-    pub fn new() -> Self {
+    pub fn new(idle_ttl: Duration) -> Self {
         CommandExecutor {
             next_id: Wrapping(0usize),
             clients: HashMap::new(),
+            idle_ttl,
+            last_reaped: Instant::now(),
+        }
+    }
+
+    /// Tears down clients that have been idle past `idle_ttl`. Every `Running` command is nudged
+    /// onto the same SIGTERM-then-escalate path `cancel` uses, so `check_children`'s existing
+    /// `Canceling` handling keeps driving it towards a clean exit on later ticks; a client with no
+    /// `Running` or `Canceling` commands left is dropped outright, which drops its `sender` and
+    /// ends the client's SSE stream.
+    fn reap_idle(&mut self) {
+        if self.last_reaped.elapsed() < REAP_IDLE_INTERVAL {
+            return;
+        }
+        self.last_reaped = Instant::now();
+
+        let idle: Vec<Ulid> = self
+            .clients
+            .iter()
+            .filter(|(_, connection)| connection.last_active.elapsed() >= self.idle_ttl)
+            .map(|(client_id, _)| *client_id)
+            .collect();
+
+        for client_id in idle {
+            let running_ids: Vec<usize> = match self.clients.get(&client_id) {
+                None => continue,
+                Some(connection) => connection
+                    .commands
+                    .values()
+                    .filter_map(|entry| match entry.status {
+                        CommandStatus::Running { id, .. } => Some(id),
+                        _ => None,
+                    })
+                    .collect(),
+            };
+
+            for id in running_ids {
+                let _ = self.cancel(&client_id, id);
+            }
+
+            let still_active = self.clients.get(&client_id).map_or(false, |connection| {
+                connection
+                    .commands
+                    .values()
+                    .any(|entry| matches!(entry.status, CommandStatus::Running { .. } | CommandStatus::Canceling { .. }))
+            });
+            if !still_active {
+                self.clients.remove(&client_id);
+            }
         }
     }
 
@@ -100,7 +382,18 @@ impl CommandExecutor {
         client_id
     }
 
-    fn listen(&mut self, client_id: &Ulid) -> Result<ClientConnection, UnconnectedError> {
+    fn listen(
+        &mut self,
+        client_id: &Ulid,
+        output_encoding: OutputEncoding,
+        framed_chunks: bool,
+        encryption_key: Option<[u8; 32]>,
+        content_defined_chunks: bool,
+        max_chunk_size: usize,
+        max_output_size: usize,
+        last_event_id: Option<&str>,
+        last_seq: Option<u64>,
+    ) -> Result<ClientConnection, UnconnectedError> {
         match self.clients.remove(client_id) {
             None => Err(UnconnectedError {}),
             Some(mut connection) => {
@@ -109,6 +402,24 @@ impl CommandExecutor {
                     None => Err(UnconnectedError {}),
                     Some(receiver) => {
                         connection.receiver = None;
+                        connection.output_encoding = output_encoding;
+                        connection.framed_chunks = framed_chunks;
+                        connection.encryption_key = encryption_key;
+                        connection.content_defined_chunks = content_defined_chunks;
+                        connection.max_chunk_size = max_chunk_size;
+                        connection.max_output_size = max_output_size;
+                        // `last_seq` takes priority when present - it pinpoints exactly what the
+                        // client has already seen, where `last_event_id` only does so within the
+                        // ring buffer's window (otherwise falling back to a from-scratch replay).
+                        if let Some(last_seq) = last_seq {
+                            Self::replay_from_seq(&mut connection, last_seq);
+                        } else if let Some(last_event_id) = last_event_id {
+                            if !Self::replay_from_ring_buffer(&mut connection, last_event_id) {
+                                if let Some(parsed) = parse_last_event_id(last_event_id) {
+                                    Self::replay_since(&mut connection, client_id, parsed);
+                                }
+                            }
+                        }
                         self.clients.insert(*client_id, connection);
                         Ok(ClientConnection { receiver })
                     },
@@ -117,134 +428,334 @@ impl CommandExecutor {
         }
     }
 
-    fn process_output(&mut self, client_id: &Ulid) {
-        match self.clients.get(client_id) {
+    /// Replays every event in `recent_events` after the one whose `id:` line exactly matches
+    /// `last_event_id`, so a client that reconnects within the ring buffer's window gets the
+    /// buffered events directly instead of `replay_since` recomputing transforms/encoding from the
+    /// run's full stdout/stderr. Returns `false` (without sending anything) if `last_event_id`
+    /// isn't in the buffer - either it's stale (evicted) or unrecognized - so the caller can fall
+    /// back to `replay_since`.
+    fn replay_from_ring_buffer(connection: &mut ServerConnection, last_event_id: &str) -> bool {
+        let position = connection
+            .recent_events
+            .iter()
+            .position(|(_, bytes)| event_id_str(bytes) == Some(last_event_id));
+
+        match position {
+            None => false,
+            Some(position) => {
+                let to_resend: Vec<web::Bytes> = connection.recent_events.iter().skip(position + 1).map(|(_, bytes)| bytes.clone()).collect();
+                for bytes in to_resend {
+                    if let Err(error) = connection.sender.try_send(bytes) {
+                        log::error!("Failed to replay a buffered event: error: {:#?}", error);
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    /// Replays every buffered event with a sequence number greater than `last_seq` - the numeric
+    /// analogue of `replay_from_ring_buffer`'s `id:`-line lookup, for a client that tracks its own
+    /// sequence counter instead of parsing SSE ids back out to resume correctly. If `last_seq` is
+    /// stale (older than everything still buffered), every buffered event is replayed and the
+    /// evicted ones in between are silently skipped; if it's at or past the newest buffered event,
+    /// nothing is replayed. The caller has no better fallback for an arbitrary sequence number the way
+    /// `replay_from_ring_buffer` falls back to `replay_since`.
+    fn replay_from_seq(connection: &mut ServerConnection, last_seq: u64) {
+        let to_resend: Vec<web::Bytes> = connection
+            .recent_events
+            .iter()
+            .filter(|(seq, _)| *seq > last_seq)
+            .map(|(_, bytes)| bytes.clone())
+            .collect();
+        for bytes in to_resend {
+            if let Err(error) = connection.sender.try_send(bytes) {
+                log::error!("Failed to replay a buffered event: error: {:#?}", error);
+            }
+        }
+    }
+
+    /// Replays the tail of a finished run's output the client hasn't seen yet, keyed off the
+    /// `Last-Event-ID` browsers' EventSource sends automatically on reconnect - so a dropped
+    /// connection resumes the same job instead of re-streaming it from chunk 0. Falls through to
+    /// an ordinary (empty, for a finished run) stream if the header's run id no longer has a
+    /// matching command, e.g. because it was reaped since the client last saw it.
+    fn replay_since(connection: &mut ServerConnection, client_id: &Ulid, last_event_id: LastEventId) {
+        let LastEventId { run_id, event_type, index } = last_event_id;
+
+        let ServerConnection { ref commands, ref mut sender, ref mut pending, ref mut recent_events, ref mut next_seq, .. } = *connection;
+
+        let entry = match commands.get(&run_id) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        if let CommandStatus::Finished {
+            id,
+            command: _,
+            ref status,
+            ref stderr,
+            ref stdout,
+        } = entry.status
+        {
+            // stdout is always sent in full before stderr begins, and stderr in full before the
+            // closing status event, so whichever of the three the client last saw tells us the
+            // other two's delivery state without needing to track them separately.
+            let (stdout_resume, stderr_resume) = match event_type.as_str() {
+                "stdout" => (index.map_or(0, |index| index + 1), 0),
+                "stderr" => (usize::MAX, index.map_or(0, |index| index + 1)),
+                // "status" is the last event of a run; if the client saw it, it already has
+                // everything and there's nothing left to replay.
+                _ => return,
+            };
+
+            let transformed_stdout =
+                transformers::transform_2d(&entry.line_options, &entry.row_options, stdout);
+            let stdout_chunks = encoding::stdout_chunks(
+                &transformed_stdout,
+                id,
+                connection.output_encoding,
+                connection.framed_chunks,
+                connection.encryption_key.as_ref(),
+                stdout_resume,
+                connection.content_defined_chunks,
+                connection.max_chunk_size,
+                connection.max_output_size,
+            );
+            for chunk in stdout_chunks {
+                send_and_record(sender, pending, recent_events, next_seq, client_id, chunk);
+            }
+            let stderr_chunks = encoding::stderr_chunks(
+                stderr,
+                id,
+                connection.output_encoding,
+                connection.framed_chunks,
+                connection.encryption_key.as_ref(),
+                stderr_resume,
+                connection.content_defined_chunks,
+                connection.max_chunk_size,
+                connection.max_output_size,
+            );
+            for chunk in stderr_chunks {
+                send_and_record(sender, pending, recent_events, next_seq, client_id, chunk);
+            }
+            send_and_record(sender, pending, recent_events, next_seq, client_id, encoding::status_message(status, id));
+        }
+    }
+
+    fn process_output(&mut self, client_id: &Ulid, id: usize) {
+        match self.clients.get_mut(client_id) {
             None => {
                 log::error!("A client that no longer exists was asked for output: client_id: {}", client_id);
             },
             Some(connnection) => {
+                let ServerConnection { ref commands, ref mut sender, ref mut pending, ref mut recent_events, ref mut next_seq, .. } = *connnection;
+
+                let entry = match commands.get(&id) {
+                    Some(entry) => entry,
+                    None => return,
+                };
+
                 if let CommandStatus::Finished {
                     id,
                     command: _,
                     ref status,
                     ref stderr,
                     ref stdout,
-                } = connnection.status
+                } = entry.status
                 {
                     let transformed_stdout =
-                        transformers::transform_2d(&connnection.line_options, &connnection.row_options, stdout);
-                    match encoding::stdout_chunks(&transformed_stdout, id) {
-                        Err(error) => {
-                            log::error!("Failed to encode stdout: client_id: {}, error: {:#?}", client_id, error);
-                        },
-                        Ok(stdout_chunks) => {
-                            match encoding::stderr_chunks(stderr, id) {
-                                Err(error) => {
-                                    log::error!("Failed to encode stderr: client_id: {}, error: {:#?}", client_id, error);
-                                },
-                                Ok(stderr_chunks) => {
-                                    for chunk in stdout_chunks {
-                                        if let Err(error) = connnection.sender.try_send(chunk) {
-                                            log::error!("Failed to send a chunk of stdout, client disconnected or there is too much chatter: client_id: {}, error: {:#?}", client_id, error);
-                                        }
-                                    }
-                                    for chunk in stderr_chunks {
-                                        if let Err(error) = connnection.sender.try_send(chunk) {
-                                            log::error!("Failed to send a chunk of stderr, client disconnected or there is too much chatter: client_id: {}, error: {:#?}", client_id, error);
-                                        }
-                                    }
-                                    if let Err(error) = connnection.sender.try_send(encoding::status_message(status, id)) {
-                                        log::error!("Failed to send an exit status, client disconnected or there is too much chatter: client_id: {}, error: {:#?}", client_id, error);
-                                    }
-                                },
-                            }
-                        },
+                        transformers::transform_2d(&entry.line_options, &entry.row_options, stdout);
+                    let stdout_chunks = encoding::stdout_chunks(
+                        &transformed_stdout,
+                        id,
+                        connnection.output_encoding,
+                        connnection.framed_chunks,
+                        connnection.encryption_key.as_ref(),
+                        0,
+                        connnection.content_defined_chunks,
+                        connnection.max_chunk_size,
+                        connnection.max_output_size,
+                    );
+                    let stderr_chunks = encoding::stderr_chunks(
+                        stderr,
+                        id,
+                        connnection.output_encoding,
+                        connnection.framed_chunks,
+                        connnection.encryption_key.as_ref(),
+                        0,
+                        connnection.content_defined_chunks,
+                        connnection.max_chunk_size,
+                        connnection.max_output_size,
+                    );
+                    for chunk in stdout_chunks {
+                        send_and_record(sender, pending, recent_events, next_seq, client_id, chunk);
+                    }
+                    for chunk in stderr_chunks {
+                        send_and_record(sender, pending, recent_events, next_seq, client_id, chunk);
                     }
+                    send_and_record(sender, pending, recent_events, next_seq, client_id, encoding::status_message(status, id));
                 }
             }
         };
     }
 
-    fn check_children(&mut self) -> Vec<Ulid> {
+    /// Polls every in-flight command across every client. A command that just finished has its
+    /// output and status sent from here directly, so none is queued for `ProcessOutput` and the
+    /// returned `(client_id, id)` list is always empty. A `continue` inside a command's match arm only skips the rest of that command, since a
+    /// different command on the same client may still need this tick's work done.
+    fn check_children(&mut self) -> Vec<(Ulid, usize)> {
         let mut finished_children = vec![];
         for (client_id, connection) in self.clients.iter_mut() {
-            match connection.status {
-                CommandStatus::Canceling {
-                    id,
-                    ref mut child,
-                    ref command,
-                } => {
-                    if let Err(error) = child.try_wait() {
-                        match error.kind() {
-                            io::ErrorKind::InvalidInput => (),
-                            _ => {
-                                connection.status = CommandStatus::Failed { id, error };
-                                continue;
-                            }
-                        }
-                    }
+            // Give a connection whose `pending` backlog didn't drain last tick (the client's
+            // channel was `Full`, not `Closed`) another chance, even if this tick's status arm
+            // below has nothing new to send - otherwise a backlog only drains on the next send.
+            flush_pending(&mut connection.sender, &mut connection.pending, client_id);
+
+            let ServerConnection { ref mut commands, ref mut sender, ref mut pending, ref mut recent_events, ref mut next_seq, .. } = *connection;
 
-                    connection.status = CommandStatus::Canceled {
+            for entry in commands.values_mut() {
+                match entry.status {
+                    CommandStatus::Canceling {
                         id,
-                        command: command.into(),
-                    };
-                }
-                CommandStatus::Running {
-                    id,
-                    ref mut child,
-                    ref command,
-                } => match child.try_wait() {
-                    Err(error) => {
-                        connection.status = CommandStatus::Failed { id, error };
-                    }
-                    Ok(None) => (),
-                    Ok(Some(status)) => {
-                        let stderr = match child.stderr {
-                            None => vec![],
-                            Some(ref mut stderr) => {
-                                let mut reader = BufReader::new(stderr);
-                                let mut bytes = vec![];
-                                if let Err(error) = reader.read_to_end(&mut bytes) {
-                                    connection.status = CommandStatus::Failed { id, error };
-                                    continue;
-                                };
-                                bytes
+                        ref mut child,
+                        ref command,
+                        deadline,
+                    } => {
+                        match child.try_wait() {
+                            Err(error) => {
+                                entry.status = CommandStatus::Failed { id, error };
+                                continue;
                             }
-                        };
-                        match child.stdout {
-                            None => {
-                                connection.status = CommandStatus::Finished {
+                            Ok(Some(_)) => {
+                                entry.status = CommandStatus::Canceled {
                                     id,
                                     command: command.into(),
-                                    status,
-                                    stderr,
-                                    stdout: vec![],
                                 };
+                                continue;
                             }
-                            Some(ref mut stdout) => {
-                                let mut reader = BufReader::new(stdout);
-                                let mut bytes = vec![];
-                                match reader.read_to_end(&mut bytes) {
-                                    Err(error) => {
-                                        connection.status = CommandStatus::Failed { id, error };
-                                    }
-                                    _ => {
-                                        connection.status = CommandStatus::Finished {
-                                            id,
-                                            command: command.into(),
-                                            status,
-                                            stderr,
-                                            stdout: bytes,
-                                        };
-                                    }
-                                };
+                            Ok(None) => (),
+                        }
+
+                        // Still alive past the grace period `cancel`'s SIGTERM was given -
+                        // escalate to an unignorable SIGKILL so teardown is eventually guaranteed
+                        // regardless of whether the command handled the graceful request.
+                        if Instant::now() >= deadline {
+                            if let Err(error) = child.kill() {
+                                if error.kind() != io::ErrorKind::InvalidInput {
+                                    entry.status = CommandStatus::CancellationFailed { id, error };
+                                }
                             }
-                        };
-                        finished_children.push(client_id.clone());
+                        }
                     }
-                },
-                _ => (),
-            };
+                    CommandStatus::Running {
+                        id,
+                        ref mut child,
+                        ref command,
+                        ref mut stdout,
+                        ref mut stderr,
+                        ref mut stdout_chunks_sent,
+                        ref mut stderr_chunks_sent,
+                        ref mut pty,
+                    } => {
+                        if let Some(ref mut stdout_pipe) = child.stdout {
+                            if let Err(error) = read_available(stdout_pipe, stdout) {
+                                log::error!("Failed to read partial stdout for a streamed run: client_id: {}, error: {:#?}", client_id, error);
+                            }
+                        }
+                        if let Some(ref mut stderr_pipe) = child.stderr {
+                            if let Err(error) = read_available(stderr_pipe, stderr) {
+                                log::error!("Failed to read partial stderr for a streamed run: client_id: {}, error: {:#?}", client_id, error);
+                            }
+                        }
+                        // An interactive run has no separate stderr - the PTY merges both streams
+                        // - so its output is folded into `stdout` the same as a piped run's.
+                        if let Some(ref mut pty_master) = pty {
+                            if let Err(error) = read_available(pty_master, stdout) {
+                                // A PTY read failing with EIO once the child has exited (the
+                                // slave's last reference went away) is the normal end-of-session
+                                // signal, not a real error.
+                                if error.raw_os_error() != Some(libc::EIO) {
+                                    log::error!("Failed to read partial PTY output for an interactive run: client_id: {}, error: {:#?}", client_id, error);
+                                }
+                            }
+                        }
+
+                        let exited = match child.try_wait() {
+                            Err(error) => {
+                                entry.status = CommandStatus::Failed { id, error };
+                                continue;
+                            }
+                            Ok(exited) => exited,
+                        };
+
+                        // Only transform/emit up to the last complete record - `transform_2d`'s
+                        // `ByteTrie` separators operate on whole records, so a record split
+                        // across two ticks would otherwise get cut in half. At EOF everything
+                        // left is flushed regardless, since no more data is coming to complete a
+                        // trailing partial one.
+                        let stdout_boundary = if exited.is_some() {
+                            stdout.len()
+                        } else {
+                            transformers::complete_prefix_len(&entry.line_options, stdout)
+                        };
+
+                        if stdout_boundary > 0 {
+                            let transformed = transformers::transform_2d(&entry.line_options, &entry.row_options, &stdout[..stdout_boundary].to_vec());
+                            let chunks = encoding::stdout_chunks(
+                                &transformed,
+                                id,
+                                connection.output_encoding,
+                                connection.framed_chunks,
+                                connection.encryption_key.as_ref(),
+                                *stdout_chunks_sent,
+                                connection.content_defined_chunks,
+                                connection.max_chunk_size,
+                                connection.max_output_size,
+                            );
+                            *stdout_chunks_sent += chunks.len();
+                            for chunk in chunks {
+                                send_and_record(sender, pending, recent_events, next_seq, client_id, chunk);
+                            }
+                        }
+
+                        if !stderr.is_empty() {
+                            let chunks = encoding::stderr_chunks(
+                                stderr,
+                                id,
+                                connection.output_encoding,
+                                connection.framed_chunks,
+                                connection.encryption_key.as_ref(),
+                                *stderr_chunks_sent,
+                                connection.content_defined_chunks,
+                                connection.max_chunk_size,
+                                connection.max_output_size,
+                            );
+                            *stderr_chunks_sent += chunks.len();
+                            for chunk in chunks {
+                                send_and_record(sender, pending, recent_events, next_seq, client_id, chunk);
+                            }
+                        }
+
+                        if let Some(status) = exited {
+                            // No need to queue this command for `process_output` - the
+                            // incremental sends above (plus this status message) already
+                            // delivered everything a from-scratch re-transform would, and
+                            // `process_output` always encodes from `resume_from: 0`, so queuing
+                            // it here would just resend every chunk a second time.
+                            send_and_record(sender, pending, recent_events, next_seq, client_id, encoding::status_message(&status, id));
+                            entry.status = CommandStatus::Finished {
+                                id,
+                                command: command.into(),
+                                status,
+                                stderr: stderr.clone(),
+                                stdout: stdout.clone(),
+                            };
+                        }
+                    },
+                    _ => (),
+                };
+            }
         }
         finished_children
     }
@@ -264,51 +775,166 @@ impl CommandExecutor {
         client_id
     }
 
-    fn run(&mut self, client_id: &Ulid, command: String) -> Result<(), UnconnectedError> {
+    /// Spawns `bash -c <command>` with stdin/stdout/stderr attached to a fresh PTY slave instead
+    /// of plain pipes, so TTY-detecting or line-editing programs (`top`, `less`, `vim`) behave as
+    /// they would in a real terminal. `setsid` plus `TIOCSCTTY` in `pre_exec` makes the slave the
+    /// child's controlling terminal, the same as a real login shell would set up.
+    fn spawn_interactive(command: &str) -> io::Result<(Child, File)> {
+        let (master, slave) = open_pty()?;
+        let slave_fd = slave.as_raw_fd();
+
+        let mut builder = Command::new("bash");
+        builder.args(vec!["-c", command]);
+        unsafe {
+            builder.stdin(Stdio::from_raw_fd(libc::dup(slave_fd)));
+            builder.stdout(Stdio::from_raw_fd(libc::dup(slave_fd)));
+            builder.stderr(Stdio::from_raw_fd(libc::dup(slave_fd)));
+            builder.pre_exec(|| {
+                if libc::setsid() < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = builder.spawn()?;
+        // The child inherited its own copy (via `dup`) of the slave fd; the parent only needs the
+        // master from here on.
+        drop(slave);
+        Ok((child, master))
+    }
+
+    /// Starts `command` and registers it under a fresh `id` in `connection.commands`, returning
+    /// that `id` so the caller can target this specific command with a later `Cancel`,
+    /// `SendInput`/`ResizePty`, or filter setter - concurrent commands on the same client are
+    /// otherwise indistinguishable from one another.
+    fn run(&mut self, client_id: &Ulid, command: String, interactive: bool) -> Result<usize, UnconnectedError> {
         let id = self.next_id.0;
         self.next_id = self.next_id + Wrapping(1usize);
         match self.clients.get_mut(client_id) {
             None => Err(UnconnectedError {}),
             Some(connection) => {
                 connection.last_active = Instant::now();
+                if interactive {
+                    let status = match Self::spawn_interactive(&command) {
+                        Err(error) => CommandStatus::Failed { id, error },
+                        Ok((child, pty)) => {
+                            if let Err(error) = set_nonblocking(pty.as_raw_fd()) {
+                                log::error!("Failed to set the PTY master non-blocking for an interactive run: error: {:#?}", error);
+                            }
+                            CommandStatus::Running {
+                                id,
+                                command,
+                                child,
+                                stdout: vec![],
+                                stderr: vec![],
+                                stdout_chunks_sent: 0,
+                                stderr_chunks_sent: 0,
+                                pty: Some(pty),
+                            }
+                        }
+                    };
+                    connection.commands.insert(id, CommandEntry::new(status));
+                    return Ok(id);
+                }
+
                 // Running the command through `bash -c` allows the user to use environment variables, bash arg parsing, etc.
-                match Command::new("bash").args(vec!["-c", &command]).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
-                    Err(error) => {
-                        connection.status = CommandStatus::Failed { id, error };
-                    }
+                let status = match Command::new("bash").args(vec!["-c", &command]).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+                    Err(error) => CommandStatus::Failed { id, error },
                     Ok(child) => {
-                        connection.status = CommandStatus::Running {
+                        // Non-blocking so `check_children`'s per-tick partial reads never stall
+                        // the actor waiting on a child that has nothing new to say yet. A failure
+                        // here just means this tick's (and every later tick's) reads fall back to
+                        // the old read-to-end-at-exit behavior for this run, not a fatal error.
+                        if let Some(ref stdout) = child.stdout {
+                            if let Err(error) = set_nonblocking(stdout.as_raw_fd()) {
+                                log::error!("Failed to set stdout non-blocking for a streamed run: error: {:#?}", error);
+                            }
+                        }
+                        if let Some(ref stderr) = child.stderr {
+                            if let Err(error) = set_nonblocking(stderr.as_raw_fd()) {
+                                log::error!("Failed to set stderr non-blocking for a streamed run: error: {:#?}", error);
+                            }
+                        }
+                        CommandStatus::Running {
                             id,
                             command,
                             child,
-                        };
+                            stdout: vec![],
+                            stderr: vec![],
+                            stdout_chunks_sent: 0,
+                            stderr_chunks_sent: 0,
+                            pty: None,
+                        }
                     }
                 };
-                Ok(())
+                connection.commands.insert(id, CommandEntry::new(status));
+                Ok(id)
             }
         }
     }
 
-    fn cancel(&mut self, client_id: &Ulid) -> Result<(), UnconnectedError> {
-        match self.clients.remove(client_id) {
+    fn cancel(&mut self, client_id: &Ulid, id: usize) -> Result<(), UnconnectedError> {
+        match self.clients.get_mut(client_id) {
             None => Err(UnconnectedError {}),
-            Some(mut connection) => {
+            Some(connection) => {
                 connection.last_active = Instant::now();
-                if let CommandStatus::Running { id, mut child, command } = connection.status {
-                    match child.kill() {
-                        Err(error) if error.kind() != io::ErrorKind::InvalidInput => {
-                            connection.status = CommandStatus::CancellationFailed { id, error };
-                        }
-                        _ => {
-                            connection.status = CommandStatus::Canceling {
+                if let Some(mut entry) = connection.commands.remove(&id) {
+                    if let CommandStatus::Running { child, command, .. } = entry.status {
+                        entry.status = match send_signal(child.id(), libc::SIGTERM) {
+                            Err(error) => CommandStatus::CancellationFailed { id, error },
+                            Ok(()) => CommandStatus::Canceling {
                                 id,
                                 child,
                                 command: command.clone(),
-                            };
+                                deadline: Instant::now() + CANCELLATION_GRACE_PERIOD,
+                            },
+                        };
+                    }
+                    connection.commands.insert(id, entry);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes `bytes` (a client's keystrokes) to the PTY master of the interactive run `id`. A
+    /// no-op, like `cancel`'s handling of a non-`Running` status, if `id` doesn't name a `Running`
+    /// interactive run - there's nothing useful to report back to a client racing a command that
+    /// already finished.
+    fn send_input(&mut self, client_id: &Ulid, id: usize, bytes: &[u8]) -> Result<(), UnconnectedError> {
+        match self.clients.get_mut(client_id) {
+            None => Err(UnconnectedError {}),
+            Some(connection) => {
+                connection.last_active = Instant::now();
+                if let Some(entry) = connection.commands.get_mut(&id) {
+                    if let CommandStatus::Running { pty: Some(ref mut pty), .. } = entry.status {
+                        if let Err(error) = pty.write_all(bytes) {
+                            log::error!("Failed to write input to a PTY master: client_id: {}, error: {:#?}", client_id, error);
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies a client-side terminal resize to the PTY master of the interactive run `id`. Same
+    /// no-op-if-not-applicable handling as `send_input`.
+    fn resize_pty(&mut self, client_id: &Ulid, id: usize, rows: u16, cols: u16) -> Result<(), UnconnectedError> {
+        match self.clients.get_mut(client_id) {
+            None => Err(UnconnectedError {}),
+            Some(connection) => {
+                connection.last_active = Instant::now();
+                if let Some(entry) = connection.commands.get_mut(&id) {
+                    if let CommandStatus::Running { pty: Some(ref pty), .. } = entry.status {
+                        if let Err(error) = resize_pty(pty.as_raw_fd(), rows, cols) {
+                            log::error!("Failed to resize a PTY master: client_id: {}, error: {:#?}", client_id, error);
                         }
                     }
                 }
-                self.clients.insert(*client_id, connection);
                 Ok(())
             }
         }
@@ -317,13 +943,16 @@ impl CommandExecutor {
     fn set_line_index_filters(
         &mut self,
         client_id: &Ulid,
-        filters: Option<Vec<IndexFilter>>,
+        id: usize,
+        filters: Option<IndexFilters>,
     ) -> Result<(), UnconnectedError> {
         match self.clients.get_mut(client_id) {
             None => Err(UnconnectedError {}),
             Some(connection) => {
                 connection.last_active = Instant::now();
-                connection.line_options.index_filters = filters;
+                if let Some(entry) = connection.commands.get_mut(&id) {
+                    entry.line_options.index_filters = filters;
+                }
                 Ok(())
             }
         }
@@ -332,13 +961,16 @@ impl CommandExecutor {
     fn set_line_regex_filter(
         &mut self,
         client_id: &Ulid,
+        id: usize,
         filter: Option<Regex>,
     ) -> Result<(), UnconnectedError> {
         match self.clients.get_mut(client_id) {
             None => Err(UnconnectedError {}),
             Some(connection) => {
                 connection.last_active = Instant::now();
-                connection.line_options.regex_filter = filter;
+                if let Some(entry) = connection.commands.get_mut(&id) {
+                    entry.line_options.regex_filter = filter;
+                }
                 Ok(())
             }
         }
@@ -347,13 +979,16 @@ impl CommandExecutor {
     fn set_line_separators(
         &mut self,
         client_id: &Ulid,
-        separators: Option<ByteTrie>,
+        id: usize,
+        separators: Option<FieldSeparator>,
     ) -> Result<(), UnconnectedError> {
         match self.clients.get_mut(client_id) {
             None => Err(UnconnectedError {}),
             Some(connection) => {
                 connection.last_active = Instant::now();
-                connection.line_options.separators = separators;
+                if let Some(entry) = connection.commands.get_mut(&id) {
+                    entry.line_options.separators = separators;
+                }
                 Ok(())
             }
         }
@@ -362,13 +997,16 @@ impl CommandExecutor {
     fn set_row_index_filters(
         &mut self,
         client_id: &Ulid,
-        filters: Option<Vec<IndexFilter>>,
+        id: usize,
+        filters: Option<IndexFilters>,
     ) -> Result<(), UnconnectedError> {
         match self.clients.get_mut(client_id) {
             None => Err(UnconnectedError {}),
             Some(connection) => {
                 connection.last_active = Instant::now();
-                connection.row_options.index_filters = filters;
+                if let Some(entry) = connection.commands.get_mut(&id) {
+                    entry.row_options.index_filters = filters;
+                }
                 Ok(())
             }
         }
@@ -377,13 +1015,16 @@ impl CommandExecutor {
     fn set_row_regex_filter(
         &mut self,
         client_id: &Ulid,
+        id: usize,
         filter: Option<Regex>,
     ) -> Result<(), UnconnectedError> {
         match self.clients.get_mut(client_id) {
             None => Err(UnconnectedError {}),
             Some(connection) => {
                 connection.last_active = Instant::now();
-                connection.row_options.regex_filter = filter;
+                if let Some(entry) = connection.commands.get_mut(&id) {
+                    entry.row_options.regex_filter = filter;
+                }
                 Ok(())
             }
         }
@@ -392,13 +1033,47 @@ impl CommandExecutor {
     fn set_row_separators(
         &mut self,
         client_id: &Ulid,
-        separators: Option<ByteTrie>,
+        id: usize,
+        separators: Option<FieldSeparator>,
     ) -> Result<(), UnconnectedError> {
         match self.clients.get_mut(client_id) {
             None => Err(UnconnectedError {}),
             Some(connection) => {
                 connection.last_active = Instant::now();
-                connection.row_options.separators = separators;
+                if let Some(entry) = connection.commands.get_mut(&id) {
+                    entry.row_options.separators = separators;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Sets every provided line/row option on command `id` in one pass, so batching several
+    /// settings into an `ApplySettings` request only rebuilds that command's transform pipeline
+    /// once instead of once per field, as the individual `set_*` setters above would.
+    fn apply_settings(
+        &mut self,
+        client_id: &Ulid,
+        id: usize,
+        line_separators: Option<FieldSeparator>,
+        line_index_filters: Option<IndexFilters>,
+        line_regex: Option<Regex>,
+        row_separators: Option<FieldSeparator>,
+        row_index_filters: Option<IndexFilters>,
+        row_regex: Option<Regex>,
+    ) -> Result<(), UnconnectedError> {
+        match self.clients.get_mut(client_id) {
+            None => Err(UnconnectedError {}),
+            Some(connection) => {
+                connection.last_active = Instant::now();
+                if let Some(entry) = connection.commands.get_mut(&id) {
+                    entry.line_options.separators = line_separators;
+                    entry.line_options.index_filters = line_index_filters;
+                    entry.line_options.regex_filter = line_regex;
+                    entry.row_options.separators = row_separators;
+                    entry.row_options.index_filters = row_index_filters;
+                    entry.row_options.regex_filter = row_regex;
+                }
                 Ok(())
             }
         }
@@ -441,9 +1116,95 @@ impl Stream for ClientConnection {
     }
 }
 
+/// A parsed `Last-Event-ID` header, as emitted by `ChunkWriter`'s `id:` line
+/// (`"<run-id>-<stdout|stderr>-<index>"`) or `status_message`'s (`"<run-id>-status"`).
+struct LastEventId {
+    run_id: usize,
+    event_type: String,
+    index: Option<usize>,
+}
+
+/// Parses a `Last-Event-ID` header value back into the run id/event type/index `encoding`
+/// encoded it from. Returns `None` for anything that doesn't look like one of ours, so an
+/// unrelated or malformed header just falls through to a normal, from-scratch stream.
+fn parse_last_event_id(raw: &str) -> Option<LastEventId> {
+    let mut parts = raw.split('-');
+    let run_id: usize = parts.next()?.parse().ok()?;
+    let event_type = parts.next()?.to_owned();
+    let index = parts.next().and_then(|index| index.parse().ok());
+
+    if event_type != "status" && index.is_none() {
+        return None;
+    }
+
+    Some(LastEventId { run_id, event_type, index })
+}
+
 #[derive(Deserialize)]
 pub struct Listen {
     client_id: Ulid,
+    #[serde(default)]
+    encoding: OutputEncoding,
+    #[serde(default)]
+    framed: bool,
+    /// A base64-standard-encoded 32-byte XChaCha20-Poly1305 key. When present, stdout/stderr
+    /// chunks are encrypted under it instead of sent as plaintext-encoded payloads.
+    #[serde(default)]
+    key: Option<String>,
+    /// Opts into FastCDC content-defined chunk boundaries instead of fixed-size chunks, so chunk
+    /// boundaries and hashes stay stable across reruns of the same command for caching clients.
+    #[serde(default)]
+    content_defined: bool,
+    /// Overrides the compile-time chunk/output size ceilings. A large job can raise
+    /// `max_output_size` well past the 256 MiB default, or push `max_chunk_size` toward
+    /// `usize::MAX` for effectively unbounded single-chunk streaming, instead of being cut off.
+    #[serde(default = "encoding::default_max_chunk_size")]
+    max_chunk_size: usize,
+    #[serde(default = "encoding::default_max_output_size")]
+    max_output_size: usize,
+    /// A fallback for clients that can't rely on EventSource's automatic `Last-Event-ID` header -
+    /// e.g. a non-browser client doing its own reconnect logic. Ignored whenever the header is
+    /// present; see `last_event_id` below and `server::listen`, which reconciles the two.
+    #[serde(default)]
+    pub(crate) last_event_id_query: Option<String>,
+    /// Populated by the `listen` handler from the request's `Last-Event-ID` header, falling back
+    /// to `last_event_id_query` if that header is absent - EventSource sends the header
+    /// automatically on reconnect, but it isn't itself a query param.
+    #[serde(skip)]
+    pub(crate) last_event_id: Option<String>,
+    /// A client-tracked sequence number to resume from, the numeric alternative to
+    /// `last_event_id`/`last_event_id_query` - takes priority over both when present. Lets a
+    /// client that keeps its own running count resume exactly, without parsing ids back out of
+    /// whatever `event_id_str` format the SSE stream happens to use.
+    #[serde(default)]
+    last_seq: Option<u64>,
+}
+
+impl Listen {
+    /// Malformed keys are treated the same as no key - logged and ignored - rather than failing
+    /// the whole `listen` call, consistent with how other per-connection options on this struct
+    /// degrade to their defaults instead of rejecting the request.
+    fn encryption_key(&self) -> Option<[u8; 32]> {
+        let encoded = self.key.as_ref()?;
+        let decoded = match STANDARD.decode(encoded) {
+            Ok(decoded) => decoded,
+            Err(error) => {
+                log::error!("Ignoring a `key` query param that wasn't valid base64:\n{}", error);
+                return None;
+            }
+        };
+
+        match decoded.try_into() {
+            Ok(key) => Some(key),
+            Err(decoded) => {
+                log::error!(
+                    "Ignoring a `key` query param that decoded to {} bytes instead of the 32 XChaCha20-Poly1305 needs",
+                    decoded.len()
+                );
+                None
+            }
+        }
+    }
 }
 
 impl Message for Listen {
@@ -454,7 +1215,21 @@ impl Handler<Listen> for CommandExecutor {
     type Result = Result<ClientConnection, UnconnectedError>;
 
     fn handle(&mut self, msg: Listen, _ctx: &mut Self::Context) -> Self::Result {
-        self.listen(&msg.client_id)
+        let encryption_key = msg.encryption_key();
+        self.listen(
+            &msg.client_id,
+            msg.encoding,
+            msg.framed,
+            encryption_key,
+            msg.content_defined,
+            // A chunk size of 0 would divide-by-zero in `num_chunks` and make `ChunkWriter::push`
+            // flush empty chunks forever without consuming any input, so the floor keeps a pathological request from wedging the
+            // connection instead of just producing very small chunks.
+            msg.max_chunk_size.max(1),
+            msg.max_output_size,
+            msg.last_event_id.as_deref(),
+            msg.last_seq,
+        )
     }
 }
 
@@ -493,6 +1268,7 @@ impl Handler<Connect> for CommandExecutor {
 
 struct ProcessOutput {
     client_id: Ulid,
+    id: usize,
 }
 
 impl Message for ProcessOutput {
@@ -507,7 +1283,7 @@ impl Handler<ProcessOutput> for CommandExecutor {
         msg: ProcessOutput,
         _ctx: &mut Self::Context,
     ) -> Self::Result {
-        self.process_output(&msg.client_id)
+        self.process_output(&msg.client_id, msg.id)
     }
 }
 
@@ -515,19 +1291,27 @@ impl Handler<ProcessOutput> for CommandExecutor {
 
 #[derive(Deserialize, Serialize)]
 pub struct Run {
-    client_id: Ulid,
+    pub client_id: Ulid,
     command: String,
+    /// Attach the command to a PTY instead of plain pipes, so TTY-detecting or line-editing
+    /// programs (`top`, `less`, `vim`) behave as they would in a real terminal. Defaults to
+    /// `false` so existing one-shot filtered-command clients don't have to know about it.
+    #[serde(default)]
+    interactive: bool,
 }
 
 impl Message for Run {
-    type Result = Result<(), UnconnectedError>;
+    /// The `id` this run was registered under, so the caller can target it with a later `Cancel`,
+    /// `SendInput`/`ResizePty`, or filter setter - a client may have several commands running at
+    /// once, so there's no other way to say which one a later message means.
+    type Result = Result<usize, UnconnectedError>;
 }
 
 impl Handler<Run> for CommandExecutor {
-    type Result = Result<(), UnconnectedError>;
+    type Result = Result<usize, UnconnectedError>;
 
     fn handle(&mut self, msg: Run, _ctx: &mut Self::Context) -> Self::Result {
-        self.run(&msg.client_id, msg.command)
+        self.run(&msg.client_id, msg.command, msg.interactive)
     }
 }
 
@@ -536,6 +1320,8 @@ impl Handler<Run> for CommandExecutor {
 #[derive(Deserialize, Serialize)]
 pub struct Cancel {
     client_id: Ulid,
+    /// Which of the client's concurrently-running commands to cancel, as returned by `Run`.
+    id: usize,
 }
 
 impl Message for Cancel {
@@ -546,7 +1332,52 @@ impl Handler<Cancel> for CommandExecutor {
     type Result = Result<(), UnconnectedError>;
 
     fn handle(&mut self, msg: Cancel, _ctx: &mut Self::Context) -> Self::Result {
-        self.cancel(&msg.client_id)
+        self.cancel(&msg.client_id, msg.id)
+    }
+}
+
+// Write keystrokes to an interactive run's PTY
+
+#[derive(Deserialize, Serialize)]
+pub struct SendInput {
+    pub client_id: Ulid,
+    /// Which of the client's concurrently-running commands to write to, as returned by `Run`.
+    pub id: usize,
+    bytes: Vec<u8>,
+}
+
+impl Message for SendInput {
+    type Result = Result<(), UnconnectedError>;
+}
+
+impl Handler<SendInput> for CommandExecutor {
+    type Result = Result<(), UnconnectedError>;
+
+    fn handle(&mut self, msg: SendInput, _ctx: &mut Self::Context) -> Self::Result {
+        self.send_input(&msg.client_id, msg.id, &msg.bytes)
+    }
+}
+
+// Resize an interactive run's PTY
+
+#[derive(Deserialize, Serialize)]
+pub struct ResizePty {
+    pub client_id: Ulid,
+    /// Which of the client's concurrently-running commands to resize, as returned by `Run`.
+    pub id: usize,
+    rows: u16,
+    cols: u16,
+}
+
+impl Message for ResizePty {
+    type Result = Result<(), UnconnectedError>;
+}
+
+impl Handler<ResizePty> for CommandExecutor {
+    type Result = Result<(), UnconnectedError>;
+
+    fn handle(&mut self, msg: ResizePty, _ctx: &mut Self::Context) -> Self::Result {
+        self.resize_pty(&msg.client_id, msg.id, msg.rows, msg.cols)
     }
 }
 
@@ -554,7 +1385,10 @@ impl Handler<Cancel> for CommandExecutor {
 
 pub struct SetLineIndexFilters {
     pub client_id: Ulid,
-    pub filters: Option<Vec<IndexFilter>>,
+    /// Which of the client's concurrently-running commands this filter applies to, as returned by
+    /// `Run`.
+    pub id: usize,
+    pub filters: Option<IndexFilters>,
 }
 
 impl Message for SetLineIndexFilters {
@@ -569,9 +1403,10 @@ impl Handler<SetLineIndexFilters> for CommandExecutor {
         msg: SetLineIndexFilters,
         ctx: &mut Self::Context,
     ) -> Self::Result {
-        self.set_line_index_filters(&msg.client_id, msg.filters)?;
+        self.set_line_index_filters(&msg.client_id, msg.id, msg.filters)?;
         ctx.address().do_send(ProcessOutput {
             client_id: msg.client_id,
+            id: msg.id,
         });
         Ok(())
     }
@@ -579,6 +1414,7 @@ impl Handler<SetLineIndexFilters> for CommandExecutor {
 
 pub struct SetLineRegexFilter {
     pub client_id: Ulid,
+    pub id: usize,
     pub filter: Option<Regex>,
 }
 
@@ -594,9 +1430,10 @@ impl Handler<SetLineRegexFilter> for CommandExecutor {
         msg: SetLineRegexFilter,
         ctx: &mut Self::Context,
     ) -> Self::Result {
-        self.set_line_regex_filter(&msg.client_id, msg.filter)?;
+        self.set_line_regex_filter(&msg.client_id, msg.id, msg.filter)?;
         ctx.address().do_send(ProcessOutput {
             client_id: msg.client_id,
+            id: msg.id,
         });
         Ok(())
     }
@@ -604,7 +1441,8 @@ impl Handler<SetLineRegexFilter> for CommandExecutor {
 
 pub struct SetLineSeparators {
     pub client_id: Ulid,
-    pub separators: Option<ByteTrie>,
+    pub id: usize,
+    pub separators: Option<FieldSeparator>,
 }
 
 impl Message for SetLineSeparators {
@@ -619,9 +1457,10 @@ impl Handler<SetLineSeparators> for CommandExecutor {
         msg: SetLineSeparators,
         ctx: &mut Self::Context,
     ) -> Self::Result {
-        self.set_line_separators(&msg.client_id, msg.separators)?;
+        self.set_line_separators(&msg.client_id, msg.id, msg.separators)?;
         ctx.address().do_send(ProcessOutput {
             client_id: msg.client_id,
+            id: msg.id,
         });
         Ok(())
     }
@@ -629,7 +1468,8 @@ impl Handler<SetLineSeparators> for CommandExecutor {
 
 pub struct SetRowIndexFilters {
     pub client_id: Ulid,
-    pub filters: Option<Vec<IndexFilter>>,
+    pub id: usize,
+    pub filters: Option<IndexFilters>,
 }
 
 impl Message for SetRowIndexFilters {
@@ -644,9 +1484,10 @@ impl Handler<SetRowIndexFilters> for CommandExecutor {
         msg: SetRowIndexFilters,
         ctx: &mut Self::Context,
     ) -> Self::Result {
-        self.set_row_index_filters(&msg.client_id, msg.filters)?;
+        self.set_row_index_filters(&msg.client_id, msg.id, msg.filters)?;
         ctx.address().do_send(ProcessOutput {
             client_id: msg.client_id,
+            id: msg.id,
         });
         Ok(())
     }
@@ -654,6 +1495,7 @@ impl Handler<SetRowIndexFilters> for CommandExecutor {
 
 pub struct SetRowRegexFilter {
     pub client_id: Ulid,
+    pub id: usize,
     pub filter: Option<Regex>,
 }
 
@@ -669,9 +1511,10 @@ impl Handler<SetRowRegexFilter> for CommandExecutor {
         msg: SetRowRegexFilter,
         ctx: &mut Self::Context,
     ) -> Self::Result {
-        self.set_row_regex_filter(&msg.client_id, msg.filter)?;
+        self.set_row_regex_filter(&msg.client_id, msg.id, msg.filter)?;
         ctx.address().do_send(ProcessOutput {
             client_id: msg.client_id,
+            id: msg.id,
         });
         Ok(())
     }
@@ -679,7 +1522,8 @@ impl Handler<SetRowRegexFilter> for CommandExecutor {
 
 pub struct SetRowSeparators {
     pub client_id: Ulid,
-    pub separators: Option<ByteTrie>,
+    pub id: usize,
+    pub separators: Option<FieldSeparator>,
 }
 
 impl Message for SetRowSeparators {
@@ -694,9 +1538,53 @@ impl Handler<SetRowSeparators> for CommandExecutor {
         msg: SetRowSeparators,
         ctx: &mut Self::Context,
     ) -> Self::Result {
-        self.set_row_separators(&msg.client_id, msg.separators)?;
+        self.set_row_separators(&msg.client_id, msg.id, msg.separators)?;
+        ctx.address().do_send(ProcessOutput {
+            client_id: msg.client_id,
+            id: msg.id,
+        });
+        Ok(())
+    }
+}
+
+pub struct ApplySettings {
+    pub client_id: Ulid,
+    /// Which of the client's concurrently-running commands these settings apply to, as returned
+    /// by `Run`.
+    pub id: usize,
+    pub line_separators: Option<FieldSeparator>,
+    pub line_index_filters: Option<IndexFilters>,
+    pub line_regex: Option<Regex>,
+    pub row_separators: Option<FieldSeparator>,
+    pub row_index_filters: Option<IndexFilters>,
+    pub row_regex: Option<Regex>,
+}
+
+impl Message for ApplySettings {
+    type Result = Result<(), UnconnectedError>;
+}
+
+impl Handler<ApplySettings> for CommandExecutor {
+    type Result = Result<(), UnconnectedError>;
+
+    fn handle(
+        &mut self,
+        msg: ApplySettings,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        self.apply_settings(
+            &msg.client_id,
+            msg.id,
+            msg.line_separators,
+            msg.line_index_filters,
+            msg.line_regex,
+            msg.row_separators,
+            msg.row_index_filters,
+            msg.row_regex,
+        )?;
         ctx.address().do_send(ProcessOutput {
             client_id: msg.client_id,
+            id: msg.id,
         });
         Ok(())
     }
@@ -713,9 +1601,10 @@ impl Handler<CheckChildren> for CommandExecutor {
 
     fn handle(&mut self, _: CheckChildren, ctx: &mut Self::Context) -> Self::Result {
         let finished = self.check_children();
-        for client_id in finished {
-            ctx.address().do_send(ProcessOutput { client_id });
+        for (client_id, id) in finished {
+            ctx.address().do_send(ProcessOutput { client_id, id });
         }
+        self.reap_idle();
         ctx.address().do_send(CheckChildren {});
     }
 }
\ No newline at end of file
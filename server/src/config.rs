@@ -0,0 +1,210 @@
+/// Loads default `transformers::Options` (separators, index filters, regex filters) for newly
+/// created `WebsocketConnection`s from a TOML file, and watches that file for changes via
+/// `notify` so edits take effect without restarting the server.
+///
+/// Each field is run back through the same `parsers::parse_*` functions used to validate a
+/// client's `SetColumn*`/`SetRow*` requests; a field that fails to parse is logged and dropped,
+/// and a file that fails to parse at all leaves the last-good configuration in place.
+
+use crate::parsers;
+use crate::presets;
+use crate::transformers;
+use crate::websocket_connection::WebsocketConnection;
+use actix::prelude::*;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+#[derive(Deserialize, Default, Clone)]
+struct SectionConfig {
+    separators: Option<Vec<String>>,
+    regex_filter: Option<String>,
+    index_filters: Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct FileConfig {
+    /// A named preset (see `presets::preset`) providing the base line/row separators; an
+    /// explicit `separators` in `column`/`row` below overrides its corresponding component.
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    column: SectionConfig,
+    #[serde(default)]
+    row: SectionConfig,
+}
+
+fn build_options(section: &SectionConfig, preset_separator: Option<parsers::FieldSeparator>) -> transformers::Options {
+    let mut options = transformers::Options::default();
+    options.separators = preset_separator;
+
+    if let Some(separators) = &section.separators {
+        match parsers::parse_field_separators(separators) {
+            Ok(parsed) => options.separators = Some(parsed),
+            Err(error) => log::error!("Ignoring invalid default separators in config file: {}", error),
+        }
+    }
+
+    if let Some(regex_filter) = &section.regex_filter {
+        match parsers::parse_regex_filter(regex_filter) {
+            Ok(parsed) => options.regex_filter = Some(parsed),
+            Err(error) => log::error!("Ignoring invalid default regex filter in config file: {}", error),
+        }
+    }
+
+    if let Some(index_filters) = &section.index_filters {
+        match parsers::parse_index_filters(index_filters) {
+            Ok(parsed) => options.index_filters = Some(parsed),
+            Err(error) => log::error!("Ignoring invalid default index filters in config file: {}", error),
+        }
+    }
+
+    options
+}
+
+/// Builds the column/row `Options` pair for a whole `FileConfig`, expanding `format` (if any)
+/// into its line/row separators before each section's explicit `separators` is applied on top.
+fn build_options_pair(config: &FileConfig) -> (transformers::Options, transformers::Options) {
+    let (line_separator, row_separator) = match &config.format {
+        Some(name) => match presets::preset(name) {
+            Some((line_separator, row_separator)) => (Some(line_separator), Some(row_separator)),
+            None => {
+                log::error!("Ignoring unknown format preset \"{}\" in config file", name);
+                (None, None)
+            },
+        },
+        None => (None, None),
+    };
+
+    (build_options(&config.column, line_separator), build_options(&config.row, row_separator))
+}
+
+fn load(path: &PathBuf) -> Option<FileConfig> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => match toml::from_str(&contents) {
+            Ok(config) => Some(config),
+            Err(error) => {
+                log::error!("Ignoring unparseable config file {}: {}", path.display(), error);
+                None
+            },
+        },
+        Err(error) => {
+            log::error!("Could not read config file {}: {}", path.display(), error);
+            None
+        },
+    }
+}
+
+/// Asks the watcher for the currently validated defaults, rebuilt fresh for the requesting
+/// connection (the underlying `Options` isn't `Clone`, so each caller gets its own copy).
+pub struct GetDefaults;
+
+impl Message for GetDefaults {
+    type Result = (transformers::Options, transformers::Options);
+}
+
+/// Subscribes a connection to be notified when the file's defaults change, as long as it's still
+/// using unmodified defaults by the time a reload happens.
+pub struct Register(pub Addr<WebsocketConnection>);
+
+impl Message for Register {
+    type Result = ();
+}
+
+struct Reload;
+
+impl Message for Reload {
+    type Result = ();
+}
+
+pub struct ConfigWatcher {
+    path: PathBuf,
+    current: FileConfig,
+    subscribers: Vec<Addr<WebsocketConnection>>,
+    // Keeps the notify watcher alive for as long as the actor is; dropping it stops the watch.
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        let current = load(&path).unwrap_or_default();
+        Self {
+            path,
+            current,
+            subscribers: vec![],
+            _watcher: None,
+        }
+    }
+}
+
+impl Actor for ConfigWatcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let (tx, rx) = channel();
+        let mut watcher: RecommendedWatcher = match Watcher::new(tx, Duration::from_secs(2)) {
+            Ok(watcher) => watcher,
+            Err(error) => {
+                log::error!("Could not watch {} for changes: {}", self.path.display(), error);
+                return;
+            },
+        };
+
+        if let Err(error) = watcher.watch(&self.path, RecursiveMode::NonRecursive) {
+            log::error!("Could not watch {} for changes: {}", self.path.display(), error);
+            return;
+        }
+
+        self._watcher = Some(watcher);
+
+        let address = ctx.address();
+        std::thread::spawn(move || {
+            while let Ok(_event) = rx.recv() {
+                address.do_send(Reload);
+            }
+        });
+    }
+}
+
+impl Handler<Reload> for ConfigWatcher {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Reload, _ctx: &mut Self::Context) {
+        if let Some(config) = load(&self.path) {
+            self.current = config;
+
+            // Options aren't `Clone`, so each subscriber gets its own freshly-built pair rather
+            // than sharing one.
+            for subscriber in &self.subscribers {
+                let (column_options, row_options) = build_options_pair(&self.current);
+                subscriber.do_send(ApplyDefaultOptions(column_options, row_options));
+            }
+        }
+    }
+}
+
+impl Handler<Register> for ConfigWatcher {
+    type Result = ();
+
+    fn handle(&mut self, Register(address): Register, _ctx: &mut Self::Context) {
+        self.subscribers.push(address);
+    }
+}
+
+impl Handler<GetDefaults> for ConfigWatcher {
+    type Result = MessageResult<GetDefaults>;
+
+    fn handle(&mut self, _msg: GetDefaults, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(build_options_pair(&self.current))
+    }
+}
+
+/// Sent to a `WebsocketConnection` that's still using unmodified defaults when the config file
+/// reloads.
+pub struct ApplyDefaultOptions(pub transformers::Options, pub transformers::Options);
+
+impl Message for ApplyDefaultOptions {
+    type Result = ();
+}
@@ -0,0 +1,55 @@
+use std::collections::HashSet;
+
+/// Origins allowed to make cross-origin requests against the HTTP API, loaded once at startup
+/// (see `from_env`) and shared across handlers via `web::Data`.
+///
+/// A request's `Origin` is only ever echoed back verbatim when it's on this list; an
+/// unrecognized origin gets no `Access-Control-Allow-Origin` header at all, never a wildcard, so
+/// the behavior stays correct once credentialed requests or multiple origins are involved.
+pub struct CorsConfig {
+    allowed_origins: HashSet<String>,
+}
+
+impl CorsConfig {
+    pub fn new(allowed_origins: HashSet<String>) -> Self {
+        CorsConfig { allowed_origins }
+    }
+
+    /// Parses a comma-separated `VAWK_ALLOWED_ORIGINS` environment variable into a `CorsConfig`,
+    /// falling back to the historical single dev-server origin if it isn't set.
+    pub fn from_env() -> Self {
+        let raw = std::env::var("VAWK_ALLOWED_ORIGINS").unwrap_or_else(|_| "http://localhost:3000".to_owned());
+        Self::new(raw.split(',').map(|origin| origin.trim().to_owned()).filter(|origin| !origin.is_empty()).collect())
+    }
+
+    /// The `Access-Control-Allow-Origin` value to echo back for a request bearing this `Origin`
+    /// header, or `None` if it isn't on the allow list (in which case no CORS header should be
+    /// set at all).
+    pub fn allow_origin<'a>(&self, origin: Option<&'a str>) -> Option<&'a str> {
+        origin.filter(|origin| self.allowed_origins.contains(*origin))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CorsConfig;
+
+    #[test]
+    fn allow_origin_matches() {
+        let cors = CorsConfig::new(vec!["http://localhost:3000".to_owned()].into_iter().collect());
+        assert_eq!(cors.allow_origin(Some("http://localhost:3000")), Some("http://localhost:3000"));
+    }
+
+    #[test]
+    fn allow_origin_rejects_unknown_origins() {
+        let cors = CorsConfig::new(vec!["http://localhost:3000".to_owned()].into_iter().collect());
+        assert_eq!(cors.allow_origin(Some("http://evil.example")), None);
+    }
+
+    #[test]
+    fn allow_origin_never_falls_back_to_a_wildcard() {
+        let cors = CorsConfig::new(std::collections::HashSet::new());
+        assert_eq!(cors.allow_origin(Some("http://localhost:3000")), None);
+        assert_eq!(cors.allow_origin(None), None);
+    }
+}
@@ -1,99 +1,752 @@
 use actix_web::web;
-use base64;
-use serde_json;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE};
+use base64::Engine;
+use bytes::BytesMut;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use std::process::ExitStatus;
 
 const MAX_CHUNK_SIZE: usize = 8 * 1_048_576;
 const MAX_OUTPUT_SIZE: usize = 256 * 1_048_576;
 
-#[derive(Debug)]
-pub enum EncodingError {
-    JSON(serde_json::Error),
-    TooLarge,
+/// Defaults for `Listen`'s `max_chunk_size`/`max_output_size` query params, so a request that
+/// doesn't specify either gets the same behavior as before they became configurable.
+pub fn default_max_chunk_size() -> usize {
+    MAX_CHUNK_SIZE
 }
 
-impl From<serde_json::Error> for EncodingError {
-    fn from(error: serde_json::Error) -> EncodingError {
-        EncodingError::JSON(error)
+pub fn default_max_output_size() -> usize {
+    MAX_OUTPUT_SIZE
+}
+
+/// Target chunk sizes for FastCDC content-defined chunking - chosen so unrelated small command
+/// outputs still get a handful of chunks to dedupe against. The upper bound is the caller's
+/// (runtime-configurable) `max_chunk_size`, the same ceiling fixed-size chunking uses.
+const CDC_MIN_SIZE: usize = 2 * 1_024;
+const CDC_AVG_SIZE: usize = 64 * 1_024;
+
+/// Raw bytes are fed to the base64 engine in blocks this size, so encoding never has to hold more
+/// than one block's input/output in memory at a time. 3 bytes -> 4 base64 characters with no
+/// padding, so only the last (possibly short) block of a row ever produces `=` padding.
+const BASE64_BLOCK_BYTES: usize = 3;
+
+const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_string(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for &byte in bytes {
+        out.push(HEX_CHARS[(byte >> 4) as usize] as char);
+        out.push(HEX_CHARS[(byte & 0x0f) as usize] as char);
+    }
+    out
+}
+
+fn base64_string(bytes: &[u8]) -> String {
+    let mut out = vec![0u8; STANDARD.encoded_len(bytes.len())];
+    let written = STANDARD
+        .encode_slice(bytes, &mut out)
+        .expect("`out` is sized exactly to STANDARD's encoded_len for `bytes`");
+    out.truncate(written);
+    String::from_utf8(out).expect("base64 output is always valid UTF-8")
+}
+
+/// How `stdout_chunks`/`stderr_chunks` turn raw bytes into the ASCII payload streamed out as SSE
+/// events, selected per-request rather than fixed to standard base64. URL-safe output matters
+/// because SSE `data:` payloads get embedded in JSON and sometimes proxied/logged, where `+`/`/`
+/// cause trouble; hex is for clients that can't base64-decode cheaply.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputEncoding {
+    StandardBase64,
+    UrlSafeBase64,
+    Hex,
+}
+
+impl Default for OutputEncoding {
+    fn default() -> Self {
+        OutputEncoding::StandardBase64
+    }
+}
+
+impl OutputEncoding {
+    /// The marker sent back to the client alongside each chunk, so it knows how to decode the
+    /// payload without having to remember what it originally asked for.
+    fn label(self) -> &'static str {
+        match self {
+            OutputEncoding::StandardBase64 => "base64",
+            OutputEncoding::UrlSafeBase64 => "base64url",
+            OutputEncoding::Hex => "hex",
+        }
+    }
+
+    /// The number of ASCII characters `len` raw bytes encode to under this encoding, without
+    /// actually encoding them - used to size the SSE `total` header up front, before the
+    /// (streamed) encoding pass runs.
+    fn encoded_len(self, len: usize) -> usize {
+        match self {
+            OutputEncoding::StandardBase64 | OutputEncoding::UrlSafeBase64 => {
+                ((len + BASE64_BLOCK_BYTES - 1) / BASE64_BLOCK_BYTES) * 4
+            }
+            OutputEncoding::Hex => len * 2,
+        }
+    }
+}
+
+/// Encodes `row` directly into `writer`, `BASE64_BLOCK_BYTES` at a time, so a row never needs its
+/// own encoded `String` allocation before landing in the shared chunk buffer.
+fn encode_base64_into<E: Engine>(writer: &mut ChunkWriter, row: &[u8], engine: &E) {
+    for block in row.chunks(BASE64_BLOCK_BYTES) {
+        let mut out = [0u8; 4];
+        let written = engine
+            .encode_slice(block, &mut out)
+            .expect("a block of at most BASE64_BLOCK_BYTES always fits a 4-byte buffer");
+        writer.push_raw(block);
+        writer.push(&out[..written]);
+    }
+}
+
+fn encode_hex_into(writer: &mut ChunkWriter, row: &[u8]) {
+    for &byte in row {
+        writer.push_raw(&[byte]);
+        writer.push(&[HEX_CHARS[(byte >> 4) as usize], HEX_CHARS[(byte & 0x0f) as usize]]);
+    }
+}
+
+fn encode_row_into(writer: &mut ChunkWriter, row: &[u8], encoding: OutputEncoding) {
+    match encoding {
+        OutputEncoding::StandardBase64 => encode_base64_into(writer, row, &STANDARD),
+        OutputEncoding::UrlSafeBase64 => encode_base64_into(writer, row, &URL_SAFE),
+        OutputEncoding::Hex => encode_hex_into(writer, row),
     }
 }
 
-fn encode_stderr(stderr: &Vec<u8>) -> Result<String, EncodingError> {
-    if stderr.len() > MAX_OUTPUT_SIZE {
-        return Err(EncodingError::TooLarge);
+/// The same encodings as `encode_base64_into`/`encode_hex_into`, but appended directly to a flat
+/// `Vec<u8>` instead of a `ChunkWriter` - used by content-defined chunking, which needs the whole
+/// encoded byte stream in hand before it can decide where to cut it.
+fn encode_base64_bytes<E: Engine>(out: &mut Vec<u8>, row: &[u8], engine: &E) {
+    for block in row.chunks(BASE64_BLOCK_BYTES) {
+        let mut buf = [0u8; 4];
+        let written = engine
+            .encode_slice(block, &mut buf)
+            .expect("a block of at most BASE64_BLOCK_BYTES always fits a 4-byte buffer");
+        out.extend_from_slice(&buf[..written]);
     }
+}
 
-    Ok(base64::encode(stderr))
+fn encode_hex_bytes(out: &mut Vec<u8>, row: &[u8]) {
+    for &byte in row {
+        out.push(HEX_CHARS[(byte >> 4) as usize]);
+        out.push(HEX_CHARS[(byte & 0x0f) as usize]);
+    }
 }
 
-fn encode_stdout(stdout: &Vec<Vec<Vec<u8>>>) -> Result<String, EncodingError> {
+fn encode_row_bytes(out: &mut Vec<u8>, row: &[u8], encoding: OutputEncoding) {
+    match encoding {
+        OutputEncoding::StandardBase64 => encode_base64_bytes(out, row, &STANDARD),
+        OutputEncoding::UrlSafeBase64 => encode_base64_bytes(out, row, &URL_SAFE),
+        OutputEncoding::Hex => encode_hex_bytes(out, row),
+    }
+}
+
+/// Narrows `stdout` down to however many whole rows fit within `max_output_size` raw
+/// (pre-encoding) bytes, returning the retained lines - the last one truncated to a row prefix, if
+/// that's where the cutoff lands - alongside how many raw bytes that is and whether anything had
+/// to be dropped. Used so a run whose output blows past the configured cap still gets a complete,
+/// valid partial result instead of the whole stream aborting outright.
+fn stdout_prefix(stdout: &Vec<Vec<Vec<u8>>>, max_output_size: usize) -> (Vec<&[Vec<u8>]>, usize, bool) {
     let mut output_size = 0usize;
-    let mut base64_encoded = vec![];
+    let mut lines: Vec<&[Vec<u8>]> = Vec::with_capacity(stdout.len());
 
     for line in stdout {
-        let mut base64_encoded_line = vec![];
-
+        let mut row_count = 0usize;
         for row in line {
+            if output_size + row.len() > max_output_size {
+                if row_count > 0 {
+                    lines.push(&line[..row_count]);
+                }
+                return (lines, output_size, true);
+            }
             output_size += row.len();
-            if output_size > MAX_OUTPUT_SIZE {
-                return Err(EncodingError::TooLarge);
+            row_count += 1;
+        }
+        lines.push(&line[..]);
+    }
+
+    (lines, output_size, false)
+}
+
+/// `stdout_chunks`'s JSON array of arrays of encoded strings, built directly into a `Vec<u8>`
+/// instead of streamed through `ChunkWriter`'s fixed-size boundaries - the flat form
+/// `cdc_chunks`/`CdcConfig::cut_lengths` needs to find content-defined cut points in.
+fn encode_stdout_bytes(lines: &[&[Vec<u8>]], encoding: OutputEncoding) -> Vec<u8> {
+    let mut out = Vec::with_capacity(stdout_encoded_len(lines, encoding));
+
+    out.push(b'[');
+    for (line_index, line) in lines.iter().enumerate() {
+        if line_index > 0 {
+            out.push(b',');
+        }
+        out.push(b'[');
+
+        for (row_index, row) in line.iter().enumerate() {
+            if row_index > 0 {
+                out.push(b',');
             }
 
-            base64_encoded_line.push(base64::encode(row));
+            out.push(b'"');
+            encode_row_bytes(&mut out, row, encoding);
+            out.push(b'"');
         }
 
-        base64_encoded.push(base64_encoded_line);
+        out.push(b']');
     }
+    out.push(b']');
 
-    Ok(serde_json::to_string(&base64_encoded)?)
+    out
 }
 
-fn chunked(encoded: &str, id: usize, event_type: &str) -> Vec<web::Bytes> {
-    let mut chunk_size = 0usize;
-    let mut chunks = vec![];
-    let mut index = 0;
-    let mut total = encoded.len() / MAX_CHUNK_SIZE;
-    if encoded.len() % MAX_CHUNK_SIZE > 0 {
+fn num_chunks(encoded_len: usize, max_chunk_size: usize) -> usize {
+    let mut total = encoded_len / max_chunk_size;
+    if encoded_len % max_chunk_size > 0 {
         total += 1;
     }
-    let mut chunk: Vec<char> = format!("event: {}\ndata: {{\"index\": {}, \"total\": {}}}\ndata: ", event_type, index, total).chars().collect();
+    total
+}
 
-    // With base64 encoding & JSON, each char is one byte.
-    // Each character is guaranteed to be ASCII.
-    for c in encoded.chars() {
-        chunk_size += 1;
-        chunk.push(c);
+/// Sums the exact byte length `stdout_chunks`'s JSON array of arrays of encoded strings would
+/// come out to, without building any of it, so `ChunkWriter` can report a correct `total` before
+/// the streaming pass that actually writes the bytes.
+fn stdout_encoded_len(lines: &[&[Vec<u8>]], encoding: OutputEncoding) -> usize {
+    let mut json_len = 2; // the outermost `[` `]`
 
-        if chunk_size == MAX_CHUNK_SIZE {
-            for _ in 0..2 {
-                chunk.push('\n');
+    for (line_index, line) in lines.iter().enumerate() {
+        if line_index > 0 {
+            json_len += 1; // `,` between lines
+        }
+        json_len += 2; // this line's `[` `]`
+
+        for (row_index, row) in line.iter().enumerate() {
+            if row_index > 0 {
+                json_len += 1; // `,` between rows
             }
-            chunks.push(web::Bytes::from(chunk.iter().collect::<String>()));
-            index += 1;
-            chunk = format!("event: {}\ndata: {{\"index\": {}, \"total\": {}, \"id\": {}}}\ndata: ", event_type, index, total, id).chars().collect();
+
+            json_len += 2; // the quotes around this row's encoded string
+            json_len += encoding.encoded_len(row.len());
         }
     }
 
-    if !chunk.is_empty() {
-        for _ in 0..2 {
-            chunk.push('\n');
+    json_len
+}
+
+/// A fixed table of 256 pseudo-random 64-bit values, one per possible input byte, used by
+/// `CdcConfig`'s gear hash to roll a content fingerprint across the encoded byte stream. The
+/// specific values don't matter - only that they're fixed and well-distributed - since what makes
+/// FastCDC's boundaries stable across runs is that this table never changes, not what's in it.
+const GEAR: [u64; 256] = [
+    0x1a40e10a9199efb6, 0xc4d07d2649ababb7, 0x49834e10fe34c955, 0xca472c5c040b6baf,
+    0xf9c5dc731587c294, 0x88d12e0a734191bc, 0x1afaeca0fb22d33a, 0xc8c4ac4513e4ca42,
+    0x38dab587bcf3239b, 0xe82c441d19bcfc2a, 0x595053605a9bea43, 0x02eb36346fa740b7,
+    0xd0f9eae210d4eead, 0x61e8b52fd69296cb, 0x299328886a425196, 0xa3b1a47f4f2ccb3b,
+    0xb72b7c6e4b782639, 0x49289f5748cf044d, 0xf7b36de39dfdf8a5, 0x4b74cf4f56056777,
+    0x3443bf905c90158f, 0xfdf87ae1cc35873d, 0x888aad39131db6af, 0xb777c2e8308c0511,
+    0xee98f3bcf6c7724c, 0x98390dcfe4b0784e, 0x53685f8169e96d7b, 0x05eca3b26f548508,
+    0xcbd1628cba5e323d, 0xf130fa8c012c3ba8, 0x9254edb953398e5e, 0x057d29cfaf1c7c4c,
+    0x0003200282a06934, 0x08093c33b2e70cd1, 0xaad1c1c6463abccc, 0xed2e0b079522f361,
+    0x227af8e0dc147dd1, 0x3f5f2be7f8072776, 0xa1aa41cdb06da00d, 0xe7a667d83d5bbdd1,
+    0x5fac3f17564e2170, 0x6312d62fe1250adb, 0x8cdf8aaa26a7c4b2, 0x456624deae83c5a8,
+    0x62b0665f6fa33914, 0xe968cc409b03cb41, 0x54773427db3f36f7, 0x0b6d17ffb3da98e3,
+    0xd4c680d9e3fdc6f3, 0xa4b2b6d4b24b8e8a, 0x2f9eff5fa8991c21, 0xc3f596ae12f68d17,
+    0x7bd5cc7c40022a39, 0xe169d8af2d2e7319, 0xb76b4fd61f08eeef, 0xfd0900f1eb1455a4,
+    0x8eb5f9a60db06086, 0x3c1e8e844744c720, 0xd1935fff5ed8099d, 0x13ec2ed2782b23b3,
+    0x9cc47c941c2924ee, 0x679345fe33fb30f6, 0xa62409bbbb7ab6dd, 0xb5260c411daaadcc,
+    0x05face0f019229b9, 0xd0e96890d7cf6b28, 0x01ab2ba007a3a65b, 0x9348274b4835f002,
+    0xb213193b3f78b776, 0x08f981b1df3965f6, 0xa3829d00bfae4c7c, 0x76b4e01647e9f8c6,
+    0x984fe36a98cb303d, 0xf348acb782d6f4c7, 0x9b36092950715dc6, 0x504d00a81b84d8e7,
+    0x80ce9036925c8e8f, 0x42e55080351bcede, 0xe2f175c25dc3f584, 0x85c7ace9ae1285d7,
+    0xb46c6648041a8c19, 0x76602d3115ceee87, 0x3462fffa75b84c44, 0xa7f998fe88708a3d,
+    0xc90e9e64ee43cabc, 0xee0f783085060bb0, 0xd73552d7e8b1e9b6, 0xb38390df91fe8ec8,
+    0x1d381c7f3a79f80f, 0xd7158d9e8281844d, 0x4e6fd7dfd876b91a, 0x1f742e197254803d,
+    0x52fecc16e6d80038, 0xe61b7a0a28e4e2a9, 0xde4ec03f0fcfda89, 0xb846fbf8e444a7ed,
+    0x559edc79cd065f7f, 0xdba51fee3dcba409, 0x45ed9df14e380fdc, 0x0d60cea22c785918,
+    0xcd1bf8ee2ead7c42, 0xfeb48a9ea5a8382c, 0x24f9741e8908c852, 0x88af41bb0393b545,
+    0xcf4d1dd4a32895c8, 0x0466b0e2f515fd12, 0xd363292be5895f3d, 0x018ec6114e10788d,
+    0x05acbae6a530df0b, 0x0232fc1f8eb2d2b5, 0xe54bdcced06e8fa1, 0x6aeffb28342f7e1e,
+    0x6d14aaf796128415, 0x636a8d3606990c2d, 0x9ae313a26c89853c, 0x8110e3c44ff87b5e,
+    0xd1bd2c8031f647cd, 0xc658c05c8fb91b1b, 0x38b9690d787da8ba, 0x0878471f6f68c8b0,
+    0x309d1f5bef745288, 0x1b382606f1d8dbb2, 0x2e9c1c298a60f188, 0xe5df3879ff7c095d,
+    0x083ba449d8a56840, 0x4008c366feefd63d, 0x4460d47fbfbbe40f, 0x6b3f67aebc599c04,
+    0x7296a90ab3b5063e, 0x4bb4e9844c93e561, 0xca0017814dd997d5, 0x9cdd95f1d24b2cfc,
+    0x8d3cec1daebd0f2f, 0x557f5e4ddd3ce197, 0x5d77886473ebfc37, 0x2b2eb9db92aebfb7,
+    0x575a9b07260c95c8, 0x5be8ba29ae84b948, 0xf6be0eed76075a59, 0x294bd8eaf9ba25bf,
+    0x79d32b3bb2deb44d, 0xe2b53f30c339e927, 0xed76dd90f5b1efda, 0x78218d33122e012f,
+    0xd84b7309288b0496, 0x1a195c937eaa76b1, 0xab60ee0d8233017f, 0x6e3110d2c2f5725f,
+    0x96fa25b3a5c0eccf, 0xeb1b25327d010f53, 0x23b4f3b22f96994f, 0xa57bcd789a7fa5bf,
+    0x957754d4b24d867c, 0x4f0ba858548006f1, 0xebbf25773ec28db9, 0xfe18341ed07b9b08,
+    0xac6bdd1173d09a6b, 0xa8e09e8c7bf693e7, 0xab0e05d69653a7dc, 0xcd8778a22cd38c00,
+    0xd2d78746d02887fe, 0xb82e86f1be42d89a, 0xd16f0be35e4946d1, 0x54485b15931e2b87,
+    0x72aafc055a478906, 0x2abf4f436fb16492, 0x38c377983bf83399, 0xfe1ec0d38a994cef,
+    0xf4b8e620cd367caf, 0xb6778bb42fe4bda7, 0xd9000c0f321c9e9c, 0x2af7e56e890de3fa,
+    0xe49975fb6d77982c, 0x0260b32447ba34d0, 0xbe3aaf686fe0cb34, 0x8e8fb04e23c828c4,
+    0x3f3d800ac2e3f585, 0xa34c5a55e4b7bfe4, 0xd8d5558997b2def0, 0x59dc755df19604e1,
+    0xe2c205192f48027d, 0x5504b5c3acd7c0dc, 0x5de5c9d8ad2c01df, 0xfce9f351fc2cbe3f,
+    0x78613433b6d00896, 0x8a80c7ec4dc88c12, 0x5bd084618f07e6a3, 0x44b3518700bb785e,
+    0xceda5385bc899538, 0xe282be4513731971, 0xec9703b9acfd3015, 0xf7202200cc6ea00a,
+    0xbf9e3695ea91425a, 0x09ad6b5a48b6e205, 0x7c6118f621aea77b, 0x9a0afdfe84d11cb5,
+    0x80f9ae0c6fe8edc0, 0x7aa0e77eebf7dec2, 0xb61cf33bd77581bd, 0x6b0b857f801de121,
+    0x9390c3b52b8d03c0, 0xb1fc00d76ddc358a, 0x8f16061d1c7f8a99, 0xea31ad920ac7d011,
+    0x66223c202df33e03, 0xe450108f5c138ca5, 0xa07903d9c839ee90, 0xd3e468947e1dc994,
+    0x48971917567e3fe4, 0xf803032dfae52816, 0x67ebce43781fca8c, 0x5d11f21e96aabac0,
+    0x999ad202217bd7ac, 0xac28472fb790b4d1, 0xf1010ced945a994a, 0x5e2ab63038c156f8,
+    0x083bd3cdb23405fa, 0x30541efebce72b78, 0x42724bdfe9c5b3b0, 0x90b9affabd8b6f1b,
+    0xe5ee9df08c83e653, 0x4418b44b56ec2cc3, 0x9e463ad05db4b32a, 0x8787c47fe9ca0395,
+    0x741fc7ce3396e1db, 0x37d3440535d52042, 0x1c7f1116253e6bff, 0xd2caa257a950a02d,
+    0x1e75cf2bfb9b2331, 0x9de05143779d597c, 0xc2f6224763038461, 0x35fee948b06660d9,
+    0xcbffac2c2e614ff9, 0xeb24b75c64c01865, 0x857be64657cf329d, 0x636ca93e0f9727a5,
+    0x1f6c8ba808485069, 0xd5668a19d80af003, 0x246f80c3241f2233, 0x402c3aab56c1e35a,
+    0xd27f743f30d0c151, 0x9640a8682161b374, 0xb19794765762bfbb, 0x127e5f07a140e934,
+    0x86b11e7048887d2a, 0x2fda234bb681d348, 0x226e8055ded71bce, 0xc68ab1720d5ffd07,
+    0xec53040c3557b862, 0xcc49148c31a0c0b9, 0x25544b239090f9b8, 0x799cbe66aa5e1956,
+    0xabd110ae45622f91, 0x09dc36dd37682a1c, 0x8d7c866d9fbc829a, 0xb6c8dfc62896ee18,
+];
+
+/// Content-defined chunking via FastCDC's gear hash: rolls `fp = (fp << 1) + Gear[byte]` across
+/// the byte stream and cuts whenever `fp & mask == 0`. Using a stricter `mask_s` (more 1-bits, so
+/// less likely to match) below the target average size and a looser `mask_l` (fewer 1-bits) above
+/// it - the "normalized chunking" variant - suppresses tiny chunks without letting large ones run
+/// away, so inserting or deleting a few bytes near the front of a command's output only reshuffles
+/// the chunk(s) immediately around the edit instead of every chunk after it, letting a client that
+/// already holds a chunk with a given `sha256` skip re-fetching it on a rerun.
+struct CdcConfig {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    mask_s: u64,
+    mask_l: u64,
+}
+
+impl CdcConfig {
+    fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        let bits = (avg_size.max(2) as f64).log2().round() as u32;
+        CdcConfig {
+            min_size,
+            avg_size,
+            max_size,
+            mask_s: (1u64 << (bits + 2)) - 1,
+            mask_l: (1u64 << bits.saturating_sub(2).max(1)) - 1,
         }
-        chunks.push(web::Bytes::from(chunk.iter().collect::<String>()));
     }
 
-    chunks
+    /// The byte length of each content-defined chunk `bytes` splits into.
+    fn cut_lengths(&self, bytes: &[u8]) -> Vec<usize> {
+        let mut lengths = vec![];
+        let mut current = 0usize;
+        let mut fingerprint = 0u64;
+
+        for &byte in bytes {
+            fingerprint = (fingerprint << 1).wrapping_add(GEAR[byte as usize]);
+            current += 1;
+
+            let mask = if current < self.avg_size { self.mask_s } else { self.mask_l };
+            let should_cut = current >= self.max_size || (current >= self.min_size && fingerprint & mask == 0);
+
+            if should_cut {
+                lengths.push(current);
+                current = 0;
+                fingerprint = 0;
+            }
+        }
+
+        if current > 0 {
+            lengths.push(current);
+        }
+
+        lengths
+    }
+}
+
+/// Encrypts chunk payloads with XChaCha20-Poly1305 so a client can be exposed beyond localhost
+/// without streaming command output as plaintext. The nonce is derived from the stream `id`, the
+/// `event_type` ("stdout"/"stderr"), and the chunk's `index` rather than drawn from an RNG, so
+/// encryption stays stateless: the client can recompute the same nonce from metadata it already
+/// gets (`id`/`event_type` off the `id:` line, `index`) instead of vawk having to transmit or
+/// track one. `event_type` has to be in the mix, not just `stream_id`/`index`: stdout and stderr
+/// share a stream id but each index their chunks from 0 (see the comment on `ChunkWriter::header`
+/// below), so without it stdout chunk N and stderr chunk N would reuse the same (key, nonce) pair
+/// - fatal for a stream cipher.
+struct ChunkEncryptor {
+    cipher: XChaCha20Poly1305,
+    stream_id: usize,
+    event_type: &'static str,
+}
+
+impl ChunkEncryptor {
+    fn new(key: &[u8; 32], stream_id: usize, event_type: &'static str) -> Self {
+        ChunkEncryptor {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key)),
+            stream_id,
+            event_type,
+        }
+    }
+
+    fn nonce_for(&self, index: usize) -> XNonce {
+        let mut bytes = [0u8; 24];
+        bytes[0..8].copy_from_slice(&(self.stream_id as u64).to_le_bytes());
+        bytes[8..16].copy_from_slice(&(index as u64).to_le_bytes());
+        let tag = Sha256::digest(self.event_type.as_bytes());
+        bytes[16..24].copy_from_slice(&tag[0..8]);
+        *XNonce::from_slice(&bytes)
+    }
+
+    /// Encrypts `plaintext` (a chunk's already-encoded body) and returns the nonce used alongside
+    /// the ciphertext+tag, so the caller can put both in the SSE event.
+    fn encrypt(&self, index: usize, plaintext: &[u8]) -> (XNonce, Vec<u8>) {
+        let nonce = self.nonce_for(index);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .expect("encrypting a chunk-sized plaintext with a validly-sized key/nonce never fails");
+        (nonce, ciphertext)
+    }
+}
+
+/// Accumulates a chunk's encoded body in a reused `BytesMut` capped at `max_chunk_size` bytes, so
+/// peak memory stays roughly one chunk regardless of how much total output is pushed through it.
+/// When `framed` is set, it also tracks a running SHA-256 of the pre-encoding bytes that produced
+/// the current body, so each chunk's header can carry its own `len`/`sha256` - a client that
+/// detects a mismatch can re-request that `index` instead of distrusting the whole stream. When
+/// an encryption key is supplied, the body is additionally sealed with `ChunkEncryptor` before
+/// it's sent, so the payload on the wire is ciphertext rather than the bare encoded row data.
+struct ChunkWriter {
+    event_type: &'static str,
+    id: usize,
+    encoding: OutputEncoding,
+    total: usize,
+    index: usize,
+    resume_from: usize,
+    framed: bool,
+    max_chunk_size: usize,
+    hasher: Sha256,
+    encryptor: Option<ChunkEncryptor>,
+    body: BytesMut,
+    chunks: Vec<web::Bytes>,
+}
+
+impl ChunkWriter {
+    fn new(
+        event_type: &'static str,
+        id: usize,
+        encoding: OutputEncoding,
+        total: usize,
+        framed: bool,
+        encryption_key: Option<&[u8; 32]>,
+        resume_from: usize,
+        max_chunk_size: usize,
+    ) -> Self {
+        ChunkWriter {
+            event_type,
+            id,
+            encoding,
+            total,
+            index: 0,
+            resume_from,
+            framed,
+            max_chunk_size,
+            hasher: Sha256::new(),
+            encryptor: encryption_key.map(|key| ChunkEncryptor::new(key, id, event_type)),
+            // Capped at the compile-time default even if `max_chunk_size` was raised well past it,
+            // so opting into a huge (or effectively unbounded) chunk size doesn't also mean eagerly
+            // allocating that much up front - `push` still grows `body` past this via `BytesMut`.
+            body: BytesMut::with_capacity(max_chunk_size.min(MAX_CHUNK_SIZE)),
+            chunks: vec![],
+        }
+    }
+
+    /// Folds `bytes` - raw, pre-encoding payload - into the running checksum for the chunk
+    /// currently being built. A no-op unless `framed` is set, since hashing every byte of a large
+    /// output is wasted work for callers who never asked to verify it.
+    fn push_raw(&mut self, bytes: &[u8]) {
+        if self.framed {
+            self.hasher.update(bytes);
+        }
+    }
+
+    /// Appends `bytes` to the current chunk's body, flushing and starting a fresh chunk every
+    /// time the body hits `max_chunk_size` - `bytes` is free to span more than one chunk.
+    fn push(&mut self, bytes: &[u8]) {
+        let mut remaining = bytes;
+        while !remaining.is_empty() {
+            let space = self.max_chunk_size - self.body.len();
+            let take = space.min(remaining.len());
+            self.body.extend_from_slice(&remaining[..take]);
+            remaining = &remaining[take..];
+
+            if self.body.len() == self.max_chunk_size {
+                self.flush_chunk();
+            }
+        }
+    }
+
+    /// Renders this chunk's `id:`/`event:`/`data:` header given the payload that's actually about
+    /// to be sent (post-encryption, if any). Only known once the body (and, if `framed`, its
+    /// checksum; if encrypted, its nonce) is complete, so - unlike the metadata-only fields
+    /// (`index`/`total`/`id`) - this can't be written until the chunk is about to be flushed.
+    ///
+    /// The `id:` line includes `event_type` alongside the stream id and index - stdout and stderr
+    /// each have their own independent index sequence on a shared stream id, so the event type is
+    /// needed to tell a `Last-Event-ID` of "5-stdout-2" apart from "5-stderr-2".
+    fn header(&mut self, payload_len: usize, encryption_metadata: Option<String>) -> String {
+        let mut metadata = format!("\"index\": {}, \"total\": {}", self.index, self.total);
+        if self.index > 0 {
+            metadata.push_str(&format!(", \"id\": {}", self.id));
+        }
+        metadata.push_str(&format!(", \"encoding\": \"{}\"", self.encoding.label()));
+        if self.framed {
+            let digest = self.hasher.finalize_reset();
+            metadata.push_str(&format!(", \"len\": {}, \"sha256\": \"{}\"", payload_len, hex_string(&digest)));
+        }
+        if let Some(encryption_metadata) = encryption_metadata {
+            metadata.push_str(&encryption_metadata);
+        }
+
+        format!(
+            "id: {}-{}-{}\nevent: {}\ndata: {{{}}}\ndata: ",
+            self.id, self.event_type, self.index, self.event_type, metadata
+        )
+    }
+
+    /// Re-encoding from scratch is how a dropped connection resumes: the caller recomputes the
+    /// full chunk sequence and `resume_from` just tells this writer which prefix the client
+    /// already has, so `listen`'s replay only has to transmit the chunks it's actually missing.
+    /// The checksum still has to be finalized and reset for every chunk - skipped or not - so a
+    /// later, sent chunk's hash isn't contaminated by an earlier, skipped one's bytes.
+    fn flush_chunk(&mut self) {
+        let body = self.body.split();
+
+        let (payload, encryption_metadata) = match &self.encryptor {
+            Some(encryptor) => {
+                let (nonce, ciphertext) = encryptor.encrypt(self.index, &body);
+                let payload = base64_string(&ciphertext);
+                let metadata = format!(
+                    ", \"scheme\": \"xchacha20poly1305\", \"nonce\": \"{}\"",
+                    base64_string(nonce.as_slice())
+                );
+                (BytesMut::from(payload.as_bytes()), Some(metadata))
+            }
+            None => (body, None),
+        };
+
+        let header = self.header(payload.len(), encryption_metadata);
+
+        if self.index >= self.resume_from {
+            let mut framed = BytesMut::with_capacity(header.len() + payload.len() + 2);
+            framed.extend_from_slice(header.as_bytes());
+            framed.extend_from_slice(&payload);
+            framed.extend_from_slice(b"\n\n");
+
+            self.chunks.push(framed.freeze());
+        }
+        self.index += 1;
+    }
+
+    fn finish(mut self) -> Vec<web::Bytes> {
+        self.flush_chunk();
+        self.chunks
+    }
+}
+
+/// Builds one content-defined chunk's SSE event. Unlike `ChunkWriter::header`, the checksum here
+/// is always present (it's the whole point of content-defined chunking - a client diffing two
+/// runs needs it to tell which chunks changed) rather than gated behind `framed`.
+fn cdc_chunk_event(
+    event_type: &'static str,
+    id: usize,
+    index: usize,
+    total: usize,
+    encoding: OutputEncoding,
+    chunk: &[u8],
+    encryptor: Option<&ChunkEncryptor>,
+) -> web::Bytes {
+    let digest = Sha256::digest(chunk);
+
+    let (payload, encryption_metadata) = match encryptor {
+        Some(encryptor) => {
+            let (nonce, ciphertext) = encryptor.encrypt(index, chunk);
+            let metadata = format!(
+                ", \"scheme\": \"xchacha20poly1305\", \"nonce\": \"{}\"",
+                base64_string(nonce.as_slice())
+            );
+            (base64_string(&ciphertext).into_bytes(), Some(metadata))
+        }
+        None => (chunk.to_vec(), None),
+    };
+
+    let mut metadata = format!("\"index\": {}, \"total\": {}", index, total);
+    if index > 0 {
+        metadata.push_str(&format!(", \"id\": {}", id));
+    }
+    metadata.push_str(&format!(", \"encoding\": \"{}\"", encoding.label()));
+    metadata.push_str(&format!(", \"len\": {}, \"sha256\": \"{}\"", payload.len(), hex_string(&digest)));
+    if let Some(encryption_metadata) = encryption_metadata {
+        metadata.push_str(&encryption_metadata);
+    }
+
+    let header = format!(
+        "id: {}-{}-{}\nevent: {}\ndata: {{{}}}\ndata: ",
+        id, event_type, index, event_type, metadata
+    );
+
+    let mut framed = BytesMut::with_capacity(header.len() + payload.len() + 2);
+    framed.extend_from_slice(header.as_bytes());
+    framed.extend_from_slice(&payload);
+    framed.extend_from_slice(b"\n\n");
+    framed.freeze()
+}
+
+/// Splits `bytes` at FastCDC's content-defined boundaries and turns each piece into an SSE event,
+/// skipping everything before `resume_from` the same way `ChunkWriter::flush_chunk` does for
+/// fixed-size chunking.
+fn cdc_chunks(
+    event_type: &'static str,
+    id: usize,
+    encoding: OutputEncoding,
+    bytes: &[u8],
+    encryption_key: Option<&[u8; 32]>,
+    resume_from: usize,
+    max_chunk_size: usize,
+) -> Vec<web::Bytes> {
+    let cdc = CdcConfig::new(CDC_MIN_SIZE, CDC_AVG_SIZE, max_chunk_size);
+    let lengths = cdc.cut_lengths(bytes);
+    let total = lengths.len();
+    let encryptor = encryption_key.map(|key| ChunkEncryptor::new(key, id, event_type));
+
+    let mut events = Vec::with_capacity(total.saturating_sub(resume_from));
+    let mut offset = 0;
+    for (index, length) in lengths.into_iter().enumerate() {
+        let chunk = &bytes[offset..offset + length];
+        offset += length;
+
+        if index >= resume_from {
+            events.push(cdc_chunk_event(event_type, id, index, total, encoding, chunk, encryptor.as_ref()));
+        }
+    }
+
+    events
 }
 
-pub fn stdout_chunks(stdout: &Vec<Vec<Vec<u8>>>, id: usize) -> Result<Vec<web::Bytes>, EncodingError> {
-    let encoded = encode_stdout(stdout)?;
-    Ok(chunked(&encoded, id, "stdout"))
+/// Tells the client a stream stopped early because `max_output_size` was reached, rather than
+/// letting it assume the last chunk it saw was the end of the output. `delivered_bytes` is the
+/// raw (pre-encoding) byte count actually sent; `more_available` is always `true` for now since
+/// this is only ever emitted when there was in fact more - kept as an explicit field so a future
+/// truncation reason (e.g. a client-requested cutoff) can reuse this event with `false`.
+pub fn truncated_message(event_type: &'static str, id: usize, delivered_bytes: usize, more_available: bool) -> web::Bytes {
+    web::Bytes::from(format!(
+        "id: {}-{}-truncated\nevent: truncated\ndata: {{\"id\": {}, \"delivered_bytes\": {}, \"more_available\": {}}}\n\n",
+        id, event_type, id, delivered_bytes, more_available
+    ))
+}
+
+pub fn stdout_chunks(
+    stdout: &Vec<Vec<Vec<u8>>>,
+    id: usize,
+    encoding: OutputEncoding,
+    framed: bool,
+    encryption_key: Option<&[u8; 32]>,
+    resume_from: usize,
+    content_defined: bool,
+    max_chunk_size: usize,
+    max_output_size: usize,
+) -> Vec<web::Bytes> {
+    let (lines, delivered_bytes, truncated) = stdout_prefix(stdout, max_output_size);
+
+    let mut chunks = if content_defined {
+        let bytes = encode_stdout_bytes(&lines, encoding);
+        cdc_chunks("stdout", id, encoding, &bytes, encryption_key, resume_from, max_chunk_size)
+    } else {
+        let total = num_chunks(stdout_encoded_len(&lines, encoding), max_chunk_size);
+        let mut writer = ChunkWriter::new("stdout", id, encoding, total, framed, encryption_key, resume_from, max_chunk_size);
+
+        writer.push(b"[");
+        for (line_index, line) in lines.iter().enumerate() {
+            if line_index > 0 {
+                writer.push(b",");
+            }
+            writer.push(b"[");
+
+            for (row_index, row) in line.iter().enumerate() {
+                if row_index > 0 {
+                    writer.push(b",");
+                }
+                writer.push(b"\"");
+                encode_row_into(&mut writer, row, encoding);
+                writer.push(b"\"");
+            }
+
+            writer.push(b"]");
+        }
+        writer.push(b"]");
+
+        writer.finish()
+    };
+
+    if truncated {
+        chunks.push(truncated_message("stdout", id, delivered_bytes, true));
+    }
+
+    chunks
 }
 
-pub fn stderr_chunks(stderr: &Vec<u8>, id: usize) -> Result<Vec<web::Bytes>, EncodingError> {
-    let encoded = encode_stderr(stderr)?;
-    Ok(chunked(&encoded, id, "stderr"))
+pub fn stderr_chunks(
+    stderr: &Vec<u8>,
+    id: usize,
+    encoding: OutputEncoding,
+    framed: bool,
+    encryption_key: Option<&[u8; 32]>,
+    resume_from: usize,
+    content_defined: bool,
+    max_chunk_size: usize,
+    max_output_size: usize,
+) -> Vec<web::Bytes> {
+    let truncated = stderr.len() > max_output_size;
+    let stderr: &[u8] = if truncated { &stderr[..max_output_size] } else { stderr };
+    let delivered_bytes = stderr.len();
+
+    let mut chunks = if content_defined {
+        let mut bytes = Vec::with_capacity(encoding.encoded_len(stderr.len()));
+        encode_row_bytes(&mut bytes, stderr, encoding);
+        cdc_chunks("stderr", id, encoding, &bytes, encryption_key, resume_from, max_chunk_size)
+    } else {
+        let total = num_chunks(encoding.encoded_len(stderr.len()), max_chunk_size);
+        let mut writer = ChunkWriter::new("stderr", id, encoding, total, framed, encryption_key, resume_from, max_chunk_size);
+        encode_row_into(&mut writer, stderr, encoding);
+        writer.finish()
+    };
+
+    if truncated {
+        chunks.push(truncated_message("stderr", id, delivered_bytes, true));
+    }
+
+    chunks
 }
 
 pub fn status_message(status: &ExitStatus, id: usize) -> web::Bytes {
-    web::Bytes::from(format!("event: status\ndata: {{\"status\": {}, \"id\": {}}}\n\n", status, id))
-}
\ No newline at end of file
+    web::Bytes::from(format!(
+        "id: {}-status\nevent: status\ndata: {{\"status\": {}, \"id\": {}}}\n\n",
+        id, status, id
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::ChunkEncryptor;
+
+    #[test]
+    fn nonce_for_differs_between_stdout_and_stderr_at_the_same_index() {
+        let key = [0u8; 32];
+        let stdout = ChunkEncryptor::new(&key, 5, "stdout");
+        let stderr = ChunkEncryptor::new(&key, 5, "stderr");
+
+        assert_ne!(stdout.nonce_for(2), stderr.nonce_for(2));
+    }
+}
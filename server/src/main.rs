@@ -1,9 +1,12 @@
 mod byte_trie;
+mod config;
 mod parsers;
+mod presets;
 mod protos;
 mod transformers;
 mod websocket_connection;
 
+use actix::prelude::*;
 use actix_cors::Cors;
 use actix_files;
 use actix_web::middleware::Logger;
@@ -11,17 +14,25 @@ use actix_web::web;
 use actix_web_actors::ws;
 use env_logger;
 use std::io;
+use std::path::PathBuf;
 
-async fn connect(r: actix_web::HttpRequest, stream: web::Payload) -> Result<actix_web::HttpResponse, actix_web::Error> {
-    ws::start(websocket_connection::WebsocketConnection::new(transformers::Options::default(), transformers::Options::default()), &r, stream)
+async fn connect(r: actix_web::HttpRequest, stream: web::Payload, config_watcher: web::Data<Addr<config::ConfigWatcher>>) -> Result<actix_web::HttpResponse, actix_web::Error> {
+    let config_watcher = config_watcher.get_ref().clone();
+    let (column_options, row_options) = config_watcher.send(config::GetDefaults).await.unwrap_or_else(|_| (transformers::Options::default(), transformers::Options::default()));
+
+    ws::start(websocket_connection::WebsocketConnection::new(column_options, row_options, config_watcher), &r, stream)
 }
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
     env_logger::init();
 
+    let config_path = PathBuf::from(std::env::var("VAWK_CONFIG_PATH").unwrap_or_else(|_| "vawk.toml".to_owned()));
+    let config_watcher = config::ConfigWatcher::new(config_path).start();
+
     actix_web::HttpServer::new(move || {
         actix_web::App::new()
+            .app_data(web::Data::new(config_watcher.clone()))
             .service(web::resource("/ws/").route(web::get().to(connect)))
             .service(actix_files::Files::new("/", "../client/").index_file("index.html"))
             .wrap(Logger::default())
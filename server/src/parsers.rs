@@ -1,6 +1,6 @@
 use crate::byte_trie::ByteTrie;
 use nom::branch::alt;
-use nom::bytes::complete::{tag, take};
+use nom::bytes::complete::{tag, take, take_while_m_n};
 use nom::character::complete::{digit1, space0};
 use nom::combinator::{self, value};
 use nom::multi::many0;
@@ -8,16 +8,89 @@ use nom::sequence::{delimited, preceded, separated_pair, terminated, tuple};
 use nom::Finish;
 use nom::IResult;
 use regex::bytes::Regex;
+use serde::Serialize;
 use std::str::FromStr;
 
+/// A structured description of why a user-supplied separator/filter/regex string failed to
+/// parse, meant to be serialized straight into a 400 response body so a client can point at the
+/// offending character instead of just flashing a generic "invalid" error.
+#[derive(Debug, Serialize)]
+pub struct ParseErrorBody {
+    pub field: String,
+    pub message: String,
+    pub byte_offset: Option<usize>,
+    pub snippet: String,
+}
+
+/// How far past the offending byte to keep in `ParseErrorBody::snippet`, so a long separator
+/// string doesn't produce an unreadable wall of text in the response body.
+const SNIPPET_LEN: usize = 32;
+
+/// Locates where a nom parse failed relative to the original input: `byte_offset` is how far
+/// into `original` the unconsumed `remainder` begins, and the snippet is the unconsumed text
+/// itself (already positioned at the offending token), capped to `SNIPPET_LEN`.
+fn locate_error(original: &str, remainder: &str) -> (Option<usize>, String) {
+    let byte_offset = original.len().checked_sub(remainder.len());
+    let snippet = remainder.chars().take(SNIPPET_LEN).collect();
+    (byte_offset, snippet)
+}
+
 #[derive(Debug)]
-pub struct InvalidFieldSeparatorError(String);
+pub struct InvalidFieldSeparatorError {
+    message: String,
+    byte_offset: Option<usize>,
+    snippet: String,
+}
+
+impl InvalidFieldSeparatorError {
+    pub fn into_response_body(self, field: &str) -> ParseErrorBody {
+        ParseErrorBody {
+            field: field.to_owned(),
+            message: self.message,
+            byte_offset: self.byte_offset,
+            snippet: self.snippet,
+        }
+    }
+}
 
 #[derive(Debug)]
-pub struct InvalidIndexFiltersError(String);
+pub struct InvalidIndexFiltersError {
+    message: String,
+    byte_offset: Option<usize>,
+    snippet: String,
+}
+
+impl InvalidIndexFiltersError {
+    pub fn into_response_body(self, field: &str) -> ParseErrorBody {
+        ParseErrorBody {
+            field: field.to_owned(),
+            message: self.message,
+            byte_offset: self.byte_offset,
+            snippet: self.snippet,
+        }
+    }
+}
 
 #[derive(Debug)]
-pub struct InvalidRegexFilterError(String);
+pub struct InvalidRegexFilterError {
+    message: String,
+    byte_offset: Option<usize>,
+    snippet: String,
+}
+
+impl InvalidRegexFilterError {
+    pub fn into_response_body(self, field: &str) -> ParseErrorBody {
+        ParseErrorBody {
+            field: field.to_owned(),
+            message: self.message,
+            byte_offset: self.byte_offset,
+            snippet: self.snippet,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidRegexTransformError(String);
 
 /*********************************************************************************************************************
  * Rules for separating data                                                                                         *
@@ -28,23 +101,74 @@ pub struct InvalidRegexFilterError(String);
  * empty string.)  However, just an empty string is not treated as a separator, to avoid garbled-looking output.     *
  *********************************************************************************************************************/
 
+/// Parses `\xHH`, two hex digits collapsing to the one byte they encode.
+fn hex_byte(input: &str) -> IResult<&str, Vec<u8>> {
+    combinator::map_opt(
+        preceded(tag("\\x"), take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit())),
+        |hex: &str| u8::from_str_radix(hex, 16).ok().map(|byte| vec![byte]),
+    )(input)
+}
+
+/// Parses `\0` followed by one or two octal digits, e.g. `\012`, collapsing to the one byte they
+/// encode. Ordered ahead of the bare `\0` (NUL) alternative in `escaped_field_separator` so `\012`
+/// reads as octal `0o12` rather than a NUL followed by the literal characters `'1'`, `'2'`.
+fn octal_byte(input: &str) -> IResult<&str, Vec<u8>> {
+    combinator::map_opt(
+        preceded(tag("\\0"), take_while_m_n(1, 2, |c: char| ('0'..='7').contains(&c))),
+        |oct: &str| u8::from_str_radix(oct, 8).ok().map(|byte| vec![byte]),
+    )(input)
+}
+
+/// Parses `\u{...}` (one to six hex digits), a Unicode code point encoded as its UTF-8 bytes.
+fn unicode_escape_braced(input: &str) -> IResult<&str, Vec<u8>> {
+    combinator::map_opt(
+        delimited(tag("\\u{"), take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit()), tag("}")),
+        |hex: &str| u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).map(|c| c.to_string().into_bytes()),
+    )(input)
+}
+
+/// Parses `\uXXXX`, exactly four hex digits, a Unicode code point encoded as its UTF-8 bytes.
+fn unicode_escape_plain(input: &str) -> IResult<&str, Vec<u8>> {
+    combinator::map_opt(
+        preceded(tag("\\u"), take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit())),
+        |hex: &str| u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).map(|c| c.to_string().into_bytes()),
+    )(input)
+}
+
 /// escaped_separator handles getting escaped characters from a user-input separator string.
-/// It will treat "\\n", "\\t", "\\r", and "\\s" as the literal characters '\n', '\t', '\r', and ' '.
-fn escaped_field_separator(input: &str) -> IResult<&str, u8> {
+/// It will treat "\\n", "\\t", "\\r", and "\\s" as the literal characters '\n', '\t', '\r', and
+/// ' '; "\\0" as a NUL byte; "\\0NN" (one or two octal digits) as that octal byte; "\\\\" as a
+/// literal backslash; "\\xHH" as the byte `0xHH`; and "\\u{...}"/"\\uXXXX" as a Unicode code
+/// point's UTF-8 bytes.
+fn escaped_field_separator(input: &str) -> IResult<&str, Vec<u8>> {
     alt((
-        value(b'\n', tag("\\n")),
-        value(b'\t', tag("\\t")),
-        value(b'\r', tag("\\r")),
-        value(b' ', tag("\\s")),
+        value(vec![b'\n'], tag("\\n")),
+        value(vec![b'\t'], tag("\\t")),
+        value(vec![b'\r'], tag("\\r")),
+        value(vec![b' '], tag("\\s")),
+        octal_byte,
+        value(vec![0u8], tag("\\0")),
+        value(vec![b'\\'], tag("\\\\")),
+        hex_byte,
+        unicode_escape_braced,
+        unicode_escape_plain,
     ))(input)
 }
 
+/// A single character that isn't the start of an escape, taken as-is. Excluding `\` here (rather
+/// than falling back to it once `escaped_field_separator` fails) means a malformed escape like
+/// `\q` or a truncated `\x4` is rejected outright instead of being silently read as a literal
+/// backslash followed by the rest of the token.
+fn plain_field_separator_char(input: &str) -> IResult<&str, Vec<u8>> {
+    combinator::map(
+        combinator::verify(take(1usize), |s: &str| s != "\\"),
+        |s: &str| s.bytes().collect::<Vec<u8>>(),
+    )(input)
+}
+
 fn field_separator<'a>(input: &'a str, byte_trie: &mut ByteTrie) -> IResult<&'a str, ()> {
     combinator::map(
-        many0(alt((
-            combinator::map(escaped_field_separator, |byte| vec![byte]),
-            combinator::map(take(1usize), |s: &str| s.bytes().collect::<Vec<u8>>()),
-        ))),
+        many0(alt((escaped_field_separator, plain_field_separator_char))),
         |mut chars: Vec<Vec<u8>>| {
             let mut combined = vec![];
             for char_bytes in chars.iter_mut() {
@@ -56,25 +180,68 @@ fn field_separator<'a>(input: &'a str, byte_trie: &mut ByteTrie) -> IResult<&'a
     )(input)
 }
 
+/// How a row/column is split into fields. `Literal` matches any of a fixed set of byte
+/// sequences, the historical behavior. `Pattern` matches an arbitrary regex, so a separator like
+/// `\s+` can collapse runs of whitespace the way awk's FS does.
+pub enum FieldSeparator {
+    Literal(ByteTrie),
+    Pattern(Regex),
+}
+
+/// True if `string_representation` contains a byte that only makes sense as a regex construct
+/// (a quantifier, character class, group, or anchor), so a plain multi-character literal
+/// separator like "::" isn't mistaken for a pattern.
+fn looks_like_pattern(string_representation: &str) -> bool {
+    string_representation
+        .bytes()
+        .any(|byte| matches!(byte, b'*' | b'+' | b'?' | b'[' | b']' | b'(' | b')' | b'|' | b'^' | b'$' | b'{' | b'}' | b'.'))
+}
+
 /// Parses field separators from a string.
+///
+/// A single separator string containing a regex metacharacter is compiled as a `Pattern`;
+/// otherwise every given string is parsed as a literal (with the usual escapes) and inserted
+/// into a shared `ByteTrie`.
 pub fn parse_field_separators(
     string_representations: &Vec<String>,
-) -> Result<ByteTrie, InvalidFieldSeparatorError> {
+) -> Result<FieldSeparator, InvalidFieldSeparatorError> {
+    if let [only] = string_representations.as_slice() {
+        if looks_like_pattern(only) {
+            return Regex::new(only).map(FieldSeparator::Pattern).map_err(|error| InvalidFieldSeparatorError {
+                message: format!("{}", error),
+                byte_offset: None,
+                snippet: only.chars().take(SNIPPET_LEN).collect(),
+            });
+        }
+    }
+
     let mut separators = ByteTrie::new();
 
     for string_representation in string_representations {
         match field_separator(string_representation, &mut separators).finish() {
-            Err(error) => return Err(InvalidFieldSeparatorError(error.input.to_owned())),
+            Err(error) => {
+                let (byte_offset, snippet) = locate_error(string_representation, error.input);
+                return Err(InvalidFieldSeparatorError {
+                    message: "could not parse a separator escape".to_owned(),
+                    byte_offset,
+                    snippet,
+                });
+            }
             Ok((unconsumed_input, _))
                 if separators.is_empty() && !unconsumed_input.is_empty() =>
             {
-                return Err(InvalidFieldSeparatorError(unconsumed_input.to_owned()))
+                let (byte_offset, snippet) = locate_error(string_representation, unconsumed_input);
+                return Err(InvalidFieldSeparatorError {
+                    message: "could not parse a separator escape".to_owned(),
+                    byte_offset,
+                    snippet,
+                });
             }
             _ => (),
         }
     }
 
-    Ok(separators)
+    Ok(FieldSeparator::Literal(separators))
 }
 
 /*********************************************************************************************************************
@@ -86,12 +253,17 @@ pub fn parse_field_separators(
  * - By regex; users can say that they only want lines matching a particular regex.                                  *
  *********************************************************************************************************************/
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum IndexFilter {
     Bounded(usize, usize),
     LowerBounded(usize),
     UpperBounded(usize),
     Exact(usize),
+    /// Counts back from the end of the data, e.g. "-1" means the last entry.
+    FromEnd(usize),
+    /// A `Bounded`-style range where both ends count back from the end of the data, e.g.
+    /// "-3..-1" means the third-from-last entry up to (but not including) the last entry.
+    BoundedFromEnd(usize, usize),
 }
 
 impl IndexFilter {
@@ -101,10 +273,36 @@ impl IndexFilter {
             IndexFilter::LowerBounded(lower) => i >= *lower,
             IndexFilter::UpperBounded(upper) => i < *upper,
             IndexFilter::Exact(j) => i == *j,
+            IndexFilter::FromEnd(_) | IndexFilter::BoundedFromEnd(_, _) => {
+                unreachable!("from-end filters must be resolved against a length before matching")
+            }
+        }
+    }
+
+    /// Resolves a from-end filter into its absolute equivalent given `len`, so that `is_match`
+    /// never needs to know how large the data is.
+    pub fn resolve(&self, len: usize) -> IndexFilter {
+        match self {
+            IndexFilter::FromEnd(n) => IndexFilter::Exact(len.saturating_sub(*n)),
+            IndexFilter::BoundedFromEnd(lower, upper) => {
+                IndexFilter::Bounded(len.saturating_sub(*lower), len.saturating_sub(*upper))
+            }
+            other => *other,
         }
     }
 }
 
+/// A set of index filters, optionally negated.
+///
+/// `negate` comes from a leading `!` on the whole rule string (e.g. `!2,4` keeps everything
+/// except indexes 2 and 4), and applies to the result of matching against every rule in
+/// `rules`, not to each rule individually.
+#[derive(Debug, PartialEq)]
+pub struct IndexFilters {
+    pub negate: bool,
+    pub rules: Vec<IndexFilter>,
+}
+
 fn index(input: &str) -> IResult<&str, usize> {
     combinator::map(digit1, |s: &str| usize::from_str(s).unwrap())(input)
 }
@@ -131,8 +329,23 @@ fn exact(input: &str) -> IResult<&str, IndexFilter> {
     combinator::map(index, |i| IndexFilter::Exact(i))(input)
 }
 
+fn from_end_index(input: &str) -> IResult<&str, usize> {
+    preceded(tag("-"), index)(input)
+}
+
+fn bounded_from_end(input: &str) -> IResult<&str, IndexFilter> {
+    combinator::map(
+        separated_pair(from_end_index, tag(".."), from_end_index),
+        |(lower, upper)| IndexFilter::BoundedFromEnd(lower, upper),
+    )(input)
+}
+
+fn from_end(input: &str) -> IResult<&str, IndexFilter> {
+    combinator::map(from_end_index, IndexFilter::FromEnd)(input)
+}
+
 fn index_filter(input: &str) -> IResult<&str, IndexFilter> {
-    alt((bounded, lower_bounded, upper_bounded, exact))(input)
+    alt((bounded_from_end, from_end, bounded, lower_bounded, upper_bounded, exact))(input)
 }
 
 fn index_filter_separator(input: &str) -> IResult<&str, ()> {
@@ -141,11 +354,14 @@ fn index_filter_separator(input: &str) -> IResult<&str, ()> {
 
 /// Parses index filters that a user inputs.
 ///
-/// This parses 4 types of index filters:
+/// This parses 6 types of index filters:
 /// 1. Exact: "4" matches the row with the index of "4".
 /// 2. Bounded: "6..10" matches rows where the index is >= 6 and < 10.
 /// 3. Lower bounded: "5.." matches rows where the index is >= 5.
 /// 4. Upper bounded: "..96" matches rows where the index is < 96.
+/// 5. From end: "-1" matches the last row, "-2" the second-to-last, and so on.
+/// 6. Bounded from end: "-3..-1" matches rows from the third-to-last up to (but not including)
+///    the last.
 fn index_filters(input: &str) -> IResult<&str, Vec<IndexFilter>> {
     delimited(
         space0,
@@ -157,45 +373,225 @@ fn index_filters(input: &str) -> IResult<&str, Vec<IndexFilter>> {
     )(input)
 }
 
+/// Parses index filters that a user inputs, along with an optional exclusion flavor.
+///
+/// A leading `!` on the whole string (e.g. `!2,4`) negates the result: rows are kept only when
+/// none of the rules match. See `index_filters` for the rule syntax itself.
 pub fn parse_index_filters(
     string_representation: &str,
-) -> Result<Vec<IndexFilter>, InvalidIndexFiltersError> {
-    match index_filters(string_representation).finish() {
-        Err(error) => Err(InvalidIndexFiltersError(error.input.to_owned())),
+) -> Result<IndexFilters, InvalidIndexFiltersError> {
+    let (negate, rest) = match string_representation.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, string_representation),
+    };
+
+    match index_filters(rest).finish() {
+        Err(error) => {
+            let (byte_offset, snippet) = locate_error(rest, error.input);
+            Err(InvalidIndexFiltersError {
+                message: "could not parse an index filter".to_owned(),
+                byte_offset,
+                snippet,
+            })
+        }
         Ok((unconsumed_input, rules)) if rules.is_empty() && !unconsumed_input.is_empty() => {
-            Err(InvalidIndexFiltersError(unconsumed_input.to_owned()))
+            let (byte_offset, snippet) = locate_error(rest, unconsumed_input);
+            Err(InvalidIndexFiltersError {
+                message: "could not parse an index filter".to_owned(),
+                byte_offset,
+                snippet,
+            })
         }
-        Ok((_, rules)) => Ok(rules),
+        Ok((_, rules)) => Ok(IndexFilters { negate, rules }),
     }
 }
 
 pub fn parse_regex_filter(string_representation: &str) -> Result<Regex, InvalidRegexFilterError> {
-    Regex::new(string_representation).map_err(|error| InvalidRegexFilterError(format!("{}", error)))
+    Regex::new(string_representation).map_err(|error| InvalidRegexFilterError {
+        message: format!("{}", error),
+        byte_offset: None,
+        snippet: string_representation.chars().take(SNIPPET_LEN).collect(),
+    })
+}
+
+/*********************************************************************************************************************
+ * Rules for rewriting data                                                                                          *
+ *                                                                                                                   *
+ * Where a regex filter only ever keeps or drops a field, a regex transform rewrites a matching field using a        *
+ * replacement template, the way sed's s/// does.                                                                    *
+ *********************************************************************************************************************/
+
+/// Rewrites a field that matches `regex` using `template`, analogous to sed's `s///`.
+///
+/// `template` is handed directly to `regex::bytes::Regex::replace`/`replace_all`, which already
+/// expands `$1`/`${name}` references into `regex`'s capture groups and treats `$$` as a literal
+/// `$`, so no separate template parser is needed here.
+pub struct RegexTransform {
+    pub regex: Regex,
+    pub template: Vec<u8>,
+    /// Mirrors sed's `/g` flag: replace every match in a field rather than only the first.
+    pub global: bool,
+    /// Drop fields that don't match `regex` instead of passing them through unmodified.
+    pub keep_only_matches: bool,
+}
+
+pub fn parse_regex_transform(
+    regex_string: &str,
+    template: &str,
+    global: bool,
+    keep_only_matches: bool,
+) -> Result<RegexTransform, InvalidRegexTransformError> {
+    Regex::new(regex_string)
+        .map(|regex| RegexTransform {
+            regex,
+            template: template.as_bytes().to_vec(),
+            global,
+            keep_only_matches,
+        })
+        .map_err(|error| InvalidRegexTransformError(format!("{}", error)))
 }
 
 #[cfg(test)]
 mod test {
     use crate::byte_trie::ByteTrie;
+    use super::FieldSeparator;
 
     #[test]
-    fn parse_field_separators() {
+    fn parse_field_separators_literal() {
         let mut expected = ByteTrie::new();
         expected.insert(&[b'\r', b'\n']);
         match super::parse_field_separators(&vec!["\\r\\n".into()]) {
-            Ok(actual) => assert_eq!(actual, expected),
+            Ok(FieldSeparator::Literal(actual)) => assert_eq!(actual, expected),
+            Ok(FieldSeparator::Pattern(_)) => assert!(false),
+            Err(_) => assert!(false),
+        }
+    }
+
+    #[test]
+    fn parse_field_separators_pattern() {
+        match super::parse_field_separators(&vec!["\\s+".into()]) {
+            Ok(FieldSeparator::Pattern(regex)) => {
+                assert!(regex.is_match(b"   "));
+                assert!(!regex.is_match(b""));
+            }
+            Ok(FieldSeparator::Literal(_)) => assert!(false),
+            Err(_) => assert!(false),
+        }
+    }
+
+    #[test]
+    fn parse_field_separators_hex_and_nul_and_backslash() {
+        let mut expected = ByteTrie::new();
+        expected.insert(&[0u8, 0x41, b'\\']);
+        match super::parse_field_separators(&vec!["\\0\\x41\\\\".into()]) {
+            Ok(FieldSeparator::Literal(actual)) => assert_eq!(actual, expected),
+            Ok(FieldSeparator::Pattern(_)) => assert!(false),
+            Err(_) => assert!(false),
+        }
+    }
+
+    #[test]
+    fn parse_field_separators_octal() {
+        let mut expected = ByteTrie::new();
+        expected.insert(&[0o12u8]);
+        match super::parse_field_separators(&vec!["\\012".into()]) {
+            Ok(FieldSeparator::Literal(actual)) => assert_eq!(actual, expected),
+            Ok(FieldSeparator::Pattern(_)) => assert!(false),
+            Err(_) => assert!(false),
+        }
+
+        let mut expected = ByteTrie::new();
+        expected.insert(&[0u8, b'8']);
+        match super::parse_field_separators(&vec!["\\08".into()]) {
+            Ok(FieldSeparator::Literal(actual)) => assert_eq!(actual, expected),
+            Ok(FieldSeparator::Pattern(_)) => assert!(false),
             Err(_) => assert!(false),
         }
     }
 
+    #[test]
+    fn parse_field_separators_unicode() {
+        let mut expected = ByteTrie::new();
+        expected.insert("€".as_bytes());
+        match super::parse_field_separators(&vec!["\\u{20ac}".into()]) {
+            Ok(FieldSeparator::Literal(actual)) => assert_eq!(actual, expected),
+            Ok(FieldSeparator::Pattern(_)) => assert!(false),
+            Err(_) => assert!(false),
+        }
+
+        let mut expected = ByteTrie::new();
+        expected.insert("€".as_bytes());
+        match super::parse_field_separators(&vec!["\\u20ac".into()]) {
+            Ok(FieldSeparator::Literal(actual)) => assert_eq!(actual, expected),
+            Ok(FieldSeparator::Pattern(_)) => assert!(false),
+            Err(_) => assert!(false),
+        }
+    }
+
+    #[test]
+    fn parse_field_separators_malformed_escape_is_an_error() {
+        assert!(super::parse_field_separators(&vec!["\\q".into()]).is_err());
+        assert!(super::parse_field_separators(&vec!["\\x4".into()]).is_err());
+    }
+
     #[test]
     fn parse_index_filters() {
-        let expected = vec![
-            super::IndexFilter::Exact(1usize),
-            super::IndexFilter::LowerBounded(5usize),
-        ];
+        let expected = super::IndexFilters {
+            negate: false,
+            rules: vec![
+                super::IndexFilter::Exact(1usize),
+                super::IndexFilter::LowerBounded(5usize),
+            ],
+        };
         match super::parse_index_filters("1, 5..") {
             Ok(actual) => assert_eq!(actual, expected),
             Err(_) => assert!(false),
         }
     }
+
+    #[test]
+    fn parse_index_filters_from_end() {
+        let expected = super::IndexFilters {
+            negate: false,
+            rules: vec![
+                super::IndexFilter::FromEnd(1usize),
+                super::IndexFilter::BoundedFromEnd(3usize, 1usize),
+            ],
+        };
+        match super::parse_index_filters("-1, -3..-1") {
+            Ok(actual) => assert_eq!(actual, expected),
+            Err(_) => assert!(false),
+        }
+    }
+
+    #[test]
+    fn parse_index_filters_negated() {
+        let expected = super::IndexFilters {
+            negate: true,
+            rules: vec![super::IndexFilter::Exact(2usize), super::IndexFilter::Exact(4usize)],
+        };
+        match super::parse_index_filters("!2,4") {
+            Ok(actual) => assert_eq!(actual, expected),
+            Err(_) => assert!(false),
+        }
+    }
+
+    #[test]
+    fn index_filter_resolve() {
+        assert_eq!(super::IndexFilter::FromEnd(1).resolve(5), super::IndexFilter::Exact(4));
+        assert_eq!(
+            super::IndexFilter::BoundedFromEnd(3, 1).resolve(5),
+            super::IndexFilter::Bounded(2, 4)
+        );
+    }
+
+    #[test]
+    fn parse_regex_transform() {
+        match super::parse_regex_transform(r"(\w+)@(\w+)", "$2:$1", false, false) {
+            Ok(transform) => {
+                assert_eq!(transform.regex.replace(b"user@host", transform.template.as_slice()), &b"host:user"[..]);
+            }
+            Err(_) => assert!(false),
+        }
+    }
 }
@@ -0,0 +1,55 @@
+/// Built-in format presets, analogous to ripgrep's file type definitions: a short name that
+/// expands into the line/row separators for a common tabular format in one shot, so users don't
+/// have to re-type the same escape sequences for the common cases.
+///
+/// Kept as its own table, sorted lexicographically by name, so it stays easy to scan and extend.
+use crate::parsers::{self, FieldSeparator};
+
+const PRESETS: &[(&str, &[&str], &[&str])] = &[
+    ("csv", &["\\n"], &[","]),
+    ("lines", &["\\n"], &[]),
+    ("ssv", &["\\n"], &["\\s+"]),
+    ("tsv", &["\\n"], &["\\t"]),
+];
+
+fn field_separator(string_representations: &[&str]) -> FieldSeparator {
+    let owned: Vec<String> = string_representations.iter().map(|s| s.to_string()).collect();
+    parsers::parse_field_separators(&owned).expect("built-in presets must always parse")
+}
+
+/// Looks up a named format preset, returning its `(line separator, row separator)` pair, or
+/// `None` if `name` isn't a known preset.
+pub fn preset(name: &str) -> Option<(FieldSeparator, FieldSeparator)> {
+    PRESETS
+        .iter()
+        .find(|(preset_name, _, _)| *preset_name == name)
+        .map(|(_, line_separators, row_separators)| (field_separator(line_separators), field_separator(row_separators)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parsers::FieldSeparator;
+
+    #[test]
+    fn preset_csv() {
+        match super::preset("csv") {
+            Some((FieldSeparator::Literal(_), FieldSeparator::Literal(_))) => (),
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn preset_ssv_is_a_pattern() {
+        match super::preset("ssv") {
+            Some((FieldSeparator::Literal(_), FieldSeparator::Pattern(regex))) => {
+                assert!(regex.is_match(b"   "));
+            }
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn preset_unknown() {
+        assert!(super::preset("json").is_none());
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use ulid::Ulid;
+
+/// Per-client token-bucket rate limiter guarding how many `Run`/`SetLine*`/`SetRow*` commands a
+/// client can issue per window, so a runaway frontend firing a filter update on every keystroke
+/// can't overwhelm the `CommandExecutor`.
+///
+/// Held in `web::Data` and shared across all workers, so the bucket map sits behind a `Mutex`
+/// rather than actor-isolated state the way `CommandExecutor`'s per-client data is.
+pub struct RateLimiter {
+    capacity: f64,
+    rate_per_sec: f64,
+    buckets: Mutex<HashMap<Ulid, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// A client is over its rate limit; `retry_after_secs` is how long until it has another full
+/// token, rounded up so a client that waits at least this long is guaranteed to succeed.
+pub struct RateLimited {
+    pub retry_after_secs: u64,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: f64, rate_per_sec: f64) -> Self {
+        RateLimiter {
+            capacity,
+            rate_per_sec,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads `VAWK_RATE_LIMIT_CAPACITY`/`VAWK_RATE_LIMIT_PER_SEC`, falling back to a bucket of 20
+    /// tokens refilling at 2/sec if either is unset or unparseable.
+    pub fn from_env() -> Self {
+        let capacity = std::env::var("VAWK_RATE_LIMIT_CAPACITY").ok().and_then(|value| value.parse().ok()).unwrap_or(20.0);
+        let rate_per_sec = std::env::var("VAWK_RATE_LIMIT_PER_SEC").ok().and_then(|value| value.parse().ok()).unwrap_or(2.0);
+        Self::new(capacity, rate_per_sec)
+    }
+
+    /// Refills `client_id`'s bucket for the time elapsed since it was last touched, then takes
+    /// one token if one is available. A client seen for the first time starts at full capacity,
+    /// so an initial burst up to `capacity` is always allowed.
+    pub fn try_acquire(&self, client_id: Ulid) -> Result<(), RateLimited> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+        let bucket = buckets.entry(client_id).or_insert_with(|| Bucket {
+            tokens: self.capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate_per_sec).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let deficit = 1.0 - bucket.tokens;
+            Err(RateLimited {
+                retry_after_secs: (deficit / self.rate_per_sec).ceil() as u64,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RateLimiter;
+    use ulid::Ulid;
+
+    #[test]
+    fn try_acquire_allows_a_burst_up_to_capacity() {
+        let limiter = RateLimiter::new(3.0, 1.0);
+        let client_id = Ulid::new();
+        assert!(limiter.try_acquire(client_id).is_ok());
+        assert!(limiter.try_acquire(client_id).is_ok());
+        assert!(limiter.try_acquire(client_id).is_ok());
+        assert!(limiter.try_acquire(client_id).is_err());
+    }
+
+    #[test]
+    fn try_acquire_tracks_clients_independently() {
+        let limiter = RateLimiter::new(1.0, 1.0);
+        let a = Ulid::new();
+        let b = Ulid::new();
+        assert!(limiter.try_acquire(a).is_ok());
+        assert!(limiter.try_acquire(a).is_err());
+        assert!(limiter.try_acquire(b).is_ok());
+    }
+}
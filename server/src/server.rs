@@ -1,76 +1,181 @@
 use actix::prelude::Addr;
 use actix_web;
+use actix_web::error::BlockingError;
 use actix_web::HttpResponse;
 use actix_web::web;
-use crate::command_executor::{self, Cancel, CommandExecutor, Connect, Listen, Run};
+use crate::command_executor::{self, Cancel, CommandExecutor, Connect, Listen, ResizePty, Run, SendInput};
+use crate::cors::CorsConfig;
 use crate::parsers;
+use crate::rate_limit::{RateLimited, RateLimiter};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
-pub async fn listen(executor: web::Data<Addr<CommandExecutor>>, web::Query(listen_msg): web::Query<Listen>) -> HttpResponse {
+/// Applies the `Access-Control-Allow-Origin` header for `req`'s `Origin` (if it's allowed) to a
+/// response builder, so every handler below agrees on CORS instead of each hardcoding its own
+/// header (or, as `listen` used to, none at all).
+fn with_cors(mut builder: actix_web::HttpResponseBuilder, cors: &CorsConfig, req: &actix_web::HttpRequest) -> actix_web::HttpResponseBuilder {
+    if let Some(origin) = cors.allow_origin(req.headers().get("Origin").and_then(|value| value.to_str().ok())) {
+        builder.header("Access-Control-Allow-Origin", origin);
+    }
+    builder
+}
+
+/// A 429 distinguishable from the mailbox-error 503s below: this means the client itself is over
+/// its per-window command budget, not that the `CommandExecutor` is unreachable.
+fn rate_limited(cors: &CorsConfig, req: &actix_web::HttpRequest, limited: RateLimited) -> HttpResponse {
+    with_cors(actix_web::HttpResponse::TooManyRequests(), cors, req)
+        .header("Retry-After", limited.retry_after_secs.to_string())
+        .finish()
+}
+
+/// Runs `parse(&input)` on the blocking threadpool via `web::block`, since regex compilation can
+/// be slow enough on a pathological pattern to stall the actix worker thread otherwise. A `None`
+/// input (the "clear this filter" case) is returned as-is without spawning any blocking work.
+/// `Err(())` means the blocking task itself panicked or was canceled, which the caller should turn
+/// into a `500` distinct from `Ok(Some(Err(_)))`'s ordinary `400` parse error.
+async fn parse_blocking<I, T, E, F>(input: Option<I>, parse: F) -> Result<Option<Result<T, E>>, ()>
+where
+    I: Send + 'static,
+    T: Send + 'static,
+    E: Send + std::fmt::Debug + 'static,
+    F: FnOnce(&I) -> Result<T, E> + Send + 'static,
+{
+    match input {
+        None => Ok(None),
+        Some(input) => match web::block(move || parse(&input)).await {
+            Ok(parsed) => Ok(Some(Ok(parsed))),
+            Err(BlockingError::Error(error)) => Ok(Some(Err(error))),
+            Err(BlockingError::Canceled) => Err(()),
+        },
+    }
+}
+
+pub async fn listen(
+    req: actix_web::HttpRequest,
+    executor: web::Data<Addr<CommandExecutor>>,
+    cors: web::Data<CorsConfig>,
+    web::Query(mut listen_msg): web::Query<Listen>,
+) -> HttpResponse {
+    // Browsers' EventSource sends this automatically on reconnect; it's how a dropped connection
+    // resumes the job it was already watching instead of re-streaming it from chunk 0. A
+    // `last_event_id` query param covers clients that manage their own reconnects and can't set
+    // headers as easily as the header-based mechanism.
+    listen_msg.last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_owned())
+        .or_else(|| listen_msg.last_event_id_query.clone());
+
     match executor.send(listen_msg).await {
         Ok(Ok(connection)) => {
-            actix_web::HttpResponse::Ok()
+            with_cors(actix_web::HttpResponse::Ok(), &cors, &req)
                 .header("Content-Type", "text/event-stream")
-                .header("Access-Control-Allow-Origin", "http://localhost:3000")
                 .streaming(connection)
         },
-        Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-        Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+        Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+        Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
     }
 }
 
-pub async fn connect(executor: web::Data<Addr<CommandExecutor>>) -> HttpResponse {
+pub async fn connect(req: actix_web::HttpRequest, executor: web::Data<Addr<CommandExecutor>>, cors: web::Data<CorsConfig>) -> HttpResponse {
     match executor.send(Connect {}).await {
-        Ok(response) => actix_web::HttpResponse::Ok().json(response),
-        Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+        Ok(response) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).json(response),
+        Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
     }
 }
 
-pub async fn run(executor: web::Data<Addr<CommandExecutor>>, web::Json(run): web::Json<Run>) -> HttpResponse {
+pub async fn run(
+    req: actix_web::HttpRequest,
+    executor: web::Data<Addr<CommandExecutor>>,
+    cors: web::Data<CorsConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+    web::Json(run): web::Json<Run>,
+) -> HttpResponse {
+    if let Err(limited) = rate_limiter.try_acquire(run.client_id) {
+        return rate_limited(&cors, &req, limited);
+    }
+
     match executor.send(run).await {
-        Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-        Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-        Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+        Ok(Ok(id)) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).json(RunResponse { id }),
+        Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+        Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
     }
 }
 
-pub async fn cancel(executor: web::Data<Addr<CommandExecutor>>, web::Json(cancel): web::Json<Cancel>) -> HttpResponse {
+/// The `id` a `run` call was registered under - the client needs it back to target this specific
+/// command with a later `Cancel`, `SendInput`/`ResizePty`, or filter setter, since a client may
+/// have several commands running at once.
+#[derive(Serialize)]
+pub struct RunResponse {
+    pub id: usize,
+}
+
+pub async fn cancel(req: actix_web::HttpRequest, executor: web::Data<Addr<CommandExecutor>>, cors: web::Data<CorsConfig>, web::Json(cancel): web::Json<Cancel>) -> HttpResponse {
     match executor.send(cancel).await {
-        Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-        Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-        Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+        Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+        Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+        Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
+    }
+}
+
+pub async fn send_input(req: actix_web::HttpRequest, executor: web::Data<Addr<CommandExecutor>>, cors: web::Data<CorsConfig>, web::Json(send_input): web::Json<SendInput>) -> HttpResponse {
+    match executor.send(send_input).await {
+        Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+        Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+        Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
+    }
+}
+
+pub async fn resize_pty(req: actix_web::HttpRequest, executor: web::Data<Addr<CommandExecutor>>, cors: web::Data<CorsConfig>, web::Json(resize_pty): web::Json<ResizePty>) -> HttpResponse {
+    match executor.send(resize_pty).await {
+        Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+        Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+        Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
     }
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct SetLineIndexFilters {
     pub client_id: Ulid,
+    pub id: usize,
     pub filters: Option<String>,
 }
 
-pub async fn set_line_index_filters(executor: web::Data<Addr<CommandExecutor>>, web::Json(set_line_index_filters): web::Json<SetLineIndexFilters>) -> HttpResponse {
-    match set_line_index_filters.filters.map(|filters| parsers::parse_index_filters(&filters)) {
-        // TODO: Give more of an update to the user here about what went wrong.
-        Some(Err(_error)) => actix_web::HttpResponse::BadRequest().finish(),
-        Some(Ok(index_filters)) => {
+pub async fn set_line_index_filters(
+    req: actix_web::HttpRequest,
+    executor: web::Data<Addr<CommandExecutor>>,
+    cors: web::Data<CorsConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+    web::Json(set_line_index_filters): web::Json<SetLineIndexFilters>,
+) -> HttpResponse {
+    if let Err(limited) = rate_limiter.try_acquire(set_line_index_filters.client_id) {
+        return rate_limited(&cors, &req, limited);
+    }
+
+    match parse_blocking(set_line_index_filters.filters, |filters| parsers::parse_index_filters(filters)).await {
+        Err(()) => with_cors(actix_web::HttpResponse::InternalServerError(), &cors, &req).finish(),
+        Ok(Some(Err(error))) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).json(error.into_response_body("filters")),
+        Ok(Some(Ok(index_filters))) => {
             match executor.send(command_executor::SetLineIndexFilters {
                 client_id: set_line_index_filters.client_id,
+                id: set_line_index_filters.id,
                 filters: Some(index_filters),
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         },
-        None => {
+        Ok(None) => {
             match executor.send(command_executor::SetLineIndexFilters {
                 client_id: set_line_index_filters.client_id,
+                id: set_line_index_filters.id,
                 filters: None,
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         }
     }
@@ -79,31 +184,44 @@ pub async fn set_line_index_filters(executor: web::Data<Addr<CommandExecutor>>,
 #[derive(Deserialize, Serialize)]
 pub struct SetLineRegexFilter {
     pub client_id: Ulid,
+    pub id: usize,
     pub filter: Option<String>,
 }
 
-pub async fn set_line_regex_filter(executor: web::Data<Addr<CommandExecutor>>, web::Json(set_line_regex_filter): web::Json<SetLineRegexFilter>) -> HttpResponse {
-    match set_line_regex_filter.filter.map(|filter| parsers::parse_regex_filter(&filter)) {
-        // TODO: Give more of an update to the user here about what went wrong.
-        Some(Err(_error)) => actix_web::HttpResponse::BadRequest().finish(),
-        Some(Ok(regex_filter)) => {
+pub async fn set_line_regex_filter(
+    req: actix_web::HttpRequest,
+    executor: web::Data<Addr<CommandExecutor>>,
+    cors: web::Data<CorsConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+    web::Json(set_line_regex_filter): web::Json<SetLineRegexFilter>,
+) -> HttpResponse {
+    if let Err(limited) = rate_limiter.try_acquire(set_line_regex_filter.client_id) {
+        return rate_limited(&cors, &req, limited);
+    }
+
+    match parse_blocking(set_line_regex_filter.filter, |filter| parsers::parse_regex_filter(filter)).await {
+        Err(()) => with_cors(actix_web::HttpResponse::InternalServerError(), &cors, &req).finish(),
+        Ok(Some(Err(error))) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).json(error.into_response_body("filter")),
+        Ok(Some(Ok(regex_filter))) => {
             match executor.send(command_executor::SetLineRegexFilter {
                 client_id: set_line_regex_filter.client_id,
+                id: set_line_regex_filter.id,
                 filter: Some(regex_filter),
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         },
-        None => {
+        Ok(None) => {
             match executor.send(command_executor::SetLineRegexFilter {
                 client_id: set_line_regex_filter.client_id,
+                id: set_line_regex_filter.id,
                 filter: None,
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         }
     }
@@ -112,31 +230,44 @@ pub async fn set_line_regex_filter(executor: web::Data<Addr<CommandExecutor>>, w
 #[derive(Deserialize, Serialize)]
 pub struct SetLineSeparators {
     pub client_id: Ulid,
+    pub id: usize,
     pub separators: Option<Vec<String>>,
 }
 
-pub async fn set_line_separators(executor: web::Data<Addr<CommandExecutor>>, web::Json(set_line_separators): web::Json<SetLineSeparators>) -> HttpResponse {
-    match set_line_separators.separators.map(|separators| parsers::parse_field_separators(&separators)) {
-        // TODO: Give more of an update to the user here about what went wrong.
-        Some(Err(_error)) => actix_web::HttpResponse::BadRequest().finish(),
-        Some(Ok(separators)) => {
+pub async fn set_line_separators(
+    req: actix_web::HttpRequest,
+    executor: web::Data<Addr<CommandExecutor>>,
+    cors: web::Data<CorsConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+    web::Json(set_line_separators): web::Json<SetLineSeparators>,
+) -> HttpResponse {
+    if let Err(limited) = rate_limiter.try_acquire(set_line_separators.client_id) {
+        return rate_limited(&cors, &req, limited);
+    }
+
+    match parse_blocking(set_line_separators.separators, |separators| parsers::parse_field_separators(separators)).await {
+        Err(()) => with_cors(actix_web::HttpResponse::InternalServerError(), &cors, &req).finish(),
+        Ok(Some(Err(error))) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).json(error.into_response_body("separators")),
+        Ok(Some(Ok(separators))) => {
             match executor.send(command_executor::SetLineSeparators {
                 client_id: set_line_separators.client_id,
+                id: set_line_separators.id,
                 separators: Some(separators),
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         },
-        None => {
+        Ok(None) => {
             match executor.send(command_executor::SetLineSeparators {
                 client_id: set_line_separators.client_id,
+                id: set_line_separators.id,
                 separators: None,
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         }
     }
@@ -145,31 +276,44 @@ pub async fn set_line_separators(executor: web::Data<Addr<CommandExecutor>>, web
 #[derive(Deserialize, Serialize)]
 pub struct SetRowIndexFilters {
     pub client_id: Ulid,
+    pub id: usize,
     pub filters: Option<String>,
 }
 
-pub async fn set_row_index_filters(executor: web::Data<Addr<CommandExecutor>>, web::Json(set_row_index_filters): web::Json<SetRowIndexFilters>) -> HttpResponse {
-    match set_row_index_filters.filters.map(|filters| parsers::parse_index_filters(&filters)) {
-        // TODO: Give more of an update to the user here about what went wrong.
-        Some(Err(_error)) => actix_web::HttpResponse::BadRequest().finish(),
-        Some(Ok(index_filters)) => {
+pub async fn set_row_index_filters(
+    req: actix_web::HttpRequest,
+    executor: web::Data<Addr<CommandExecutor>>,
+    cors: web::Data<CorsConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+    web::Json(set_row_index_filters): web::Json<SetRowIndexFilters>,
+) -> HttpResponse {
+    if let Err(limited) = rate_limiter.try_acquire(set_row_index_filters.client_id) {
+        return rate_limited(&cors, &req, limited);
+    }
+
+    match parse_blocking(set_row_index_filters.filters, |filters| parsers::parse_index_filters(filters)).await {
+        Err(()) => with_cors(actix_web::HttpResponse::InternalServerError(), &cors, &req).finish(),
+        Ok(Some(Err(error))) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).json(error.into_response_body("filters")),
+        Ok(Some(Ok(index_filters))) => {
             match executor.send(command_executor::SetRowIndexFilters {
                 client_id: set_row_index_filters.client_id,
+                id: set_row_index_filters.id,
                 filters: Some(index_filters),
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         },
-        None => {
+        Ok(None) => {
             match executor.send(command_executor::SetRowIndexFilters {
                 client_id: set_row_index_filters.client_id,
+                id: set_row_index_filters.id,
                 filters: None,
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         }
     }
@@ -178,31 +322,44 @@ pub async fn set_row_index_filters(executor: web::Data<Addr<CommandExecutor>>, w
 #[derive(Deserialize, Serialize)]
 pub struct SetRowRegexFilter {
     pub client_id: Ulid,
+    pub id: usize,
     pub filter: Option<String>,
 }
 
-pub async fn set_row_regex_filter(executor: web::Data<Addr<CommandExecutor>>, web::Json(set_row_regex_filter): web::Json<SetRowRegexFilter>) -> HttpResponse {
-    match set_row_regex_filter.filter.map(|filter| parsers::parse_regex_filter(&filter)) {
-        // TODO: Give more of an update to the user here about what went wrong.
-        Some(Err(_error)) => actix_web::HttpResponse::BadRequest().finish(),
-        Some(Ok(regex_filter)) => {
+pub async fn set_row_regex_filter(
+    req: actix_web::HttpRequest,
+    executor: web::Data<Addr<CommandExecutor>>,
+    cors: web::Data<CorsConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+    web::Json(set_row_regex_filter): web::Json<SetRowRegexFilter>,
+) -> HttpResponse {
+    if let Err(limited) = rate_limiter.try_acquire(set_row_regex_filter.client_id) {
+        return rate_limited(&cors, &req, limited);
+    }
+
+    match parse_blocking(set_row_regex_filter.filter, |filter| parsers::parse_regex_filter(filter)).await {
+        Err(()) => with_cors(actix_web::HttpResponse::InternalServerError(), &cors, &req).finish(),
+        Ok(Some(Err(error))) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).json(error.into_response_body("filter")),
+        Ok(Some(Ok(regex_filter))) => {
             match executor.send(command_executor::SetRowRegexFilter {
                 client_id: set_row_regex_filter.client_id,
+                id: set_row_regex_filter.id,
                 filter: Some(regex_filter),
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         },
-        None => {
+        Ok(None) => {
             match executor.send(command_executor::SetRowRegexFilter {
                 client_id: set_row_regex_filter.client_id,
+                id: set_row_regex_filter.id,
                 filter: None,
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         }
     }
@@ -211,32 +368,141 @@ pub async fn set_row_regex_filter(executor: web::Data<Addr<CommandExecutor>>, we
 #[derive(Deserialize, Serialize)]
 pub struct SetRowSeparators {
     pub client_id: Ulid,
+    pub id: usize,
     pub separators: Option<Vec<String>>,
 }
 
-pub async fn set_row_separators(executor: web::Data<Addr<CommandExecutor>>, web::Json(set_row_separators): web::Json<SetRowSeparators>) -> HttpResponse {
-    match set_row_separators.separators.map(|separators| parsers::parse_field_separators(&separators)) {
-        // TODO: Give more of an update to the user here about what went wrong.
-        Some(Err(_error)) => actix_web::HttpResponse::BadRequest().finish(),
-        Some(Ok(separators)) => {
+pub async fn set_row_separators(
+    req: actix_web::HttpRequest,
+    executor: web::Data<Addr<CommandExecutor>>,
+    cors: web::Data<CorsConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+    web::Json(set_row_separators): web::Json<SetRowSeparators>,
+) -> HttpResponse {
+    if let Err(limited) = rate_limiter.try_acquire(set_row_separators.client_id) {
+        return rate_limited(&cors, &req, limited);
+    }
+
+    match parse_blocking(set_row_separators.separators, |separators| parsers::parse_field_separators(separators)).await {
+        Err(()) => with_cors(actix_web::HttpResponse::InternalServerError(), &cors, &req).finish(),
+        Ok(Some(Err(error))) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).json(error.into_response_body("separators")),
+        Ok(Some(Ok(separators))) => {
             match executor.send(command_executor::SetRowSeparators {
                 client_id: set_row_separators.client_id,
+                id: set_row_separators.id,
                 separators: Some(separators),
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         },
-        None => {
+        Ok(None) => {
             match executor.send(command_executor::SetRowSeparators {
                 client_id: set_row_separators.client_id,
+                id: set_row_separators.id,
                 separators: None,
             }).await {
-                Ok(Ok(())) => actix_web::HttpResponse::Ok().finish(),
-                Ok(Err(_)) => actix_web::HttpResponse::BadRequest().finish(),
-                Err(_) => actix_web::HttpResponse::TooManyRequests().finish(),
+                Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+                Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+                Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
             }
         }
     }
 }
+
+#[derive(Deserialize, Serialize)]
+pub struct ApplySettings {
+    pub client_id: Ulid,
+    pub id: usize,
+    pub line_separators: Option<Vec<String>>,
+    pub line_index_filters: Option<String>,
+    pub line_regex: Option<String>,
+    pub row_separators: Option<Vec<String>>,
+    pub row_index_filters: Option<String>,
+    pub row_regex: Option<String>,
+}
+
+/// Parses every provided field of an `ApplySettings` body and sends a single
+/// `command_executor::ApplySettings` message, instead of the caller firing `set_line_separators`,
+/// `set_line_index_filters`, etc. one at a time - each of which rebuilds and reruns the transform
+/// pipeline on its own, flickering intermediate output when several settings change together.
+/// All fields are parsed before anything is rejected or sent, so a request with one bad field
+/// reports every bad field at once rather than just the first one this function happens to check.
+pub async fn apply_settings(
+    req: actix_web::HttpRequest,
+    executor: web::Data<Addr<CommandExecutor>>,
+    cors: web::Data<CorsConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+    web::Json(apply_settings): web::Json<ApplySettings>,
+) -> HttpResponse {
+    if let Err(limited) = rate_limiter.try_acquire(apply_settings.client_id) {
+        return rate_limited(&cors, &req, limited);
+    }
+
+    let line_separators = parse_blocking(apply_settings.line_separators, |separators| parsers::parse_field_separators(separators)).await;
+    let line_index_filters = parse_blocking(apply_settings.line_index_filters, |filters| parsers::parse_index_filters(filters)).await;
+    let line_regex = parse_blocking(apply_settings.line_regex, |filter| parsers::parse_regex_filter(filter)).await;
+    let row_separators = parse_blocking(apply_settings.row_separators, |separators| parsers::parse_field_separators(separators)).await;
+    let row_index_filters = parse_blocking(apply_settings.row_index_filters, |filters| parsers::parse_index_filters(filters)).await;
+    let row_regex = parse_blocking(apply_settings.row_regex, |filter| parsers::parse_regex_filter(filter)).await;
+
+    if [&line_separators, &line_index_filters, &line_regex, &row_separators, &row_index_filters, &row_regex]
+        .iter()
+        .any(|result| matches!(result, Err(())))
+    {
+        return with_cors(actix_web::HttpResponse::InternalServerError(), &cors, &req).finish();
+    }
+
+    let mut errors = Vec::new();
+
+    let line_separators = match line_separators {
+        Ok(Some(Err(error))) => { errors.push(error.into_response_body("line_separators")); None },
+        Ok(Some(Ok(value))) => Some(value),
+        _ => None,
+    };
+    let line_index_filters = match line_index_filters {
+        Ok(Some(Err(error))) => { errors.push(error.into_response_body("line_index_filters")); None },
+        Ok(Some(Ok(value))) => Some(value),
+        _ => None,
+    };
+    let line_regex = match line_regex {
+        Ok(Some(Err(error))) => { errors.push(error.into_response_body("line_regex")); None },
+        Ok(Some(Ok(value))) => Some(value),
+        _ => None,
+    };
+    let row_separators = match row_separators {
+        Ok(Some(Err(error))) => { errors.push(error.into_response_body("row_separators")); None },
+        Ok(Some(Ok(value))) => Some(value),
+        _ => None,
+    };
+    let row_index_filters = match row_index_filters {
+        Ok(Some(Err(error))) => { errors.push(error.into_response_body("row_index_filters")); None },
+        Ok(Some(Ok(value))) => Some(value),
+        _ => None,
+    };
+    let row_regex = match row_regex {
+        Ok(Some(Err(error))) => { errors.push(error.into_response_body("row_regex")); None },
+        Ok(Some(Ok(value))) => Some(value),
+        _ => None,
+    };
+
+    if !errors.is_empty() {
+        return with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).json(errors);
+    }
+
+    match executor.send(command_executor::ApplySettings {
+        client_id: apply_settings.client_id,
+        id: apply_settings.id,
+        line_separators,
+        line_index_filters,
+        line_regex,
+        row_separators,
+        row_index_filters,
+        row_regex,
+    }).await {
+        Ok(Ok(())) => with_cors(actix_web::HttpResponse::Ok(), &cors, &req).finish(),
+        Ok(Err(_)) => with_cors(actix_web::HttpResponse::BadRequest(), &cors, &req).finish(),
+        Err(_) => with_cors(actix_web::HttpResponse::ServiceUnavailable(), &cors, &req).finish(),
+    }
+}
@@ -1,13 +1,15 @@
 use crate::byte_trie::{ByteTrie, Membership};
-use crate::parsers::IndexFilter;
+use crate::parsers::{FieldSeparator, IndexFilters, RegexTransform};
 use csv;
+use rayon::prelude::*;
 use regex::bytes::Regex;
-use std::io;
+use std::io::{self, BufRead, Write};
 
 pub struct Options {
-    pub separators: Option<ByteTrie>,
+    pub separators: Option<FieldSeparator>,
     pub regex_filter: Option<Regex>,
-    pub index_filters: Option<Vec<IndexFilter>>,
+    pub index_filters: Option<IndexFilters>,
+    pub regex_transform: Option<RegexTransform>,
 }
 
 impl Options {
@@ -16,6 +18,7 @@ impl Options {
             separators: None,
             regex_filter: None,
             index_filters: None,
+            regex_transform: None,
         }
     }
 }
@@ -58,16 +61,45 @@ fn split(separators: &ByteTrie, data: &Vec<u8>) -> Vec<Vec<u8>> {
     result
 }
 
+/// Splits data on a regex, walking its matches and emitting the gaps between them. Mirrors
+/// `split`'s behavior of never emitting an empty field (so runs of separators collapse, and a
+/// match at the very start doesn't produce a leading empty field), and treats a zero-width match
+/// as a non-split rather than a boundary.
+fn split_pattern(pattern: &Regex, data: &Vec<u8>) -> Vec<Vec<u8>> {
+    let mut result = vec![];
+    let mut position = 0;
+
+    for mat in pattern.find_iter(data) {
+        if mat.start() == mat.end() {
+            continue;
+        }
+
+        if mat.start() > position {
+            result.push(data[position..mat.start()].to_vec());
+        }
+
+        position = mat.end();
+    }
+
+    if position < data.len() {
+        result.push(data[position..].to_vec());
+    }
+
+    result
+}
+
 /// Parse the rules for indexes, then keep only entries in the data that match the rules given for indexes.
 ///
 /// This function is a bit atypical in that the rules_str argument is expected to be user input, and has purposefully relaxed parsing logic.
 /// It also returns data even in the error case, so that the user still gets some feedback even with invalid input.
 /// This is **not** a goal of the rest of the code, in general failing fast is preferred unless there is a strong tie to user input.
-fn keep_index_matches(rules: &Vec<IndexFilter>, data: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+fn keep_index_matches(filters: &IndexFilters, data: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let len = data.len();
+    let resolved: Vec<_> = filters.rules.iter().map(|rule| rule.resolve(len)).collect();
     let mut result = vec![];
 
-    for i in 0..data.len() {
-        if rules.iter().any(|rule| rule.is_match(i)) {
+    for i in 0..len {
+        if resolved.iter().any(|rule| rule.is_match(i)) != filters.negate {
             result.push(data[i].clone());
         }
     }
@@ -82,10 +114,31 @@ fn keep_regex_matches(regex: &Regex, data: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
         .collect()
 }
 
+/// Rewrites every field matching `transform.regex` using its replacement template, and drops
+/// non-matching fields if `transform.keep_only_matches` is set.
+fn transform_regex_matches(transform: &RegexTransform, data: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    data.into_iter()
+        .filter_map(|field| {
+            if !transform.regex.is_match(field.as_slice()) {
+                return if transform.keep_only_matches { None } else { Some(field) };
+            }
+
+            let replaced = if transform.global {
+                transform.regex.replace_all(field.as_slice(), transform.template.as_slice())
+            } else {
+                transform.regex.replace(field.as_slice(), transform.template.as_slice())
+            };
+
+            Some(replaced.into_owned())
+        })
+        .collect()
+}
+
 fn split_into_records(options: &Options, data: &Vec<u8>) -> Vec<Vec<u8>> {
-    let mut result = match options.separators {
+    let mut result = match &options.separators {
         None => vec![data.clone()],
-        Some(ref separators) => split(separators, data),
+        Some(FieldSeparator::Literal(separators)) => split(separators, data),
+        Some(FieldSeparator::Pattern(pattern)) => split_pattern(pattern, data),
     };
 
     if let Some(ref index_filters) = options.index_filters {
@@ -96,14 +149,77 @@ fn split_into_records(options: &Options, data: &Vec<u8>) -> Vec<Vec<u8>> {
         result = keep_regex_matches(regex_filter, result);
     }
 
+    if let Some(ref regex_transform) = options.regex_transform {
+        result = transform_regex_matches(regex_transform, result);
+    }
+
     result
 }
 
+/// Scans `data` the same way `split` does, but instead of returning the split fields it returns
+/// the byte length of the longest leading prefix that ends exactly on a complete record boundary.
+/// Incremental streaming transforms and emits only that prefix each tick, retaining the trailing
+/// partial record for the next read. With no separator configured (or a regex separator, which
+/// can't be scanned incrementally a byte at a time) nothing is considered complete until EOF, so
+/// this always returns 0.
+pub fn complete_prefix_len(options: &Options, data: &[u8]) -> usize {
+    let separators = match &options.separators {
+        Some(FieldSeparator::Literal(separators)) => separators,
+        _ => return 0,
+    };
+
+    let mut boundary = 0;
+    let mut current_line_len = 0usize;
+    let mut current_separator = vec![];
+
+    for (index, byte) in data.iter().enumerate() {
+        current_separator.push(*byte);
+        match separators.membership(current_separator.as_slice()) {
+            Membership::NotIncluded => {
+                current_line_len += 1;
+                current_separator.clear();
+            }
+            Membership::Included if current_line_len > 0 => {
+                boundary = index + 1;
+                current_line_len = 0;
+            }
+            Membership::Included => (),
+            Membership::IncludedAndTerminal if current_line_len > 0 => {
+                boundary = index + 1;
+                current_line_len = 0;
+                current_separator.clear();
+            }
+            Membership::IncludedAndTerminal => {
+                current_separator.clear();
+            }
+        }
+    }
+
+    boundary
+}
+
+/// Splits `data` into lines via `line_options`, then splits each line into fields via
+/// `row_options` - the 2-D grid of cells `encoding::stdout_chunks` serializes as a JSON array of
+/// rows. Named for the two axes it runs the split/filter/transform pipeline along, one after the
+/// other.
+pub fn transform_2d(line_options: &Options, row_options: &Options, data: &Vec<u8>) -> Vec<Vec<Vec<u8>>> {
+    split_into_records(line_options, data)
+        .iter()
+        .map(|line| split_into_records(row_options, line))
+        .collect()
+}
+
 pub fn transform_output(column_options: &Options, row_options: &Options, data: &Vec<u8>) -> io::Result<Vec<u8>> {
     let mut inner = vec![];
     { // Scope so that inner does not get dropped when the writer does
         let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(&mut inner);
-        let rows: Vec<Vec<Vec<u8>>> = split_into_records(column_options, data).iter_mut().map(|row_data| split_into_records(row_options, row_data)).collect();
+        // Every line is independent, so the row-level split/filter/transform pipeline runs
+        // across rayon's thread pool; `par_iter` on a `Vec` preserves the original order on
+        // `collect`, so output rows still line up with input lines.
+        let rows: Vec<Vec<Vec<u8>>> = split_into_records(column_options, data)
+            .par_iter()
+            .map(|row_data| split_into_records(row_options, row_data))
+            .collect();
         let mut longest_number_of_cells = 0;
 
         for row in &rows {
@@ -128,9 +244,42 @@ pub fn transform_output(column_options: &Options, row_options: &Options, data: &
     Ok(inner)
 }
 
+/// Like `transform_output`, but reads `reader` one line at a time and writes each row to
+/// `writer` as soon as it's split, instead of buffering the whole input and every output row in
+/// memory first. Peak memory is bounded by the largest single line rather than the whole input.
+///
+/// Unlike `transform_output`, this can't honor `column_options.separators` (a `BufRead` can only
+/// split incrementally on a single byte), so lines are always split on `\n`; `row_options` still
+/// applies in full. Output rows also aren't padded to a common width, since that requires
+/// knowing every row's length up front.
+pub fn transform_output_streaming<R: BufRead, W: Write>(
+    row_options: &Options,
+    mut reader: R,
+    writer: W,
+) -> io::Result<()> {
+    let mut csv_writer = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+    let mut line = vec![];
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+
+        csv_writer.write_record(split_into_records(row_options, &line))?;
+    }
+
+    csv_writer.flush()
+}
+
 #[cfg(test)]
 mod test {
     use crate::byte_trie::ByteTrie;
+    use crate::parsers::{IndexFilter, IndexFilters, RegexTransform};
     use regex::bytes::Regex;
 
     fn bytes_vec(data: Vec<&str>) -> Vec<Vec<u8>> {
@@ -150,6 +299,26 @@ mod test {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn split_pattern() {
+        // Runs of whitespace collapse into a single separator, and a match at the very start
+        // doesn't produce a leading empty field.
+        let pattern = Regex::new(r"\s+").unwrap();
+        let expected: Vec<Vec<u8>> = bytes_vec(vec!["hi", "there", "this", "could", "be", "csv"]);
+        let actual = super::split_pattern(&pattern, &"  hi there   this\ncould be\tcsv".bytes().collect());
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn split_pattern_zero_width_match() {
+        // A zero-width match (e.g. a word boundary) is treated as a non-split rather than a
+        // boundary, so it never produces an infinite loop or a spurious split.
+        let pattern = Regex::new(r"\b").unwrap();
+        let expected: Vec<Vec<u8>> = bytes_vec(vec!["hithere"]);
+        let actual = super::split_pattern(&pattern, &"hithere".bytes().collect());
+        assert_eq!(actual, expected);
+    }
+
     #[test]
     fn keep_index_matches() {
         // The rule "1, 5.." keeps indexes 1, 5, 6, 7, 8.
@@ -158,10 +327,43 @@ mod test {
         ]);
         let expected: Vec<Vec<u8>> = bytes_vec(vec!["quick", "over", "the", "lazy", "dog"]);
         let actual = super::keep_index_matches(
-            &vec![
-                super::IndexFilter::Exact(1usize),
-                super::IndexFilter::LowerBounded(5usize),
-            ],
+            &IndexFilters {
+                negate: false,
+                rules: vec![
+                    IndexFilter::Exact(1usize),
+                    IndexFilter::LowerBounded(5usize),
+                ],
+            },
+            data,
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn keep_index_matches_from_end() {
+        // "-1" keeps only the last entry.
+        let data: Vec<Vec<u8>> = bytes_vec(vec!["The", "quick", "brown", "fox"]);
+        let expected: Vec<Vec<u8>> = bytes_vec(vec!["fox"]);
+        let actual = super::keep_index_matches(
+            &IndexFilters {
+                negate: false,
+                rules: vec![IndexFilter::FromEnd(1usize)],
+            },
+            data,
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn keep_index_matches_negated() {
+        // "!1" excludes index 1 and keeps everything else.
+        let data: Vec<Vec<u8>> = bytes_vec(vec!["The", "quick", "brown", "fox"]);
+        let expected: Vec<Vec<u8>> = bytes_vec(vec!["The", "brown", "fox"]);
+        let actual = super::keep_index_matches(
+            &IndexFilters {
+                negate: true,
+                rules: vec![IndexFilter::Exact(1usize)],
+            },
             data,
         );
         assert_eq!(actual, expected);
@@ -189,4 +391,48 @@ mod test {
         );
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn transform_regex_matches() {
+        // Rewrites "user@host" pairs to "host:user", leaving a non-matching field untouched.
+        let transform = RegexTransform {
+            regex: Regex::new(r"(\w+)@(\w+)").unwrap(),
+            template: b"$2:$1".to_vec(),
+            global: false,
+            keep_only_matches: false,
+        };
+        let expected = bytes_vec(vec!["host:user", "no-match-here"]);
+        let actual = super::transform_regex_matches(&transform, bytes_vec(vec!["user@host", "no-match-here"]));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn transform_regex_matches_keep_only_matches() {
+        // With keep_only_matches set, non-matching fields are dropped instead of passed through.
+        let transform = RegexTransform {
+            regex: Regex::new(r"(\w+)@(\w+)").unwrap(),
+            template: b"$2:$1".to_vec(),
+            global: false,
+            keep_only_matches: true,
+        };
+        let expected = bytes_vec(vec!["host:user"]);
+        let actual = super::transform_regex_matches(&transform, bytes_vec(vec!["user@host", "no-match-here"]));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn transform_output_streaming() {
+        // Splits on "\n" line-by-line (a trailing line without a final newline still counts),
+        // and applies row_options to split each line into cells.
+        let mut row_options = super::Options::default();
+        let mut separators = ByteTrie::new();
+        separators.insert(&[b'\t']);
+        row_options.separators = Some(super::FieldSeparator::Literal(separators));
+
+        let input = b"a\tb\nc\td".to_vec();
+        let mut output = vec![];
+        super::transform_output_streaming(&row_options, input.as_slice(), &mut output).unwrap();
+
+        assert_eq!(output, b"a,b\nc,d\n".to_vec());
+    }
 }
@@ -4,6 +4,16 @@
 /// - Heartbeat handling (clients are expected to ping every HEARTBEAT_INTERVAL and are disconnected if they stop responding)
 /// - Continuation support (frames are collected and rolled into a single text or binary message, to reduce the number of handlers needed)
 /// - Actor shutdown on close messages
+/// - Incremental output: a running command's stdout is transformed and sent a complete line at a
+///   time instead of only once the process exits, so long-running or high-volume commands show
+///   something in the browser right away
+/// - Fully async command execution: stdout/stderr are read and the child is waited on via
+///   futures driven by the actix arbiter's reactor, rather than a timer re-polling blocking I/O
+/// - Protocol-conformant continuation handling: fragmented messages are validated against the
+///   kind (text or binary) of their first frame and a maximum reassembled size, closing with the
+///   appropriate RFC 6455 close code rather than silently coercing or growing unbounded
+/// - Hot-reloadable defaults: a connection that hasn't customized its column/row options is
+///   notified and updated when the shared `config::ConfigWatcher` reloads its config file
 ///
 /// For simplicity's sake, text messages are treated as binary.
 
@@ -11,16 +21,21 @@ use actix::prelude::*;
 use actix_http::ws::{CloseCode, CloseReason, Item};
 use actix_web_actors::ws;
 use bytes::{Bytes, BytesMut};
+use crate::config::{self, ConfigWatcher};
 use crate::parsers;
-use crate::protos::definitions::{CompletedCommand, FromClient, FromClient_oneof_inner as FromClientInner, FromServer, FromServer_oneof_inner as FromServerInner, RunCommand, SetColumnIndexFilters, SetColumnRegexFilter, SetColumnSeparators, SetRowIndexFilters, SetRowRegexFilter, SetRowSeparators, UnexpectedError};
+use crate::protos::definitions::{CompletedCommand, DefaultOptionsChanged, FromClient, FromClient_oneof_inner as FromClientInner, FromServer, FromServer_oneof_inner as FromServerInner, PartialCommandOutput, RunCommand, SetColumnIndexFilters, SetColumnRegexFilter, SetColumnSeparators, SetRowIndexFilters, SetRowRegexFilter, SetRowSeparators, UnexpectedError};
 use crate::transformers;
+use futures::StreamExt;
 use protobuf::{Message as ProtobufMessage, ProtobufError};
 use std::cell::RefCell;
 use std::fmt;
-use std::io::{self, Read};
-use std::process::{Child, Command, ExitStatus, Stdio};
+use std::io;
+use std::process::{ExitStatus, Stdio};
 use std::rc::Rc;
 use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tokio::sync::oneshot;
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
 
 struct MessageParseError(ProtobufError);
 
@@ -38,19 +53,6 @@ impl fmt::Display for EmptyMessageError {
     }
 }
 
-#[derive(Debug)]
-enum CancelError {
-    KillError(io::Error),
-}
-
-impl fmt::Display for CancelError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            CancelError::KillError(error) => write!(f, "An error occurred while killing the current command process:\n{}", error),
-        }
-    }
-}
-
 #[derive(Debug)]
 enum RunError {
     SpawnError(io::Error),
@@ -67,8 +69,8 @@ impl fmt::Display for RunError {
 #[derive(Debug)]
 enum WorkerError {
     WaitError(io::Error),
-    ReadStdoutError(io::Error),
-    ReadStderrError(io::Error),
+    ReadStdoutError(LinesCodecError),
+    ReadStderrError(LinesCodecError),
     TransformStdoutError(io::Error),
     EncodeCommandError(ProtobufError),
 }
@@ -76,7 +78,7 @@ enum WorkerError {
 impl fmt::Display for WorkerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            WorkerError::WaitError(error) => write!(f, "An error occurred while calling wait() on the current command process:\n{}", error),
+            WorkerError::WaitError(error) => write!(f, "An error occurred while waiting for the current command process to exit:\n{}", error),
             WorkerError::ReadStdoutError(error) => write!(f, "An error occurred while reading stdout from the current command process:\n{}", error),
             WorkerError::ReadStderrError(error) => write!(f, "An error occurred while reading stderr from the current command process:\n{}", error),
             WorkerError::TransformStdoutError(error) => write!(f, "An error occurred while turning stdout for the current command process into CSV format:\n{}", error),
@@ -85,9 +87,39 @@ impl fmt::Display for WorkerError {
     }
 }
 
+/// What `wait_for_exit_or_cancellation` raced: the child exiting on its own, or `cancel()`
+/// asking for it to be killed first. Both still end in a wait()'d `ExitStatus` (or the I/O error
+/// that prevented one), so `on_child_exited` can route either into the right state.
+enum WaitOutcome {
+    Exited(io::Result<ExitStatus>),
+    Canceled(io::Result<ExitStatus>),
+}
+
+/// Owns `child` exclusively for the rest of its life: races its natural exit against a
+/// cancellation signal from `cancel()`, killing and reaping it first if canceled. Keeping the
+/// child's only `&mut` handle inside this one task is what lets cancellation and the exit wait
+/// coexist without fighting over access to it.
+async fn wait_for_exit_or_cancellation(mut child: Child, cancel_rx: oneshot::Receiver<()>) -> WaitOutcome {
+    tokio::select! {
+        result = child.wait() => WaitOutcome::Exited(result),
+        _ = cancel_rx => {
+            match child.start_kill() {
+                Ok(()) => WaitOutcome::Canceled(child.wait().await),
+                Err(error) => WaitOutcome::Canceled(Err(error)),
+            }
+        },
+    }
+}
+
+/// One complete, newline-decoded line of a running command's stdout, delivered to the actor via
+/// `ctx.add_stream`.
+struct StdoutLine(Result<String, LinesCodecError>);
+
+/// The stderr equivalent of `StdoutLine`.
+struct StderrLine(Result<String, LinesCodecError>);
+
 #[derive(Debug, Clone)]
 struct CancelingCommandStatus {
-    child: Rc<RefCell<Child>>,
     command: String,
 }
 
@@ -99,15 +131,25 @@ struct FinishedCommandStatus {
     stdout: Rc<Vec<u8>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 struct RunningCommandStatus {
-    child: Rc<RefCell<Child>>,
+    /// Consumed by `cancel()` to ask `wait_for_exit_or_cancellation` to kill the child. `None`
+    /// once a cancellation has already been sent.
+    cancel_tx: Rc<RefCell<Option<oneshot::Sender<()>>>>,
     command: String,
     stderr: Rc<RefCell<Vec<u8>>>,
     stdout: Rc<RefCell<Vec<u8>>>,
+    /// How many bytes of `stdout` have already been transformed and sent as a
+    /// `PartialCommandOutput`. Everything before this offset ends on a `\n`, so the next partial
+    /// (or the final `CompletedCommand`) only has to transform the bytes after it.
+    stdout_sent: Rc<RefCell<usize>>,
+    /// Set once the stdout stream has hit EOF. The command isn't `Finished` until this, its
+    /// stderr counterpart, and `exit_status` have all been filled in.
+    stdout_done: Rc<RefCell<bool>>,
+    stderr_done: Rc<RefCell<bool>>,
+    exit_status: Rc<RefCell<Option<io::Result<ExitStatus>>>>,
 }
 
-#[derive(Debug, Clone)]
 enum CommandStatus {
     Canceled {
         command: String,
@@ -124,40 +166,46 @@ enum CommandStatus {
 pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 /// How long before lack of client response causes a timeout.
 pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The largest a reassembled continuation message is allowed to grow to before the connection is
+/// closed with 1009 (Message too big), so an endless continuation stream can't exhaust memory.
+pub const MAX_CONTINUATION_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Whether an in-progress continuation started as a text or binary message, so the reassembled
+/// payload can be validated the same way a single-frame message of that kind would be.
+enum ContinuationKind {
+    Text,
+    Binary,
+}
+
+struct ContinuationFrame {
+    kind: ContinuationKind,
+    data: BytesMut,
+}
 
 pub struct WebsocketConnection {
     column_options: transformers::Options,
     row_options: transformers::Options,
     command_status: CommandStatus,
     should_resend_csv: bool,
+    /// True as long as no `set_column_*`/`set_row_*` request has ever been handled. A connection
+    /// that's still using unmodified defaults is kept registered with `config_watcher` so it can
+    /// pick up a hot-reloaded config file; the first customization opts it out for good.
+    uses_default_options: bool,
+    config_watcher: Addr<ConfigWatcher>,
     last_seen_heartbeat: Instant,
-    continuation_frame: Option<BytesMut>
+    continuation_frame: Option<ContinuationFrame>
 }
 
-const BUFFER_SIZE: usize = 5 * 1024;
-
-fn read_and_extend<R: Read>(mut reader: R, result: &mut Vec<u8>) -> io::Result<bool> {
-    let mut buffer = [0u8; BUFFER_SIZE];
-    let bytes_read = reader.read(&mut buffer)?;
-
-    for i in 0..bytes_read {
-        result.push(buffer[i]);
-    }
-
-    if bytes_read < BUFFER_SIZE {
-        return Ok(true);
-    }
-
-    Ok(false)
-}
 
 impl WebsocketConnection {
-    pub fn new(column_options: transformers::Options, row_options: transformers::Options) -> Self {
+    pub fn new(column_options: transformers::Options, row_options: transformers::Options, config_watcher: Addr<ConfigWatcher>) -> Self {
         Self {
             column_options,
             row_options,
             command_status: CommandStatus::Idle,
             should_resend_csv: false,
+            uses_default_options: true,
+            config_watcher,
             last_seen_heartbeat: Instant::now(),
             continuation_frame: None,
         }
@@ -197,68 +245,160 @@ impl WebsocketConnection {
         Ok(())
     }
 
-    fn on_canceling(&mut self, CancelingCommandStatus { child, command }: CancelingCommandStatus) -> Result<(), WorkerError> {
-        if let Err(error) = (*child).borrow_mut().try_wait() {
-            match error.kind() {
-                io::ErrorKind::InvalidInput => (),
-                _ => {
-                    self.command_status = CommandStatus::Failed;
-                    return Err(WorkerError::WaitError(error));
+    /// Transforms and sends just the newly-accumulated, newline-terminated slice of stdout as a
+    /// `PartialCommandOutput`, so a long-running command's output shows up incrementally instead
+    /// of all at once when it exits.
+    fn send_partial_csv(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, delta: &[u8]) -> Result<(), WorkerError> {
+        let transformed_stdout = transformers::transform_output(&self.column_options, &self.row_options, &delta.to_vec()).map_err(|error| WorkerError::TransformStdoutError(error))?;
+
+        let mut partial_command_output_response = FromServer::default();
+        let mut partial_command_output_wrapper = PartialCommandOutput::default();
+        partial_command_output_wrapper.set_stdout(transformed_stdout);
+        partial_command_output_response.inner = Some(FromServerInner::partial_command_output(partial_command_output_wrapper));
+        let encoded_partial_command_output_response = partial_command_output_response.write_to_bytes().map_err(|error| WorkerError::EncodeCommandError(error))?;
+
+        ctx.binary(encoded_partial_command_output_response);
+
+        Ok(())
+    }
+
+    /// Handles a single decoded line of a running command's stdout: accumulates it, transforms
+    /// and sends it as a `PartialCommandOutput`, and advances the `stdout_sent` watermark. A
+    /// no-op if the command isn't `Running` any more (e.g. a stray line that was already
+    /// in-flight when `cancel()` fired).
+    fn on_stdout_line(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, line: Result<String, LinesCodecError>) {
+        let running = match &self.command_status {
+            CommandStatus::Running(running) => running.clone(),
+            _ => return,
+        };
+
+        match line {
+            Ok(line) => {
+                let mut delta = line.into_bytes();
+                delta.push(b'\n');
+                running.stdout.borrow_mut().extend_from_slice(&delta);
+                *running.stdout_sent.borrow_mut() += delta.len();
+
+                if let Err(error) = self.send_partial_csv(ctx, &delta) {
+                    self.send_error(ctx, error);
                 }
-            }
+            },
+            Err(error) => {
+                self.send_error(ctx, WorkerError::ReadStdoutError(error));
+            },
         }
+    }
+
+    /// The stdout stream hit EOF. Records that and, if the command has also exited and stderr
+    /// has finished, finalizes it.
+    fn on_stdout_eof(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>) {
+        if let CommandStatus::Running(running) = &self.command_status {
+            *running.stdout_done.borrow_mut() = true;
+        }
+
+        self.maybe_finish_running(ctx);
+    }
 
-        self.command_status = CommandStatus::Canceled {
-            command: command.into(),
+    /// The stderr equivalent of `on_stdout_line`. Stderr isn't streamed incrementally to the
+    /// client - only the final `CompletedCommand` carries it - so this just accumulates it.
+    fn on_stderr_line(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, line: Result<String, LinesCodecError>) {
+        let running = match &self.command_status {
+            CommandStatus::Running(running) => running.clone(),
+            _ => return,
         };
 
-        Ok(())
+        match line {
+            Ok(line) => {
+                let mut stderr = running.stderr.borrow_mut();
+                stderr.extend_from_slice(line.as_bytes());
+                stderr.push(b'\n');
+            },
+            Err(error) => {
+                self.send_error(ctx, WorkerError::ReadStderrError(error));
+            },
+        }
+    }
+
+    /// The stderr equivalent of `on_stdout_eof`.
+    fn on_stderr_eof(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>) {
+        if let CommandStatus::Running(running) = &self.command_status {
+            *running.stderr_done.borrow_mut() = true;
+        }
+
+        self.maybe_finish_running(ctx);
     }
 
-    fn on_running(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, RunningCommandStatus { child, command, stderr, stdout }: RunningCommandStatus) -> Result<(), WorkerError> {
-        dbg!(command.clone());
-        let mut finished_stderr = false;
-        if let Some(stderr_handle) = (*child).borrow_mut().stderr.take() {
-            finished_stderr = read_and_extend(stderr_handle, (*stderr).borrow_mut().as_mut()).map_err(|error| WorkerError::ReadStderrError(error))?;
+    /// Called once `wait_for_exit_or_cancellation` resolves. A natural exit records the status
+    /// and tries to finalize; a cancellation (successful or not) resolves `Canceling` directly,
+    /// since a canceled command never sends a `CompletedCommand`.
+    fn on_child_exited(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, outcome: WaitOutcome) {
+        match outcome {
+            WaitOutcome::Exited(result) => {
+                if let CommandStatus::Running(running) = &self.command_status {
+                    *running.exit_status.borrow_mut() = Some(result);
+                }
+
+                self.maybe_finish_running(ctx);
+            },
+            WaitOutcome::Canceled(Ok(_)) => {
+                if let CommandStatus::Canceling(CancelingCommandStatus { command }) = &self.command_status {
+                    self.command_status = CommandStatus::Canceled { command: command.clone() };
+                }
+            },
+            WaitOutcome::Canceled(Err(error)) => {
+                self.command_status = CommandStatus::CancellationFailed;
+                self.send_error(ctx, WorkerError::WaitError(error));
+            },
         }
+    }
+
+    /// If the command is `Running` and has reported stdout EOF, stderr EOF, and an exit status,
+    /// transitions it to `Finished` and sends the as-yet-unsent tail of stdout (plus all of
+    /// stderr) as the `CompletedCommand`.
+    fn maybe_finish_running(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>) {
+        let is_ready = match &self.command_status {
+            CommandStatus::Running(running) => {
+                *running.stdout_done.borrow() && *running.stderr_done.borrow() && running.exit_status.borrow().is_some()
+            },
+            _ => false,
+        };
 
-        let mut finished_stdout = false;
-        if let Some(stdout_handle) = (*child).borrow_mut().stdout.take() {
-            finished_stdout = read_and_extend(stdout_handle, (*stdout).borrow_mut().as_mut()).map_err(|error| WorkerError::ReadStdoutError(error))?;
+        if !is_ready {
+            return;
         }
 
-        let maybe_status = Some((*child).borrow_mut().wait().map_err(|error| WorkerError::WaitError(error))?);
-        if let Some(status) = maybe_status {
-            if finished_stderr && finished_stdout {
+        let running = match std::mem::replace(&mut self.command_status, CommandStatus::Idle) {
+            CommandStatus::Running(running) => running,
+            other => {
+                self.command_status = other;
+                return;
+            },
+        };
+
+        match running.exit_status.borrow_mut().take().expect("checked by is_ready above") {
+            Ok(status) => {
                 self.command_status = CommandStatus::Finished(FinishedCommandStatus {
-                    command: command.into(),
+                    command: running.command.clone(),
                     status,
-                    stderr: Rc::new((*stderr).borrow().clone()),
-                    stdout: Rc::new((*stdout).borrow().clone()),
+                    stderr: Rc::new(running.stderr.borrow().clone()),
+                    stdout: Rc::new(running.stdout.borrow().clone()),
                 });
-    
-                self.send_csvs(ctx, status, &(*stdout).borrow(), &(*stderr).borrow())?;
-            }
-        }
-
-        Ok(())
-    }
 
-    fn check_status(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>) {
-        let result = match self.command_status.clone() {
-            CommandStatus::Canceling(canceling_command_status) => self.on_canceling(canceling_command_status),
-            CommandStatus::Running(running_command_status) => self.on_running(ctx, running_command_status),
-            CommandStatus::Finished(finished_command_status) if self.should_resend_csv => {
-                self.send_csvs(ctx, finished_command_status.status, &finished_command_status.stdout, &finished_command_status.stderr)
+                // The client has already seen everything up to `stdout_sent`; the completed
+                // command only needs to carry the as-yet-unsent tail (plus the exit status).
+                let tail = running.stdout.borrow()[*running.stdout_sent.borrow()..].to_vec();
+                if let Err(error) = self.send_csvs(ctx, status, &tail, &running.stderr.borrow()) {
+                    self.send_error(ctx, error);
+                }
+            },
+            Err(error) => {
+                self.command_status = CommandStatus::Failed;
+                self.send_error(ctx, WorkerError::WaitError(error));
             },
-            _ => Ok(()),
-        };
-        if let Err(error) = result {
-            self.send_error(ctx, error);
         }
     }
 
-    fn run(&mut self, command: RunCommand) -> Result<(), RunError> {
+    fn run(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, command: RunCommand) -> Result<(), RunError> {
         let command_str = command.get_command();
         match Command::new("sh")
             .arg("-c")
@@ -266,15 +406,31 @@ impl WebsocketConnection {
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
+            .kill_on_drop(true)
             .spawn()
         {
-            Ok(child) => {
+            Ok(mut child) => {
+                let stdout = child.stdout.take().expect("stdout was requested as piped");
+                let stderr = child.stderr.take().expect("stderr was requested as piped");
+                let (cancel_tx, cancel_rx) = oneshot::channel();
+
                 self.command_status = CommandStatus::Running(RunningCommandStatus {
+                    cancel_tx: Rc::new(RefCell::new(Some(cancel_tx))),
                     command: command_str.to_owned(),
-                    child: Rc::new(RefCell::new(child)),
                     stderr: Rc::new(RefCell::new(vec![])),
                     stdout: Rc::new(RefCell::new(vec![])),
+                    stdout_sent: Rc::new(RefCell::new(0)),
+                    stdout_done: Rc::new(RefCell::new(false)),
+                    stderr_done: Rc::new(RefCell::new(false)),
+                    exit_status: Rc::new(RefCell::new(None)),
                 });
+
+                ctx.add_stream(FramedRead::new(stdout, LinesCodec::new()).map(StdoutLine));
+                ctx.add_stream(FramedRead::new(stderr, LinesCodec::new()).map(StderrLine));
+                ctx.spawn(actix::fut::wrap_future(wait_for_exit_or_cancellation(child, cancel_rx)).map(
+                    |outcome, connection: &mut Self, ctx| connection.on_child_exited(ctx, outcome),
+                ));
+
                 Ok(())
             },
             Err(error) => {
@@ -284,36 +440,26 @@ impl WebsocketConnection {
         }
     }
 
-    fn cancel(&mut self) -> Result<(), CancelError> {
-        let (maybe_new_command_status, result) = match &self.command_status {
-            CommandStatus::Running(RunningCommandStatus { child, command, stderr: _, stdout: _ }) => {
-                match (**child).borrow_mut().kill() {
-                    // Already canceled.
-                    Err(error) if error.kind() == io::ErrorKind::InvalidInput => (None, Ok(())),
-                    Err(error) => (Some(CommandStatus::CancellationFailed), Err(CancelError::KillError(error))),
-                    Ok(()) => {
-                        (
-                            Some(CommandStatus::Canceling(CancelingCommandStatus {
-                                child: child.clone(),
-                                command: command.clone(),
-                            })),
-                            Ok(())
-                        )
-                    },
-                }
-            },
-            _ => (None, Ok(())),
-        };
-
-        if let Some(new_command_status) = maybe_new_command_status {
-            self.command_status = new_command_status;
+    fn cancel(&mut self) {
+        if let CommandStatus::Running(RunningCommandStatus { cancel_tx, command, .. }) = &self.command_status {
+            let command = command.clone();
+            // A send error just means the command already exited on its own between the client
+            // deciding to cancel and this message arriving - `on_child_exited` has already
+            // recorded its exit status, so it's left `Running` for `maybe_finish_running` to finish.
+            let canceled = match cancel_tx.borrow_mut().take() {
+                Some(sender) => sender.send(()).is_ok(),
+                None => true,
+            };
+
+            if canceled {
+                self.command_status = CommandStatus::Canceling(CancelingCommandStatus { command });
+            }
         }
-
-        result
     }
 
     fn set_column_index_filters(&mut self, filters: SetColumnIndexFilters) -> Result<(), parsers::InvalidIndexFiltersError> {
         self.should_resend_csv = true;
+        self.uses_default_options = false;
         match parsers::parse_index_filters(filters.get_filters()) {
             Ok(parsed_filters) => {
                 self.column_options.index_filters = Some(parsed_filters);
@@ -328,6 +474,7 @@ impl WebsocketConnection {
 
     fn set_column_regex_filter(&mut self, filter: SetColumnRegexFilter) -> Result<(), parsers::InvalidRegexFilterError> {
         self.should_resend_csv = true;
+        self.uses_default_options = false;
         match parsers::parse_regex_filter(filter.get_filter()) {
             Ok(parsed_filter) => {
                 self.column_options.regex_filter = Some(parsed_filter);
@@ -342,6 +489,7 @@ impl WebsocketConnection {
 
     fn set_column_separators(&mut self, separators: SetColumnSeparators) -> Result<(), parsers::InvalidFieldSeparatorError> {
         self.should_resend_csv = true;
+        self.uses_default_options = false;
         match parsers::parse_field_separators(separators.get_separators()) {
             Ok(parsed_separators) => {
                 self.column_options.separators = Some(parsed_separators);
@@ -356,6 +504,7 @@ impl WebsocketConnection {
 
     fn set_row_index_filters(&mut self, filters: SetRowIndexFilters) -> Result<(), parsers::InvalidIndexFiltersError> {
         self.should_resend_csv = true;
+        self.uses_default_options = false;
         match parsers::parse_index_filters(filters.get_filters()) {
             Ok(parsed_filters) => {
                 self.row_options.index_filters = Some(parsed_filters);
@@ -370,6 +519,7 @@ impl WebsocketConnection {
 
     fn set_row_regex_filter(&mut self, filter: SetRowRegexFilter) -> Result<(), parsers::InvalidRegexFilterError> {
         self.should_resend_csv = true;
+        self.uses_default_options = false;
         match parsers::parse_regex_filter(filter.get_filter()) {
             Ok(parsed_filter) => {
                 self.row_options.regex_filter = Some(parsed_filter);
@@ -384,6 +534,7 @@ impl WebsocketConnection {
 
     fn set_row_separators(&mut self, separators: SetRowSeparators) -> Result<(), parsers::InvalidFieldSeparatorError> {
         self.should_resend_csv = true;
+        self.uses_default_options = false;
         match parsers::parse_field_separators(separators.get_separators()) {
             Ok(parsed_separators) => {
                 self.row_options.separators = Some(parsed_separators);
@@ -401,12 +552,10 @@ impl WebsocketConnection {
             Ok(message) => {
                 match message.inner {
                     Some(FromClientInner::cancel_command(_cancel_command)) => {
-                        if let Err(error) = self.cancel() {
-                            self.send_error(ctx, error);
-                        }
+                        self.cancel();
                     },
                     Some(FromClientInner::run_command(run_command)) => {
-                        if let Err(error) = self.run(run_command) {
+                        if let Err(error) = self.run(ctx, run_command) {
                             self.send_error(ctx, error);
                         }
                     },
@@ -451,39 +600,65 @@ impl WebsocketConnection {
         }
     }
 
-    fn set_first_frame_part(&mut self, data: Bytes) {
+    fn set_first_frame_part(&mut self, kind: ContinuationKind, data: Bytes) {
         let mut frame_data = BytesMut::with_capacity(2 * data.len());
         frame_data.extend(data.iter());
-        self.continuation_frame = Some(frame_data);
+        self.continuation_frame = Some(ContinuationFrame { kind, data: frame_data });
     }
 
-    fn set_frame_part(&mut self, additional_data: Bytes) {
+    /// Appends a middle fragment of an in-progress continuation. Closes with 1002 (Protocol
+    /// error) if there was no preceding first frame, and with 1009 (Message too big) if the
+    /// reassembled message would exceed `MAX_CONTINUATION_FRAME_SIZE`.
+    fn set_frame_part(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, additional_data: Bytes) {
         match &mut self.continuation_frame {
-            // If the client messes up and sends an invalid continuation, treat it as binary to be safe.
-            // We're choosing not to error here; it would provide more error handling complexity than I would like to take on at this time.
-            None => self.set_first_frame_part(additional_data),
-            Some(data) => {
-                data.extend(additional_data.iter());
-            }
+            None => {
+                ctx.close(Some(CloseReason::from(CloseCode::Protocol)));
+                ctx.stop();
+            },
+            Some(frame) => {
+                if frame.data.len() + additional_data.len() > MAX_CONTINUATION_FRAME_SIZE {
+                    self.continuation_frame = None;
+                    ctx.close(Some(CloseReason::from(CloseCode::Size)));
+                    ctx.stop();
+                    return;
+                }
+
+                frame.data.extend(additional_data.iter());
+            },
         }
     }
 
-    fn set_last_frame_part(&mut self, additional_data: Bytes) {
-        match &mut self.continuation_frame {
-            // If the client messes up and sends an invalid continuation, treat it as binary to be safe.
-            // We're choosing not to error here; it would provide more error handling complexity than I would like to take on at this time.
-            None => self.set_first_frame_part(additional_data),
-            Some(data) => {
-                data.extend(additional_data.iter());
-            }
+    /// Appends the final fragment, then reassembles and validates the complete message: 1002
+    /// (Protocol error) if there was no preceding first frame, 1009 (Message too big) if the
+    /// reassembled message exceeds `MAX_CONTINUATION_FRAME_SIZE`, and 1007 (Invalid frame payload
+    /// data) if a text message doesn't reassemble into valid UTF-8.
+    fn set_last_frame_part(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, additional_data: Bytes) {
+        let mut frame = match self.continuation_frame.take() {
+            None => {
+                ctx.close(Some(CloseReason::from(CloseCode::Protocol)));
+                ctx.stop();
+                return;
+            },
+            Some(frame) => frame,
+        };
+
+        if frame.data.len() + additional_data.len() > MAX_CONTINUATION_FRAME_SIZE {
+            ctx.close(Some(CloseReason::from(CloseCode::Size)));
+            ctx.stop();
+            return;
         }
-    }
 
-    fn send_full_continuation_frame(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>) {
-        if let Some(ref data) = self.continuation_frame {
-            let frozen_data = data.clone().freeze();
-            self.handle_message(ctx, frozen_data);
+        frame.data.extend(additional_data.iter());
+
+        if let ContinuationKind::Text = frame.kind {
+            if std::str::from_utf8(&frame.data).is_err() {
+                ctx.close(Some(CloseReason::from(CloseCode::Invalid)));
+                ctx.stop();
+                return;
+            }
         }
+
+        self.handle_message(ctx, frame.data.freeze());
     }
 }
 
@@ -502,9 +677,48 @@ impl Actor for WebsocketConnection {
             ctx.ping(b"");
         });
 
-        ctx.run_interval(Duration::from_millis(250), |connection, ctx| {
-            connection.check_status(ctx);
-        });
+        self.config_watcher.do_send(config::Register(ctx.address()));
+    }
+}
+
+impl Handler<config::ApplyDefaultOptions> for WebsocketConnection {
+    type Result = ();
+
+    fn handle(&mut self, config::ApplyDefaultOptions(column_options, row_options): config::ApplyDefaultOptions, ctx: &mut Self::Context) {
+        if !self.uses_default_options {
+            return;
+        }
+
+        self.column_options = column_options;
+        self.row_options = row_options;
+        self.should_resend_csv = true;
+
+        let mut notification = FromServer::default();
+        notification.inner = Some(FromServerInner::default_options_changed(DefaultOptionsChanged::default()));
+        match notification.write_to_bytes() {
+            Ok(encoded) => ctx.binary(encoded),
+            Err(error) => log::error!("{}", error),
+        }
+    }
+}
+
+impl StreamHandler<StdoutLine> for WebsocketConnection {
+    fn handle(&mut self, StdoutLine(line): StdoutLine, ctx: &mut Self::Context) {
+        self.on_stdout_line(ctx, line);
+    }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        self.on_stdout_eof(ctx);
+    }
+}
+
+impl StreamHandler<StderrLine> for WebsocketConnection {
+    fn handle(&mut self, StderrLine(line): StderrLine, ctx: &mut Self::Context) {
+        self.on_stderr_line(ctx, line);
+    }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        self.on_stderr_eof(ctx);
     }
 }
 
@@ -517,17 +731,16 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketConnecti
         match msg {
             Ok(ws::Message::Nop) => {},
             Ok(ws::Message::Continuation(Item::FirstText(data))) => {
-                self.set_first_frame_part(data);
+                self.set_first_frame_part(ContinuationKind::Text, data);
             },
             Ok(ws::Message::Continuation(Item::FirstBinary(data))) => {
-                self.set_first_frame_part(data);
+                self.set_first_frame_part(ContinuationKind::Binary, data);
             },
             Ok(ws::Message::Continuation(Item::Continue(additional_data))) => {
-                self.set_frame_part(additional_data);
+                self.set_frame_part(ctx, additional_data);
             },
             Ok(ws::Message::Continuation(Item::Last(additional_data))) => {
-                self.set_last_frame_part(additional_data);
-                self.send_full_continuation_frame(ctx);
+                self.set_last_frame_part(ctx, additional_data);
             },
             Ok(ws::Message::Ping(data)) => {
                 self.last_seen_heartbeat = Instant::now();
@@ -544,8 +757,8 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketConnecti
             Ok(ws::Message::Binary(data)) => {
                 self.handle_message(ctx, data);
             },
-            Ok(ws::Message::Close(reason)) => {
-                ctx.close(reason);
+            Ok(ws::Message::Close(_reason)) => {
+                ctx.close(Some(CloseReason::from(CloseCode::Normal)));
                 ctx.stop();
             }
             Err(error) => {
@@ -0,0 +1,122 @@
+/// Watches `--file`'s path for changes (when `--watch` is set) and re-renders every connected
+/// `WebsocketConnection` without the client having to reconnect.
+///
+/// This is `--file`'s complement to `spawn_stdin_reader` in `main.rs`: where a streaming pipe is
+/// grown in place and picked up by each connection's own heartbeat-driven `refresh_stdin`, a
+/// watched file can shrink or be rewritten wholesale (e.g. a log rotation), so instead this
+/// actively pushes the fresh bytes to every subscriber the moment `notify` reports a change -
+/// mirroring how `server::config::ConfigWatcher` pushes reloaded defaults to its own subscribers.
+use crate::websocket_connection::WebsocketConnection;
+use actix::prelude::*;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long `notify` waits after the last filesystem event before firing, so a writer doing
+/// several small writes in a row (e.g. a log rotation) only triggers one reload.
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(1);
+
+fn load(path: &PathBuf) -> Vec<u8> {
+    match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            log::error!("Could not read {}:\n{}", path.display(), error);
+            vec![]
+        }
+    }
+}
+
+/// Subscribes a connection to be notified when `path`'s contents change.
+pub struct Register(pub Addr<WebsocketConnection>);
+
+impl Message for Register {
+    type Result = ();
+}
+
+struct Reload;
+
+impl Message for Reload {
+    type Result = ();
+}
+
+pub struct FileWatcher {
+    path: PathBuf,
+    /// The same `Arc<Mutex<Vec<u8>>>` a `Context` hands to every new connection, kept current so
+    /// a client that connects after a reload still gets the latest contents without needing
+    /// `Register` to have already fired for it.
+    shared: Arc<Mutex<Vec<u8>>>,
+    subscribers: Vec<Addr<WebsocketConnection>>,
+    // Keeps the notify watcher alive for as long as the actor is; dropping it stops the watch.
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl FileWatcher {
+    pub fn new(path: PathBuf, shared: Arc<Mutex<Vec<u8>>>) -> Self {
+        Self {
+            path,
+            shared,
+            subscribers: vec![],
+            _watcher: None,
+        }
+    }
+}
+
+impl Actor for FileWatcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let (tx, rx) = channel();
+        let mut watcher: RecommendedWatcher = match Watcher::new(tx, WATCH_DEBOUNCE) {
+            Ok(watcher) => watcher,
+            Err(error) => {
+                log::error!("Could not watch {} for changes:\n{}", self.path.display(), error);
+                return;
+            }
+        };
+
+        if let Err(error) = watcher.watch(&self.path, RecursiveMode::NonRecursive) {
+            log::error!("Could not watch {} for changes:\n{}", self.path.display(), error);
+            return;
+        }
+
+        self._watcher = Some(watcher);
+
+        let address = ctx.address();
+        std::thread::spawn(move || {
+            while let Ok(_event) = rx.recv() {
+                address.do_send(Reload);
+            }
+        });
+    }
+}
+
+impl Handler<Reload> for FileWatcher {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Reload, _ctx: &mut Self::Context) {
+        let contents = load(&self.path);
+        *self.shared.lock().unwrap() = contents.clone();
+
+        for subscriber in &self.subscribers {
+            subscriber.do_send(ApplyStdin(contents.clone()));
+        }
+    }
+}
+
+impl Handler<Register> for FileWatcher {
+    type Result = ();
+
+    fn handle(&mut self, Register(address): Register, _ctx: &mut Self::Context) {
+        self.subscribers.push(address);
+    }
+}
+
+/// Sent to a `WebsocketConnection` subscribed via `Register` when the watched file's contents
+/// change.
+pub struct ApplyStdin(pub Vec<u8>);
+
+impl Message for ApplyStdin {
+    type Result = ();
+}
@@ -1,26 +1,63 @@
 mod byte_trie;
+mod file_watch;
 mod parsers;
+mod permessage_deflate;
+mod protocol;
 mod protos;
+mod sessions;
 mod transformers;
 mod websocket_connection;
 
 use actix::clock;
+use actix::Addr;
 use actix_cors::Cors;
 use actix_web::middleware::Logger;
 use actix_web::web;
 use actix_web_actors::ws;
 use clap::{App, Arg};
 use env_logger;
-use std::io::{self, Read};
+use protobuf::Message;
+use protos::definitions::{TableExport, TableRow};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
 use std::process::Command;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use futures::executor;
 use std::thread;
 use std::sync::mpsc;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
 
-fn open_gui(socket_address: &str) -> io::Result<()> {
+/// How much of stdin `spawn_stdin_reader` reads per blocking-read call, borrowed from the size
+/// actix-files' `ChunkedReadFile` uses for its own bounded reads.
+const STDIN_READ_CHUNK_SIZE: usize = 65_536;
+
+/// Reads stdin incrementally into `sink`, appending each chunk as it arrives instead of blocking
+/// the caller until EOF - so the server can start (and the GUI can open) immediately, and a
+/// producer like `tail -f` shows up in already-open connections via `WebsocketConnection`'s
+/// heartbeat-driven `refresh_stdin` instead of only being visible on the next reconnect.
+fn spawn_stdin_reader(sink: Arc<Mutex<Vec<u8>>>) {
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut chunk = [0u8; STDIN_READ_CHUNK_SIZE];
+
+        loop {
+            match stdin.read(&mut chunk) {
+                Ok(0) => return,
+                Ok(n) => sink.lock().unwrap().extend_from_slice(&chunk[..n]),
+                Err(error) => {
+                    log::error!("Failed to read command input:\n{}", error);
+                    return;
+                }
+            }
+        }
+    });
+}
+
+fn open_gui(socket_address: &str, scheme: &str) -> io::Result<()> {
     let mut child = Command::new("open")
-        .arg(format!("http://{}", socket_address))
+        .arg(format!("{}://{}", scheme, socket_address))
         .spawn()?;
 
     match child.wait() {
@@ -33,30 +70,96 @@ fn open_gui(socket_address: &str) -> io::Result<()> {
     }
 }
 
+/// Builds the `rustls::ServerConfig` for `run_server`'s `bind_rustls` from a PEM-encoded
+/// certificate chain and private key, i.e. exactly what `--cert`/`--key` point at. Accepts the
+/// key in either PKCS#8 or PKCS#1/RSA form, since `openssl genrsa`-style keys are PKCS#1 and
+/// would otherwise parse as zero keys with no error.
+fn load_tls_config(cert_path: &str, key_path: &str) -> io::Result<rustls::ServerConfig> {
+    let mut config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+
+    let cert_chain = rustls::internal::pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Failed to parse TLS certificate chain"))?;
+
+    let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Failed to parse TLS private key"))?;
+    if keys.is_empty() {
+        keys = rustls::internal::pemfile::rsa_private_keys(&mut BufReader::new(File::open(key_path)?))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Failed to parse TLS private key"))?;
+    }
+    if keys.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No PKCS#8 or PKCS#1 private keys found in the file given to --key",
+        ));
+    }
+
+    config
+        .set_single_cert(cert_chain, keys.remove(0))
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    Ok(config)
+}
+
 struct Context {
     bundled_html: String,
     bundled_css: String,
     bundled_js: String,
     bundled_js_map: String,
-    stdin: Vec<u8>,
+    /// The process's raw command input, grown incrementally by `spawn_stdin_reader` rather than
+    /// read to completion up front - shared so every connection and export endpoint sees
+    /// whatever has arrived by the time it reads it, instead of only what had arrived at startup.
+    stdin: Arc<Mutex<Vec<u8>>>,
+    /// Set when `--file --watch` is in effect, so `connect` can subscribe the new connection to
+    /// live reloads instead of just the one-time snapshot `stdin` holds at connect time.
+    file_watcher: Option<Addr<file_watch::FileWatcher>>,
     shutdown_channel: mpsc::Sender<()>,
+    websocket_connection_config: websocket_connection::WebsocketConnectionConfig,
+    /// The separators/regex/index filters built from `--col-sep`/`--row-sep`/`--regex`/`--fields`,
+    /// seeded into every new connection instead of `transformers::Options::default()`.
+    column_options: transformers::Options,
+    row_options: transformers::Options,
 }
 
 async fn connect(
     r: actix_web::HttpRequest,
     stream: web::Payload,
     context: web::Data<Context>,
+    session_store: web::Data<sessions::SessionStore>,
 ) -> Result<actix_web::HttpResponse, actix_web::Error> {
-    ws::start(
-        websocket_connection::WebsocketConnection::new(
-            context.stdin.clone(),
-            transformers::Options::default(),
-            transformers::Options::default(),
-            context.shutdown_channel.clone(),
-        ),
-        &r,
-        stream,
-    )
+    let actor = websocket_connection::WebsocketConnection::new(
+        Arc::clone(&context.stdin),
+        context.file_watcher.clone(),
+        context.column_options.clone(),
+        context.row_options.clone(),
+        session_store,
+        context.websocket_connection_config,
+    );
+
+    // Full CSV payloads compress extremely well, so honor a client's permessage-deflate offer
+    // (RFC 7692) when it sends one. `ws::start` can't do this itself - it has no hook for
+    // negotiating extensions or for the RSV1 bit compressed frames need - so this path builds
+    // the handshake response by hand and wraps both directions of the byte stream with
+    // `permessage_deflate`'s codec instead.
+    let offered_deflate = r
+        .headers()
+        .get("Sec-WebSocket-Extensions")
+        .and_then(|value| value.to_str().ok())
+        .and_then(permessage_deflate::negotiate);
+
+    match offered_deflate {
+        Some(params) => {
+            let inflating_stream = permessage_deflate::InflatingPayload::new(stream, params);
+            let mut response = ws::handshake(&r)?;
+            response.insert_header((
+                "Sec-WebSocket-Extensions",
+                permessage_deflate::response_header(&params),
+            ));
+
+            let frames = ws::WebsocketContext::create(actor, inflating_stream);
+            Ok(response.streaming(permessage_deflate::DeflatingFrames::new(frames, params)))
+        }
+        None => ws::start(actor, &r, stream),
+    }
 }
 
 #[actix_web::get("/")]
@@ -87,9 +190,148 @@ async fn index_js_map(context: web::Data<Context>) -> impl actix_web::Responder
         .body(context.bundled_js_map.clone())
 }
 
+/// The formats `/export.csv`, `/export.json`, and `/export.pb` each pin down explicitly, and that
+/// `/export` picks between based on `Accept`.
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    Csv,
+    Json,
+    Pb,
+}
+
+impl ExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Pb => "application/x-protobuf",
+        }
+    }
+}
+
+/// Encodes a transformed grid as a JSON array of arrays of UTF-8 (lossily decoded) cells, rather
+/// than the array-of-row-objects shape other parts of this crate use for the websocket - callers
+/// scripting against this endpoint want the raw grid, not a guess at column names.
+fn encode_json_grid(rows: &[Vec<Vec<u8>>]) -> io::Result<Vec<u8>> {
+    let grid = serde_json::Value::Array(
+        rows.iter()
+            .map(|row| {
+                serde_json::Value::Array(
+                    row.iter()
+                        .map(|cell| serde_json::Value::String(String::from_utf8_lossy(cell).into_owned()))
+                        .collect(),
+                )
+            })
+            .collect(),
+    );
+
+    serde_json::to_vec(&grid).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Encodes a transformed grid as a length-prefixed `TableExport` message, reusing the `protos`
+/// module `build.rs` generates from `definitions.proto`.
+fn encode_protobuf_grid(rows: &[Vec<Vec<u8>>]) -> io::Result<Vec<u8>> {
+    let mut export = TableExport::default();
+    export.rows = protobuf::RepeatedField::from_vec(
+        rows.iter()
+            .map(|row| {
+                let mut table_row = TableRow::default();
+                table_row.cells = protobuf::RepeatedField::from_vec(row.clone());
+                table_row
+            })
+            .collect(),
+    );
+
+    export
+        .write_length_delimited_to_bytes()
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Runs the context's current column/row `Options` over `context.stdin` and encodes the result in
+/// the given format - the shared body behind `/export.csv`, `/export.json`, `/export.pb`, and
+/// `/export`'s `Accept`-driven dispatch.
+fn render_export(context: &Context, format: ExportFormat) -> io::Result<Vec<u8>> {
+    let stdin = context.stdin.lock().unwrap();
+    let rows = transformers::transform_rows(&context.column_options, &context.row_options, &stdin);
+
+    match format {
+        ExportFormat::Csv => transformers::encode_csv(&rows),
+        ExportFormat::Json => encode_json_grid(&rows),
+        ExportFormat::Pb => encode_protobuf_grid(&rows),
+    }
+}
+
+fn export_response(context: &Context, format: ExportFormat) -> actix_web::HttpResponse {
+    match render_export(context, format) {
+        Ok(body) => actix_web::HttpResponse::Ok()
+            .content_type(format.content_type())
+            .body(body),
+        Err(error) => {
+            log::error!("Failed to render export:\n{}", error);
+            actix_web::HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[actix_web::get("/export.csv")]
+async fn export_csv(context: web::Data<Context>) -> impl actix_web::Responder {
+    export_response(&context, ExportFormat::Csv)
+}
+
+#[actix_web::get("/export.json")]
+async fn export_json(context: web::Data<Context>) -> impl actix_web::Responder {
+    export_response(&context, ExportFormat::Json)
+}
+
+#[actix_web::get("/export.pb")]
+async fn export_pb(context: web::Data<Context>) -> impl actix_web::Responder {
+    export_response(&context, ExportFormat::Pb)
+}
+
+/// The extension-less counterpart to `export_csv`/`export_json`/`export_pb`, for clients that
+/// negotiate format with `Accept` instead of the URL. Falls back to CSV when `Accept` names
+/// neither protobuf nor JSON.
+#[actix_web::get("/export")]
+async fn export(request: actix_web::HttpRequest, context: web::Data<Context>) -> impl actix_web::Responder {
+    let accept = request
+        .headers()
+        .get("Accept")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+
+    let format = if accept.contains("application/x-protobuf") {
+        ExportFormat::Pb
+    } else if accept.contains("application/json") {
+        ExportFormat::Json
+    } else {
+        ExportFormat::Csv
+    };
+
+    export_response(&context, format)
+}
+
+/// Set from `handle_sigterm` (an OS signal handler, which may only touch an `AtomicBool` - no
+/// allocation, locking, or channel sends are safe there) and polled by a plain thread in
+/// `run_server`, which is. `ctrlc::set_handler` below only traps SIGINT/Ctrl-C (and Ctrl-Break on
+/// Windows) unless its `termination` feature is on, so SIGTERM needs this separate unix-only path.
+#[cfg(unix)]
+static SIGTERM_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn handle_sigterm(_: libc::c_int) {
+    SIGTERM_RECEIVED.store(true, Ordering::SeqCst);
+}
+
 async fn run_server(
-    stdin: Vec<u8>,
+    stdin: Arc<Mutex<Vec<u8>>>,
+    file_watcher: Option<Addr<file_watch::FileWatcher>>,
     socket_address: &str,
+    websocket_connection_config: websocket_connection::WebsocketConnectionConfig,
+    session_dir: std::path::PathBuf,
+    session_ttl: Duration,
+    column_options: transformers::Options,
+    row_options: transformers::Options,
+    tls_config: Option<rustls::ServerConfig>,
 ) -> io::Result<()> {
     let html = include_str!("../ui/index.html");
     let css = include_str!("../ui/out.css");
@@ -98,6 +340,42 @@ async fn run_server(
 
     let (tx, rx) = mpsc::channel::<()>();
 
+    // Closing the terminal (SIGINT, or Ctrl-Break on Windows) should tear the server down cleanly
+    // instead of leaving it running with nothing left to read stdin for - feed it into the same
+    // shutdown path a `WebsocketConnection` already uses to ask for a graceful stop.
+    let signal_tx = tx.clone();
+    ctrlc::set_handler(move || {
+        if let Err(error) = signal_tx.send(()) {
+            log::error!("Failed to propagate a shutdown signal:\n{}", error);
+        }
+    })
+    .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+
+    // `ctrlc` doesn't trap SIGTERM (what a process supervisor sends to ask for a graceful stop)
+    // without its `termination` feature, so register it separately here and poll the flag it sets
+    // from a plain thread, forwarding into the same shutdown channel as the Ctrl-C path above.
+    #[cfg(unix)]
+    {
+        unsafe {
+            libc::signal(libc::SIGTERM, handle_sigterm as libc::sighandler_t);
+        }
+
+        let sigterm_tx = tx.clone();
+        thread::spawn(move || loop {
+            if SIGTERM_RECEIVED.load(Ordering::SeqCst) {
+                if let Err(error) = sigterm_tx.send(()) {
+                    log::error!("Failed to propagate a shutdown signal:\n{}", error);
+                }
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        });
+    }
+
+    // Shared across every worker, so a client that reconnects to a different worker still finds
+    // the session it's asking to resume.
+    let session_store = web::Data::new(sessions::SessionStore::new(session_dir, session_ttl));
+
     let server = actix_web::HttpServer::new(move || {
         actix_web::App::new()
             .data(Context {
@@ -105,18 +383,32 @@ async fn run_server(
                 bundled_css: css.to_owned(),
                 bundled_js: js.to_owned(),
                 bundled_js_map: js_map.to_owned(),
-                stdin: stdin.clone(),
+                stdin: Arc::clone(&stdin),
+                file_watcher: file_watcher.clone(),
                 shutdown_channel: tx.clone(),
+                websocket_connection_config,
+                column_options: column_options.clone(),
+                row_options: row_options.clone(),
             })
+            .app_data(session_store.clone())
             .service(web::resource("/ws/").route(web::get().to(connect)))
             .service(index)
             .service(index_css)
             .service(index_js)
             .service(index_js_map)
+            .service(export_csv)
+            .service(export_json)
+            .service(export_pb)
+            .service(export)
             .wrap(Logger::default())
             .wrap(Cors::permissive())
-    })
-    .bind(socket_address)?
+    });
+
+    let scheme = if tls_config.is_some() { "https" } else { "http" };
+    let server = match tls_config {
+        Some(tls_config) => server.bind_rustls(socket_address, tls_config)?,
+        None => server.bind(socket_address)?,
+    }
     .run();
 
     // clone the Server handle
@@ -137,7 +429,7 @@ async fn run_server(
     clock::delay_for(Duration::from_millis(150)).await;
 
     // Open the GUI.
-    open_gui(socket_address)?;
+    open_gui(socket_address, scheme)?;
 
     // And back to waiting for the server.
     server.await
@@ -146,6 +438,7 @@ async fn run_server(
 #[actix_web::main]
 async fn main() {
     env_logger::init();
+    websocket_connection::install_panic_hook();
 
     let matches = App::new("VAWK (Visual AWK)")
         .version("1.7.0")
@@ -163,17 +456,336 @@ async fn main() {
                 .value_name("PORT")
                 .required(false),
         )
+        .arg(
+            Arg::with_name("heartbeat-interval-ms")
+                .long("heartbeat-interval-ms")
+                .help("How often, in milliseconds, the server pings the client to check it's still connected.")
+                .default_value("100")
+                .takes_value(true)
+                .value_name("MILLISECONDS")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("client-timeout-ms")
+                .long("client-timeout-ms")
+                .help("How long, in milliseconds, the server waits for a client response before considering the connection dropped.")
+                .default_value("500")
+                .takes_value(true)
+                .value_name("MILLISECONDS")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("persistent")
+                .long("persistent")
+                .help("Keep the server running after a client disconnects, instead of exiting once the single session it was started for ends.")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("outbound-batch-max-rows")
+                .long("outbound-batch-max-rows")
+                .help("The maximum number of rows sent to the client in a single batch.")
+                .default_value("1000")
+                .takes_value(true)
+                .value_name("ROWS")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("outbound-batch-max-bytes")
+                .long("outbound-batch-max-bytes")
+                .help("The number of raw cell bytes, before encoding, at which a batch is sent to the client.")
+                .default_value("65536")
+                .takes_value(true)
+                .value_name("BYTES")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("outbound-flush-interval-ms")
+                .long("outbound-flush-interval-ms")
+                .help("How long, in milliseconds, to wait between outbound batches while more rows remain queued.")
+                .default_value("100")
+                .takes_value(true)
+                .value_name("MILLISECONDS")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("session-dir")
+                .long("session-dir")
+                .help("Where to persist session state for reconnecting clients to resume. Defaults to a vawk-sessions directory under the OS temp dir.")
+                .takes_value(true)
+                .value_name("DIRECTORY")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("session-ttl-secs")
+                .long("session-ttl-secs")
+                .help("How long, in seconds, a persisted session is kept around for a reconnecting client before it's evicted as stale.")
+                .default_value("3600")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("col-sep")
+                .long("col-sep")
+                .help("A column separator, e.g. \",\" or \"\\\\t\". Can be given more than once to split on any of several separators.")
+                .takes_value(true)
+                .value_name("SEPARATOR")
+                .multiple(true)
+                .number_of_values(1)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("row-sep")
+                .long("row-sep")
+                .help("A row separator, e.g. \"\\\\n\". Can be given more than once to split on any of several separators.")
+                .takes_value(true)
+                .value_name("SEPARATOR")
+                .multiple(true)
+                .number_of_values(1)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("regex")
+                .long("regex")
+                .help("Only keep rows whose raw text matches this regex, same as the GUI's row regex filter.")
+                .takes_value(true)
+                .value_name("REGEX")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("fields")
+                .long("fields")
+                .help("Only keep columns matching these index rules, e.g. \"0, 2..4\" - same syntax as the GUI's column index filter.")
+                .takes_value(true)
+                .value_name("RULES")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("no-gui")
+                .long("no-gui")
+                .help("Skip the GUI and HTTP server entirely; transform stdin with --col-sep/--row-sep/--regex/--fields and write the result to stdout, like a pipe filter.")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("tls")
+                .long("tls")
+                .help("Serve the GUI and websocket over HTTPS instead of HTTP. Requires --cert and --key.")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("cert")
+                .long("cert")
+                .help("Path to a PEM-encoded TLS certificate chain, used when --tls is set.")
+                .takes_value(true)
+                .value_name("PATH")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .help("Path to the PEM-encoded PKCS#8 or PKCS#1 (RSA) private key matching --cert, used when --tls is set.")
+                .takes_value(true)
+                .value_name("PATH")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("file")
+                .long("file")
+                .help("Read command input from this file instead of stdin.")
+                .takes_value(true)
+                .value_name("PATH")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("Reload --file and push the refreshed table to every connected client whenever it changes on disk. Requires --file.")
+                .takes_value(false)
+                .required(false),
+        )
         .get_matches();
     let port = matches.value_of("port").unwrap();
+    let heartbeat_interval_ms: u64 = matches
+        .value_of("heartbeat-interval-ms")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| websocket_connection::DEFAULT_HEARTBEAT_INTERVAL.as_millis() as u64);
+    let client_timeout_ms: u64 = matches
+        .value_of("client-timeout-ms")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| websocket_connection::DEFAULT_CLIENT_TIMEOUT.as_millis() as u64);
+    let shutdown_mode = if matches.is_present("persistent") {
+        websocket_connection::ShutdownMode::Persistent
+    } else {
+        websocket_connection::ShutdownMode::SingleSession
+    };
+    let outbound_batch_max_rows: usize = matches
+        .value_of("outbound-batch-max-rows")
+        .unwrap()
+        .parse()
+        .unwrap_or(websocket_connection::DEFAULT_OUTBOUND_BATCH_MAX_ROWS);
+    let outbound_batch_max_bytes: usize = matches
+        .value_of("outbound-batch-max-bytes")
+        .unwrap()
+        .parse()
+        .unwrap_or(websocket_connection::DEFAULT_OUTBOUND_BATCH_MAX_BYTES);
+    let outbound_flush_interval_ms: u64 = matches
+        .value_of("outbound-flush-interval-ms")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| websocket_connection::DEFAULT_OUTBOUND_FLUSH_INTERVAL.as_millis() as u64);
+    let session_dir = matches
+        .value_of("session-dir")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(sessions::default_session_dir);
+    let session_ttl_secs: u64 = matches
+        .value_of("session-ttl-secs")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| sessions::DEFAULT_SESSION_TTL.as_secs());
+
+    let mut column_options = transformers::Options::default();
+    let mut row_options = transformers::Options::default();
+
+    if let Some(values) = matches.values_of("col-sep") {
+        let separators: Vec<String> = values.map(|value| value.to_owned()).collect();
+        match parsers::parse_field_separators(&separators) {
+            Ok(parsed) => column_options.separators = Some(parsed),
+            Err(error) => log::error!("Ignoring invalid --col-sep:\n{}", error),
+        }
+    }
+    if let Some(values) = matches.values_of("row-sep") {
+        let separators: Vec<String> = values.map(|value| value.to_owned()).collect();
+        match parsers::parse_field_separators(&separators) {
+            Ok(parsed) => row_options.separators = Some(parsed),
+            Err(error) => log::error!("Ignoring invalid --row-sep:\n{}", error),
+        }
+    }
+    if let Some(fields) = matches.value_of("fields") {
+        match parsers::parse_index_filters(fields) {
+            Ok(parsed) => column_options.index_filters = Some(parsed),
+            Err(error) => log::error!("Ignoring invalid --fields:\n{}", error),
+        }
+    }
+    if let Some(regex) = matches.value_of("regex") {
+        match parsers::parse_regex(regex) {
+            Ok(parsed) => row_options.regex_filter = Some(parsed),
+            Err(error) => log::error!("Ignoring invalid --regex:\n{}", error),
+        }
+    }
+
+    if matches.is_present("no-gui") {
+        // Still only writes its one output once stdin is exhausted, but feeds it through in
+        // bounded chunks via `StreamSplitter`. With `--row-sep` given, rows are split as they
+        // arrive rather than buffering the whole input up front, so a multi-gigabyte log doesn't
+        // have to fit in memory twice (once raw, once split); without it, the splitter buffers
+        // the input until EOF.
+        let mut splitter = transformers::StreamSplitter::new(column_options, row_options);
+        let mut stdin = io::stdin();
+        let mut chunk = [0u8; STDIN_READ_CHUNK_SIZE];
+
+        loop {
+            match stdin.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => splitter.feed(&chunk[..n]),
+                Err(error) => {
+                    log::error!("Failed to read command input:\n{}", error);
+                    break;
+                }
+            }
+        }
 
-    let mut stdin = vec![];
-    if let Err(error) = io::stdin().read_to_end(&mut stdin) {
-        log::error!("Failed to read command input:\n{}", error);
+        match transformers::encode_csv(&splitter.finish()) {
+            Ok(output) => {
+                if let Err(error) = io::stdout().write_all(&output) {
+                    log::error!("Failed to write transformed output:\n{}", error);
+                }
+            }
+            Err(error) => log::error!("Failed to transform command input:\n{}", error),
+        }
+        return;
     }
 
+    // Unlike `no-gui`, the GUI should be usable before stdin reaches EOF, so hand reading it off
+    // to a background thread instead of blocking here - unless `--file` points it at a file
+    // instead, in which case there's nothing to wait on, and `--watch` takes over keeping it
+    // current.
+    let (stdin, file_watcher) = match matches.value_of("file") {
+        Some(path) => {
+            let path = std::path::PathBuf::from(path);
+            let contents = std::fs::read(&path).unwrap_or_else(|error| {
+                log::error!("Failed to read --file {}:\n{}", path.display(), error);
+                vec![]
+            });
+            let stdin = Arc::new(Mutex::new(contents));
+
+            let file_watcher = if matches.is_present("watch") {
+                Some(file_watch::FileWatcher::new(path, Arc::clone(&stdin)).start())
+            } else {
+                None
+            };
+
+            (stdin, file_watcher)
+        }
+        None => {
+            if matches.is_present("watch") {
+                log::error!("--watch has no effect without --file; ignoring it");
+            }
+
+            let stdin = Arc::new(Mutex::new(vec![]));
+            spawn_stdin_reader(Arc::clone(&stdin));
+            (stdin, None)
+        }
+    };
+
+    // A failed or partial --tls must never silently downgrade to plaintext HTTP - that defeats
+    // the entire point of asking for TLS in the first place - so any problem here is a hard exit
+    // rather than a fallback to `None`.
+    let tls_config = if matches.is_present("tls") {
+        match (matches.value_of("cert"), matches.value_of("key")) {
+            (Some(cert_path), Some(key_path)) => match load_tls_config(cert_path, key_path) {
+                Ok(config) => Some(config),
+                Err(error) => {
+                    log::error!("Failed to load --cert/--key:\n{}", error);
+                    std::process::exit(1);
+                }
+            },
+            _ => {
+                log::error!("--tls requires both --cert and --key");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
     let socket_address = format!("127.0.0.1:{}", port);
+    let websocket_connection_config = websocket_connection::WebsocketConnectionConfig {
+        heartbeat_interval: Duration::from_millis(heartbeat_interval_ms),
+        client_timeout: Duration::from_millis(client_timeout_ms),
+        shutdown_mode,
+        outbound_batch_max_rows,
+        outbound_batch_max_bytes,
+        outbound_flush_interval: Duration::from_millis(outbound_flush_interval_ms),
+    };
 
-    if let Err(error) = run_server(stdin, &socket_address).await {
+    if let Err(error) = run_server(
+        stdin,
+        file_watcher,
+        &socket_address,
+        websocket_connection_config,
+        session_dir,
+        Duration::from_secs(session_ttl_secs),
+        column_options,
+        row_options,
+        tls_config,
+    )
+    .await
+    {
         log::error!("Failed to start server:\n{}", error);
     }
 }
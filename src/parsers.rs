@@ -0,0 +1,400 @@
+use crate::byte_trie::ByteTrie;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take};
+use nom::character::complete::{digit1, space0, space1};
+use nom::combinator::{self, rest, value};
+use nom::multi::many0;
+use nom::sequence::{delimited, preceded, separated_pair, terminated, tuple};
+use nom::Finish;
+use nom::IResult;
+use regex::bytes::Regex;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub struct InvalidFieldSeparatorError(String);
+
+impl fmt::Display for InvalidFieldSeparatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not parse a field separator, starting at: {}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidIndexFiltersError(String);
+
+impl fmt::Display for InvalidIndexFiltersError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not parse index filters, starting at: {}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidRegexError(String);
+
+impl fmt::Display for InvalidRegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not parse a regex: {}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidValueFilterError(String);
+
+impl fmt::Display for InvalidValueFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not parse a value filter, starting at: {}", self.0)
+    }
+}
+
+/*********************************************************************************************************************
+ * Rules for separating data                                                                                         *
+ *                                                                                                                   *
+ * Users can choose how they want to split up their result set into lines or columns.                                *
+ * The UX is patterned after Unix's IFS (Internal Field Separator), since it will be familiar to users of the tool.  *
+ * Users can give any number of separators, one per string; all the characters of one string (escapes                *
+ * included) form a single separator.  However, just an empty string is not treated as a separator, to avoid         *
+ * garbled-looking output.                                                                                           *
+ *********************************************************************************************************************/
+
+/// escaped_field_separator handles getting escaped characters from a user-input separator string.
+/// It will treat "\\n", "\\t", "\\r", and "\\s" as the literal characters '\n', '\t', '\r', and ' '.
+fn escaped_field_separator(input: &str) -> IResult<&str, u8> {
+    alt((
+        value(b'\n', tag("\\n")),
+        value(b'\t', tag("\\t")),
+        value(b'\r', tag("\\r")),
+        value(b' ', tag("\\s")),
+    ))(input)
+}
+
+fn field_separator<'a>(input: &'a str, byte_trie: &mut ByteTrie) -> IResult<&'a str, ()> {
+    combinator::map(
+        many0(alt((
+            combinator::map(escaped_field_separator, |byte| vec![byte]),
+            combinator::map(take(1usize), |s: &str| s.bytes().collect::<Vec<u8>>()),
+        ))),
+        |mut chars: Vec<Vec<u8>>| {
+            let mut combined = vec![];
+            for char_bytes in chars.iter_mut() {
+                combined.append(char_bytes);
+            }
+
+            byte_trie.insert(&combined);
+        },
+    )(input)
+}
+
+/// Parses field separators from a string.
+pub fn parse_field_separators(
+    string_representations: &[String],
+) -> Result<ByteTrie, InvalidFieldSeparatorError> {
+    let mut separators = ByteTrie::new();
+
+    for string_representation in string_representations {
+        match field_separator(string_representation, &mut separators).finish() {
+            Err(error) => return Err(InvalidFieldSeparatorError(error.input.to_owned())),
+            Ok((unconsumed_input, _))
+                if separators.is_empty() && !unconsumed_input.is_empty() =>
+            {
+                return Err(InvalidFieldSeparatorError(unconsumed_input.to_owned()))
+            }
+            _ => (),
+        }
+    }
+
+    Ok(separators)
+}
+
+/*********************************************************************************************************************
+ * Rules for including or excluding data                                                                             *
+ *                                                                                                                   *
+ * There are two ways to spell out that you only want certain strings to be included or excluded in the result set.  *
+ * They are:                                                                                                         *
+ * - By index; users can say that they want a particular index, or indices within a range, or some combination.      *
+ * - By regex; users can say that they only want lines matching a particular regex.                                  *
+ *********************************************************************************************************************/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexFilter {
+    Bounded(usize, usize),
+    LowerBounded(usize),
+    UpperBounded(usize),
+    Exact(usize),
+}
+
+impl IndexFilter {
+    pub fn is_match(&self, i: usize) -> bool {
+        match self {
+            IndexFilter::Bounded(lower, upper) => i >= *lower && i < *upper,
+            IndexFilter::LowerBounded(lower) => i >= *lower,
+            IndexFilter::UpperBounded(upper) => i < *upper,
+            IndexFilter::Exact(j) => i == *j,
+        }
+    }
+}
+
+fn index(input: &str) -> IResult<&str, usize> {
+    combinator::map(digit1, |s: &str| usize::from_str(s).unwrap())(input)
+}
+
+fn bounded(input: &str) -> IResult<&str, IndexFilter> {
+    combinator::map(separated_pair(index, tag(".."), index), |(lower, upper)| {
+        IndexFilter::Bounded(lower, upper)
+    })(input)
+}
+
+fn lower_bounded(input: &str) -> IResult<&str, IndexFilter> {
+    combinator::map(terminated(index, tag("..")), |lower| {
+        IndexFilter::LowerBounded(lower)
+    })(input)
+}
+
+fn upper_bounded(input: &str) -> IResult<&str, IndexFilter> {
+    combinator::map(preceded(tag(".."), index), |upper| {
+        IndexFilter::UpperBounded(upper)
+    })(input)
+}
+
+fn exact(input: &str) -> IResult<&str, IndexFilter> {
+    combinator::map(index, |i| IndexFilter::Exact(i))(input)
+}
+
+fn index_filter(input: &str) -> IResult<&str, IndexFilter> {
+    alt((bounded, lower_bounded, upper_bounded, exact))(input)
+}
+
+fn index_filter_separator(input: &str) -> IResult<&str, ()> {
+    combinator::map(delimited(space0, tag(","), space0), |_| ())(input)
+}
+
+/// Parses index filters that a user inputs.
+///
+/// This parses 4 types of index filters:
+/// 1. Exact: "4" matches the row with the index of "4".
+/// 2. Bounded: "6..10" matches rows where the index is >= 6 and < 10.
+/// 3. Lower bounded: "5.." matches rows where the index is >= 5.
+/// 4. Upper bounded: "..96" matches rows where the index is < 96.
+fn index_filters(input: &str) -> IResult<&str, Vec<IndexFilter>> {
+    delimited(
+        space0,
+        many0(alt((
+            combinator::map(tuple((index_filter, index_filter_separator)), |(r, _)| r),
+            index_filter,
+        ))),
+        space0,
+    )(input)
+}
+
+pub fn parse_index_filters(
+    string_representation: &str,
+) -> Result<Vec<IndexFilter>, InvalidIndexFiltersError> {
+    match index_filters(string_representation).finish() {
+        Err(error) => Err(InvalidIndexFiltersError(error.input.to_owned())),
+        Ok((unconsumed_input, rules)) if rules.is_empty() && !unconsumed_input.is_empty() => {
+            Err(InvalidIndexFiltersError(unconsumed_input.to_owned()))
+        }
+        Ok((_, rules)) => Ok(rules),
+    }
+}
+
+pub fn parse_regex(string_representation: &str) -> Result<Regex, InvalidRegexError> {
+    Regex::new(string_representation).map_err(|error| InvalidRegexError(format!("{}", error)))
+}
+
+/*********************************************************************************************************************
+ * Rules for typed value comparisons                                                                                 *
+ *                                                                                                                   *
+ * On top of index and regex filters, users can write a small comparison expression against a single field, e.g.    *
+ * "2 >= 100", "3 ~= ^foo", or "1 nonempty". These fold into the same And/Or combination as the other filter kinds.   *
+ *********************************************************************************************************************/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComparisonOperator {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+pub enum ValuePredicate {
+    Comparison(ComparisonOperator, String),
+    RegexMatch(Regex),
+    NonEmpty,
+}
+
+#[derive(Debug, Clone)]
+pub struct ValueFilter {
+    pub field_index: usize,
+    pub predicate: ValuePredicate,
+}
+
+impl ValueFilter {
+    /// Evaluates the predicate against a field's raw bytes, comparing numerically when both
+    /// sides parse as `f64` and falling back to a string comparison otherwise.
+    pub fn is_match(&self, field: &[u8]) -> bool {
+        match &self.predicate {
+            ValuePredicate::NonEmpty => !String::from_utf8_lossy(field).trim().is_empty(),
+            ValuePredicate::RegexMatch(regex) => regex.is_match(field),
+            ValuePredicate::Comparison(operator, operand) => {
+                let field_str = String::from_utf8_lossy(field);
+                let ordering = match (field_str.trim().parse::<f64>(), operand.trim().parse::<f64>()) {
+                    (Ok(field_value), Ok(operand_value)) => field_value.partial_cmp(&operand_value),
+                    _ => Some(field_str.as_ref().cmp(operand.as_str())),
+                };
+
+                match ordering {
+                    None => false,
+                    Some(ordering) => match operator {
+                        ComparisonOperator::Eq => ordering == Ordering::Equal,
+                        ComparisonOperator::Ne => ordering != Ordering::Equal,
+                        ComparisonOperator::Lt => ordering == Ordering::Less,
+                        ComparisonOperator::Gt => ordering == Ordering::Greater,
+                        ComparisonOperator::Le => ordering != Ordering::Greater,
+                        ComparisonOperator::Ge => ordering != Ordering::Less,
+                    },
+                }
+            }
+        }
+    }
+}
+
+enum RawOperator {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    RegexMatch,
+    NonEmpty,
+}
+
+fn raw_value_filter(input: &str) -> IResult<&str, (usize, RawOperator, String)> {
+    let (input, _) = space0(input)?;
+    let (input, field_index) = index(input)?;
+    let (input, _) = space1(input)?;
+
+    alt((
+        combinator::map(tag("nonempty"), move |_| {
+            (field_index, RawOperator::NonEmpty, String::new())
+        }),
+        combinator::map(
+            tuple((
+                alt((
+                    value(RawOperator::Eq, tag("==")),
+                    value(RawOperator::Ne, tag("!=")),
+                    value(RawOperator::Le, tag("<=")),
+                    value(RawOperator::Ge, tag(">=")),
+                    value(RawOperator::Lt, tag("<")),
+                    value(RawOperator::Gt, tag(">")),
+                    value(RawOperator::RegexMatch, tag("~=")),
+                )),
+                space0,
+                rest,
+            )),
+            move |(operator, _, operand): (RawOperator, &str, &str)| {
+                (field_index, operator, operand.trim().to_owned())
+            },
+        ),
+    ))(input)
+}
+
+/// Parses a value filter expression of the form `<field-index> <op> <operand>`, where `op` is
+/// one of `==`, `!=`, `<`, `>`, `<=`, `>=`, `~=`, or the unary `nonempty`.
+pub fn parse_value_filter(string_representation: &str) -> Result<ValueFilter, InvalidValueFilterError> {
+    match raw_value_filter(string_representation).finish() {
+        Err(error) => Err(InvalidValueFilterError(error.input.to_owned())),
+        Ok((unconsumed_input, _)) if !unconsumed_input.is_empty() => {
+            Err(InvalidValueFilterError(unconsumed_input.to_owned()))
+        }
+        Ok((_, (field_index, RawOperator::NonEmpty, _))) => Ok(ValueFilter {
+            field_index,
+            predicate: ValuePredicate::NonEmpty,
+        }),
+        Ok((_, (field_index, RawOperator::RegexMatch, operand))) => Ok(ValueFilter {
+            field_index,
+            predicate: ValuePredicate::RegexMatch(
+                Regex::new(&operand).map_err(|error| InvalidValueFilterError(format!("{}", error)))?,
+            ),
+        }),
+        Ok((_, (field_index, RawOperator::Eq, operand))) => Ok(ValueFilter {
+            field_index,
+            predicate: ValuePredicate::Comparison(ComparisonOperator::Eq, operand),
+        }),
+        Ok((_, (field_index, RawOperator::Ne, operand))) => Ok(ValueFilter {
+            field_index,
+            predicate: ValuePredicate::Comparison(ComparisonOperator::Ne, operand),
+        }),
+        Ok((_, (field_index, RawOperator::Lt, operand))) => Ok(ValueFilter {
+            field_index,
+            predicate: ValuePredicate::Comparison(ComparisonOperator::Lt, operand),
+        }),
+        Ok((_, (field_index, RawOperator::Gt, operand))) => Ok(ValueFilter {
+            field_index,
+            predicate: ValuePredicate::Comparison(ComparisonOperator::Gt, operand),
+        }),
+        Ok((_, (field_index, RawOperator::Le, operand))) => Ok(ValueFilter {
+            field_index,
+            predicate: ValuePredicate::Comparison(ComparisonOperator::Le, operand),
+        }),
+        Ok((_, (field_index, RawOperator::Ge, operand))) => Ok(ValueFilter {
+            field_index,
+            predicate: ValuePredicate::Comparison(ComparisonOperator::Ge, operand),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::byte_trie::ByteTrie;
+
+    #[test]
+    fn parse_field_separators() {
+        let mut expected = ByteTrie::new();
+        expected.insert(&[b'\r', b'\n']);
+        match super::parse_field_separators(&["\\r\\n".to_owned()]) {
+            Ok(actual) => assert_eq!(actual, expected),
+            Err(_) => assert!(false),
+        }
+    }
+
+    #[test]
+    fn parse_index_filters() {
+        let expected = vec![
+            super::IndexFilter::Exact(1usize),
+            super::IndexFilter::LowerBounded(5usize),
+        ];
+        match super::parse_index_filters("1, 5..") {
+            Ok(actual) => assert_eq!(actual, expected),
+            Err(_) => assert!(false),
+        }
+    }
+
+    #[test]
+    fn parse_value_filter_numeric_comparison() {
+        let filter = super::parse_value_filter("2 >= 100").unwrap();
+        assert_eq!(filter.field_index, 2);
+        assert!(filter.is_match(b"150"));
+        assert!(!filter.is_match(b"50"));
+    }
+
+    #[test]
+    fn parse_value_filter_nonempty() {
+        let filter = super::parse_value_filter("3 nonempty").unwrap();
+        assert_eq!(filter.field_index, 3);
+        assert!(filter.is_match(b"hi"));
+        assert!(!filter.is_match(b"   "));
+    }
+
+    #[test]
+    fn parse_value_filter_regex() {
+        let filter = super::parse_value_filter("0 ~= ^foo").unwrap();
+        assert!(filter.is_match(b"foobar"));
+        assert!(!filter.is_match(b"barfoo"));
+    }
+}
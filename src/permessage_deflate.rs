@@ -0,0 +1,417 @@
+/// RFC 7692 permessage-deflate support for the websocket server.
+///
+/// `actix-web-actors::ws` parses frames into `ws::Message`s but never exposes (or lets us set)
+/// the RSV1 bit those frames carried, and its `Codec` isn't pluggable - so there's no way to ask
+/// it to compress/decompress on our behalf. Instead, this module sits at the raw byte level on
+/// either side of the stock machinery: `InflatingPayload` rewrites compressed inbound frames
+/// into ordinary ones before the stock codec ever sees them, and `DeflatingFrames` rewrites the
+/// stock codec's outbound frames to compress their payload and set RSV1. Everything else about
+/// a frame - opcode, fragmentation, masking - passes through exactly as the stock codec already
+/// produces or expects it.
+///
+/// Scope is deliberately narrow: only single, unfragmented text/binary frames are compressed.
+/// `websocket_connection`'s own continuation handling already punts on doing anything clever
+/// with malformed fragment sequences, and every message this server actually sends or expects to
+/// receive is a single frame, so reassembling fragments just to recompress them isn't worth the
+/// complexity it would add here.
+use bytes::{Buf, Bytes, BytesMut};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use futures::Stream;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The trailing 4 bytes DEFLATE appends for an empty stored block, which permessage-deflate
+/// strips from the wire before sending and expects re-appended before inflating. See RFC 7692
+/// §7.2.1.
+const EMPTY_DEFLATE_BLOCK: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+
+/// The context-takeover parameters negotiated for one connection, parsed out of the client's
+/// `Sec-WebSocket-Extensions` offer. `client_max_window_bits`/`server_max_window_bits` are
+/// accepted (so an offer naming them isn't rejected) but not enforced, since `flate2` doesn't
+/// expose a way to cap the deflate window independently of the compression level.
+#[derive(Debug, Clone, Copy)]
+pub struct PermessageDeflateParams {
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+}
+
+/// Scans a `Sec-WebSocket-Extensions` header value for a `permessage-deflate` offer and decides
+/// the parameters this server will use, per RFC 7692 §7.1. Returns `None` if the client didn't
+/// offer it, in which case the caller should fall back to an uncompressed connection.
+pub fn negotiate(header_value: &str) -> Option<PermessageDeflateParams> {
+    for offer in header_value.split(',') {
+        let mut parts = offer.split(';').map(|part| part.trim());
+        let name = parts.next()?;
+        if !name.eq_ignore_ascii_case("permessage-deflate") {
+            continue;
+        }
+
+        let mut params = PermessageDeflateParams {
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+        };
+
+        for param in parts {
+            let key = param.split('=').next().unwrap_or("").trim();
+            if key.eq_ignore_ascii_case("server_no_context_takeover") {
+                params.server_no_context_takeover = true;
+            } else if key.eq_ignore_ascii_case("client_no_context_takeover") {
+                params.client_no_context_takeover = true;
+            }
+        }
+
+        return Some(params);
+    }
+
+    None
+}
+
+/// Builds this server's `Sec-WebSocket-Extensions` response value, echoing back only the
+/// parameters it actually honors.
+pub fn response_header(params: &PermessageDeflateParams) -> String {
+    let mut value = String::from("permessage-deflate");
+    if params.server_no_context_takeover {
+        value.push_str("; server_no_context_takeover");
+    }
+    if params.client_no_context_takeover {
+        value.push_str("; client_no_context_takeover");
+    }
+
+    value
+}
+
+#[derive(Debug)]
+pub struct InflateError(String);
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to inflate a permessage-deflate frame: {}", self.0)
+    }
+}
+
+/// Compresses outbound message payloads with raw DEFLATE (no zlib header, per RFC 7692
+/// §7.2.2), carrying the compressor's dictionary forward across messages unless
+/// `no_context_takeover` was negotiated.
+struct Deflater {
+    compress: Compress,
+    no_context_takeover: bool,
+}
+
+impl Deflater {
+    fn new(no_context_takeover: bool) -> Self {
+        Self {
+            compress: Compress::new(Compression::default(), false),
+            no_context_takeover,
+        }
+    }
+
+    fn deflate(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(payload.len());
+        self.compress
+            .compress_vec(payload, &mut output, FlushCompress::Sync)
+            .expect("in-memory deflate compression cannot fail");
+
+        if output.ends_with(&EMPTY_DEFLATE_BLOCK) {
+            output.truncate(output.len() - EMPTY_DEFLATE_BLOCK.len());
+        }
+
+        if self.no_context_takeover {
+            self.compress.reset();
+        }
+
+        output
+    }
+}
+
+/// Reverses `Deflater`: re-appends the empty-block marker the sender stripped, then inflates,
+/// carrying the decompressor's dictionary forward across messages unless `no_context_takeover`
+/// was negotiated.
+struct Inflater {
+    decompress: Decompress,
+    no_context_takeover: bool,
+}
+
+impl Inflater {
+    fn new(no_context_takeover: bool) -> Self {
+        Self {
+            decompress: Decompress::new(false),
+            no_context_takeover,
+        }
+    }
+
+    fn inflate(&mut self, payload: &[u8]) -> Result<Vec<u8>, InflateError> {
+        let mut input = Vec::with_capacity(payload.len() + EMPTY_DEFLATE_BLOCK.len());
+        input.extend_from_slice(payload);
+        input.extend_from_slice(&EMPTY_DEFLATE_BLOCK);
+
+        let mut output = Vec::with_capacity(payload.len() * 4);
+        self.decompress
+            .decompress_vec(&input, &mut output, FlushDecompress::Sync)
+            .map_err(|error| InflateError(error.to_string()))?;
+
+        if self.no_context_takeover {
+            self.decompress.reset(false);
+        }
+
+        Ok(output)
+    }
+}
+
+/// One parsed RFC 6455 frame header, along with where its payload starts and ends in the buffer
+/// it was parsed from.
+struct ParsedFrame {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    masked: bool,
+    mask_key: [u8; 4],
+    header_len: usize,
+    payload_len: usize,
+}
+
+/// Parses a single frame header out of the front of `buf`, returning `None` if `buf` doesn't yet
+/// contain a complete header and payload - the caller should buffer more bytes and retry.
+fn parse_frame(buf: &[u8]) -> Option<ParsedFrame> {
+    if buf.len() < 2 {
+        return None;
+    }
+
+    let first = buf[0];
+    let second = buf[1];
+    let fin = first & 0x80 != 0;
+    let rsv1 = first & 0x40 != 0;
+    let opcode = first & 0x0f;
+    let masked = second & 0x80 != 0;
+    let base_len = (second & 0x7f) as usize;
+
+    let (payload_len, mut offset) = match base_len {
+        126 => {
+            if buf.len() < 4 {
+                return None;
+            }
+            (u16::from_be_bytes([buf[2], buf[3]]) as usize, 4)
+        }
+        127 => {
+            if buf.len() < 10 {
+                return None;
+            }
+            let mut len_bytes = [0u8; 8];
+            len_bytes.copy_from_slice(&buf[2..10]);
+            (u64::from_be_bytes(len_bytes) as usize, 10)
+        }
+        n => (n as usize, 2),
+    };
+
+    let mut mask_key = [0u8; 4];
+    if masked {
+        if buf.len() < offset + 4 {
+            return None;
+        }
+        mask_key.copy_from_slice(&buf[offset..offset + 4]);
+        offset += 4;
+    }
+
+    if buf.len() < offset + payload_len {
+        return None;
+    }
+
+    Some(ParsedFrame {
+        fin,
+        rsv1,
+        opcode,
+        masked,
+        mask_key,
+        header_len: offset,
+        payload_len,
+    })
+}
+
+fn apply_mask(payload: &mut [u8], key: [u8; 4]) {
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= key[index % 4];
+    }
+}
+
+/// Rebuilds a frame header for a payload of `payload_len` bytes, forcing RSV1 to `rsv1` and
+/// setting the mask bit iff `masked`. Doesn't write the mask key itself; the caller appends that
+/// (and the already-masked payload) separately.
+fn build_header(fin: bool, rsv1: bool, opcode: u8, masked: bool, payload_len: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(10);
+    let mut first = opcode & 0x0f;
+    if fin {
+        first |= 0x80;
+    }
+    if rsv1 {
+        first |= 0x40;
+    }
+    header.push(first);
+
+    let mask_bit = if masked { 0x80 } else { 0x00 };
+    if payload_len <= 125 {
+        header.push(mask_bit | payload_len as u8);
+    } else if payload_len <= u16::MAX as usize {
+        header.push(mask_bit | 126);
+        header.extend_from_slice(&(payload_len as u16).to_be_bytes());
+    } else {
+        header.push(mask_bit | 127);
+        header.extend_from_slice(&(payload_len as u64).to_be_bytes());
+    }
+
+    header
+}
+
+/// Wraps the raw inbound byte stream (what would otherwise be handed straight to
+/// `ws::WebsocketContext::create`), inflating any RSV1-marked text/binary frame and clearing its
+/// RSV1 bit before the stock codec sees it - so, as far as that codec is concerned, every frame
+/// arriving from the client looks uncompressed.
+pub struct InflatingPayload<S> {
+    inner: Pin<Box<S>>,
+    buf: BytesMut,
+    inflater: Inflater,
+}
+
+impl<S> InflatingPayload<S> {
+    pub fn new(inner: S, params: PermessageDeflateParams) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            buf: BytesMut::new(),
+            inflater: Inflater::new(params.client_no_context_takeover),
+        }
+    }
+}
+
+impl<S, E> Stream for InflatingPayload<S>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(frame) = parse_frame(&this.buf) {
+                let payload_start = frame.header_len;
+                let payload_end = frame.header_len + frame.payload_len;
+                let mut payload = this.buf[payload_start..payload_end].to_vec();
+                if frame.masked {
+                    apply_mask(&mut payload, frame.mask_key);
+                }
+
+                let is_data_frame = frame.opcode == OPCODE_TEXT || frame.opcode == OPCODE_BINARY;
+                let decompressed = if frame.rsv1 && is_data_frame {
+                    match this.inflater.inflate(&payload) {
+                        Ok(decompressed) => decompressed,
+                        Err(error) => {
+                            this.buf.advance(payload_end);
+                            return Poll::Ready(Some(Err(io_error_to_item::<E>(error))));
+                        }
+                    }
+                } else {
+                    payload
+                };
+
+                let mut rebuilt = build_header(
+                    frame.fin,
+                    false,
+                    frame.opcode,
+                    frame.masked,
+                    decompressed.len(),
+                );
+                let mut masked_payload = decompressed;
+                if frame.masked {
+                    apply_mask(&mut masked_payload, frame.mask_key);
+                    rebuilt.extend_from_slice(&frame.mask_key);
+                }
+                rebuilt.extend_from_slice(&masked_payload);
+
+                this.buf.advance(payload_end);
+                return Poll::Ready(Some(Ok(Bytes::from(rebuilt))));
+            }
+
+            match this.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.buf.extend_from_slice(&chunk),
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Some(Err(error))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// `InflatingPayload`'s item error type is whatever the wrapped stream's is (`PayloadError` in
+/// practice), which an `InflateError` can't be converted into directly; this is only reachable
+/// if a client claims RSV1 but sends bytes that aren't valid DEFLATE, so we just close the
+/// connection rather than threading a new error variant through `actix_web::error::PayloadError`.
+fn io_error_to_item<E>(error: InflateError) -> E
+where
+    E: From<io::Error>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string()).into()
+}
+
+/// Wraps the raw outbound byte stream produced by `ws::WebsocketContext::create`, compressing
+/// every unfragmented text/binary frame's payload and setting its RSV1 bit. Control frames (ping/pong/close)
+/// pass through untouched, per RFC 7692 §5.
+pub struct DeflatingFrames<S> {
+    inner: Pin<Box<S>>,
+    buf: BytesMut,
+    deflater: Deflater,
+}
+
+impl<S> DeflatingFrames<S> {
+    pub fn new(inner: S, params: PermessageDeflateParams) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            buf: BytesMut::new(),
+            deflater: Deflater::new(params.server_no_context_takeover),
+        }
+    }
+}
+
+impl<S, E> Stream for DeflatingFrames<S>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(frame) = parse_frame(&this.buf) {
+                let payload_start = frame.header_len;
+                let payload_end = frame.header_len + frame.payload_len;
+                // Server frames are never masked (RFC 6455 §5.1), so the payload here is
+                // already in the clear.
+                let payload = &this.buf[payload_start..payload_end];
+
+                let is_data_frame = frame.opcode == OPCODE_TEXT || frame.opcode == OPCODE_BINARY;
+                let (rsv1, output_payload) = if is_data_frame && frame.fin {
+                    (true, this.deflater.deflate(payload))
+                } else {
+                    (false, payload.to_vec())
+                };
+
+                let mut rebuilt =
+                    build_header(frame.fin, rsv1, frame.opcode, false, output_payload.len());
+                rebuilt.extend_from_slice(&output_payload);
+
+                this.buf.advance(payload_end);
+                return Poll::Ready(Some(Ok(Bytes::from(rebuilt))));
+            }
+
+            match this.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.buf.extend_from_slice(&chunk),
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Some(Err(error))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
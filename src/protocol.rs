@@ -0,0 +1,130 @@
+/// The websocket wire protocol: a self-describing, versioned JSON envelope in both directions,
+/// replacing the raw protobuf bytes `handle_message` used to dispatch on directly.
+///
+/// Every message - in either direction - carries a `protocol_version` so a client and server
+/// built against different revisions of this module can at least recognize the mismatch instead
+/// of misinterpreting each other's frames.
+use crate::transformers;
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever a variant is added, removed, or has its fields changed in a way that isn't
+/// backwards compatible. Clients should compare this against their own expected version and
+/// surface a diagnostic rather than guessing at the server's intent.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize)]
+pub struct Initialize {
+    #[serde(default)]
+    pub session_id: String,
+    #[serde(default)]
+    pub prior_session_id: String,
+    #[serde(default)]
+    pub row_separators: String,
+    #[serde(default)]
+    pub row_regex_separator: String,
+    #[serde(default)]
+    pub row_index_filters: String,
+    #[serde(default)]
+    pub row_regex_filter: String,
+    #[serde(default)]
+    pub row_filter_combination: Option<transformers::Combination>,
+    #[serde(default)]
+    pub column_separators: String,
+    #[serde(default)]
+    pub column_regex_separator: String,
+    #[serde(default)]
+    pub column_index_filters: String,
+    #[serde(default)]
+    pub column_regex_filter: String,
+    #[serde(default)]
+    pub column_filter_combination: Option<transformers::Combination>,
+}
+
+/// A command sent by the client, tagged on the wire by its `type` field.
+///
+/// These variants mirror the setters `WebsocketConnection` already exposed over protobuf; the
+/// wire format changed, the domain operations didn't.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum ClientMsg {
+    Initialize(Initialize),
+    SetColumnIndexFilters { filters: String },
+    SetColumnRegexFilter { filter: String },
+    SetColumnFilterCombination { combination: Option<transformers::Combination> },
+    SetColumnSeparators { separators: String },
+    SetColumnRegexSeparator { separator: String },
+    SetRowIndexFilters { filters: String },
+    SetRowRegexFilter { filter: String },
+    SetRowFilterCombination { combination: Option<transformers::Combination> },
+    SetRowSeparators { separators: String },
+    SetRowRegexSeparator { separator: String },
+    SetColumnSort { keys: Vec<transformers::SortKey> },
+    SetRowSort { keys: Vec<transformers::SortKey> },
+    SetColumnDedup { field_indices: Vec<usize> },
+    SetRowDedup { field_indices: Vec<usize> },
+    SetColumnValueFilter { expressions: Vec<String> },
+    SetRowValueFilter { expressions: Vec<String> },
+    SetViewport { row_offset: usize, row_limit: usize },
+    SetOutputFormat { format: transformers::OutputFormat, has_header: bool },
+    SetExplain { enabled: bool },
+}
+
+/// The envelope a client actually sends: a `protocol_version` alongside the tagged command.
+#[derive(Debug, Deserialize)]
+pub struct ClientEnvelope {
+    pub protocol_version: u32,
+    #[serde(flatten)]
+    pub message: ClientMsg,
+}
+
+/// Why a single row was kept or dropped, mirroring `transformers::MatchDecision` but shaped for
+/// the wire: `Option<bool>` becomes a presence flag plus a plain `bool`, since JSON clients would
+/// otherwise have to special-case `null` for "no such filter was configured".
+#[derive(Debug, Serialize)]
+pub struct RowDecision {
+    pub has_index_filter: bool,
+    pub index_filter_matched: bool,
+    pub has_regex_filter: bool,
+    pub regex_filter_matched: bool,
+    pub has_value_filter: bool,
+    pub value_filter_matched: bool,
+    pub kept: bool,
+}
+
+/// A response frame, tagged on the wire by its `type` field.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub enum ServerMsg {
+    /// `csv` holds one outbound batch of the encoded table (CSV/TSV/JSON/Markdown, per
+    /// `SetOutputFormat`), base64 encoded since the underlying bytes aren't guaranteed to be
+    /// valid UTF-8. Large results are split across several `Output` frames rather than sent as
+    /// one giant frame; `is_last` tells the client when it's seen the final batch.
+    Output {
+        csv: String,
+        total_row_count: u64,
+        is_last: bool,
+    },
+    Explanation {
+        row_decisions: Vec<RowDecision>,
+    },
+    Error {
+        description: String,
+    },
+}
+
+/// The envelope every server frame is wrapped in before being sent as a `ws::Message::Text`.
+#[derive(Debug, Serialize)]
+pub struct ServerEnvelope {
+    pub protocol_version: u32,
+    #[serde(flatten)]
+    pub message: ServerMsg,
+}
+
+impl ServerEnvelope {
+    pub fn new(message: ServerMsg) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            message,
+        }
+    }
+}
@@ -0,0 +1,4 @@
+// `build.rs` runs `protoc_rust::Codegen` against `definitions.proto` into this directory at
+// build time, the same way `server`'s build does - see `generate_server_protocol_buffers`.
+#[path = "protos/definitions.rs"]
+pub mod definitions;
@@ -0,0 +1,176 @@
+use crate::transformers;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The default, used when the server is started without an explicit session directory.
+pub fn default_session_dir() -> PathBuf {
+    std::env::temp_dir().join("vawk-sessions")
+}
+
+/// How long a persisted session is kept around before `SessionStore::save` sweeps it away as
+/// stale, used when the server is started without an explicit TTL.
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// A serialization-friendly mirror of `transformers::Options`. `Options` itself isn't
+/// `Serialize`/`Deserialize` - it holds a compiled `Regex` and parsed filter structures - so this
+/// keeps the raw strings the client originally sent instead, to be re-parsed through the usual
+/// `parsers` functions on rehydrate.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PersistedOptions {
+    pub separators: String,
+    pub regex_separator: String,
+    pub regex_filter: String,
+    pub index_filters: String,
+    pub value_filters: Vec<String>,
+    pub filters_combination: Option<transformers::Combination>,
+    pub sort_keys: Vec<transformers::SortKey>,
+    pub dedup_keys: Vec<usize>,
+}
+
+/// The subset of a connection's state needed to resume it after a disconnect: the original
+/// command output and both axes' filter/sort/dedup options, exactly as they stood when the prior
+/// connection's heartbeat timed out or it closed abnormally.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionState {
+    pub stdin: Vec<u8>,
+    pub column_options: PersistedOptions,
+    pub row_options: PersistedOptions,
+}
+
+/// Turns a client-supplied session id into a safe file name: anything that isn't alphanumeric,
+/// `-`, or `_` is replaced, which also rules out path separators and `..` traversal.
+fn file_name_for(session_id: &str) -> String {
+    let sanitized: String = session_id
+        .chars()
+        .map(|character| {
+            if character.is_ascii_alphanumeric() || character == '-' || character == '_' {
+                character
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!("{}.session.json", sanitized)
+}
+
+/// Persists session state to disk as JSON, keyed by the session id the client sent in
+/// `Initialize`, so a reconnecting client can resume its exact filter state - even across a
+/// process restart - instead of re-sending every setter.
+pub struct SessionStore {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl SessionStore {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        if let Err(error) = fs::create_dir_all(&dir) {
+            log::error!(
+                "Failed to create the session directory at {:?}, sessions won't be persisted:\n{}",
+                dir,
+                error
+            );
+        }
+
+        Self { dir, ttl }
+    }
+
+    fn path_for(&self, session_id: &str) -> PathBuf {
+        self.dir.join(file_name_for(session_id))
+    }
+
+    /// Serializes `state` to a temporary file and renames it into place, so a crash or
+    /// concurrent read mid-write can't ever observe a half-written session file.
+    pub fn save(&self, session_id: &str, state: &SessionState) {
+        self.evict_stale();
+
+        let path = self.path_for(session_id);
+        if let Err(error) = Self::write_atomically(&path, state) {
+            log::error!(
+                "Failed to persist session {:?} to {:?}:\n{}",
+                session_id,
+                path,
+                error
+            );
+        }
+    }
+
+    fn write_atomically(path: &Path, state: &SessionState) -> io::Result<()> {
+        let encoded = serde_json::to_vec(state)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+        let mut tmp_path = path.to_path_buf();
+        tmp_path.set_extension("json.tmp");
+
+        fs::write(&tmp_path, encoded)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Removes and returns the session's state, if any and if it hasn't expired. Sessions are
+    /// single-use: once a client rehydrates from one, it's gone.
+    pub fn take(&self, session_id: &str) -> Option<SessionState> {
+        let path = self.path_for(session_id);
+        let expired = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .map_or(false, |age| age > self.ttl);
+        let contents = fs::read(&path).ok()?;
+        let _ = fs::remove_file(&path);
+        if expired {
+            return None;
+        }
+
+        match serde_json::from_slice(&contents) {
+            Ok(state) => Some(state),
+            Err(error) => {
+                log::error!(
+                    "Discarding a session file at {:?} that failed to parse:\n{}",
+                    path,
+                    error
+                );
+                None
+            }
+        }
+    }
+
+    /// Sweeps session files whose last write is older than `ttl`. Run on every `save`, rather
+    /// than on a timer, so an idle server doesn't need a background task just to keep its
+    /// session directory from growing without bound.
+    fn evict_stale(&self) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(error) => {
+                log::error!(
+                    "Failed to read the session directory at {:?} for eviction:\n{}",
+                    self.dir,
+                    error
+                );
+                return;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_stale = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| {
+                    SystemTime::now()
+                        .duration_since(modified)
+                        .map(|age| age > self.ttl)
+                        .unwrap_or(false)
+                })
+                .unwrap_or(false);
+
+            if is_stale {
+                if let Err(error) = fs::remove_file(&path) {
+                    log::error!("Failed to evict stale session file at {:?}:\n{}", path, error);
+                }
+            }
+        }
+    }
+}
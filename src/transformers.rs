@@ -1,22 +1,68 @@
 use crate::byte_trie::{ByteTrie, Membership};
-use crate::parsers::IndexFilter;
+use crate::parsers::{IndexFilter, ValueFilter};
 use csv;
 use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::io;
 
-#[derive(Debug)]
+/// The wire format `send_csvs` serializes a transformed grid of cells into. `Csv` is the default,
+/// kept for clients that predate the other formats.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OutputFormat {
+    Csv,
+    Tsv,
+    Json,
+    MarkdownTable,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Combination {
     And,
     Or,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ComparatorMode {
+    Lexicographic,
+    Numeric,
+}
+
+/// A single key used to order retained rows/columns.
+///
+/// `field_index` is the position within a row (for row sorting) or the position within a
+/// column (for column sorting) of the value to compare.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SortKey {
+    pub field_index: usize,
+    pub order: SortOrder,
+    pub mode: ComparatorMode,
+}
+
+#[derive(Debug, Clone)]
 pub struct Options {
     pub separators: Option<ByteTrie>,
     pub regex_separator: Option<Regex>,
     pub regex_filter: Option<Regex>,
     pub index_filters: Option<Vec<IndexFilter>>,
+    /// Typed comparison predicates, e.g. `2 >= 100`. A predicate only constrains the field index
+    /// it names; indices with no matching predicate are left for the other filter kinds to decide.
+    pub value_filters: Option<Vec<ValueFilter>>,
     pub filters_combination: Option<Combination>,
+    /// Multi-key stable sort applied after filtering. Keys are tried in order; ties fall through
+    /// to the next key, and rows/columns that tie on every key keep their input order.
+    pub sort_keys: Option<Vec<SortKey>>,
+    /// When set, only the first occurrence of each distinct tuple of values at these field
+    /// indices is kept.
+    pub dedup_keys: Option<Vec<usize>>,
 }
 
 impl Options {
@@ -26,9 +72,100 @@ impl Options {
             regex_separator: None,
             regex_filter: None,
             index_filters: None,
+            value_filters: None,
             filters_combination: None,
+            sort_keys: None,
+            dedup_keys: None,
+        }
+    }
+}
+
+fn field_at<'a>(record: &'a Vec<Vec<u8>>, index: usize) -> &'a [u8] {
+    match record.get(index) {
+        Some(field) => field.as_slice(),
+        None => &[],
+    }
+}
+
+/// Parses a field as an `f64` for numeric comparisons, ignoring leading/trailing whitespace.
+fn as_f64(field: &[u8]) -> Option<f64> {
+    std::str::from_utf8(field).ok()?.trim().parse::<f64>().ok()
+}
+
+fn compare_fields(mode: ComparatorMode, a: &[u8], b: &[u8]) -> Ordering {
+    match mode {
+        ComparatorMode::Numeric => match (as_f64(a), as_f64(b)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _ => a.cmp(b),
+        },
+        ComparatorMode::Lexicographic => a.cmp(b),
+    }
+}
+
+/// Keeps only the first occurrence of each distinct tuple of values at `keys`.
+fn dedup_by_keys(keys: &Vec<usize>, records: Vec<Vec<Vec<u8>>>) -> Vec<Vec<Vec<u8>>> {
+    let mut seen: HashSet<Vec<Vec<u8>>> = HashSet::new();
+    let mut result = vec![];
+
+    for record in records {
+        let key: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|&index| field_at(&record, index).to_vec())
+            .collect();
+
+        if seen.insert(key) {
+            result.push(record);
+        }
+    }
+
+    result
+}
+
+/// Stably sorts records by the given keys, so records tying on every key preserve input order.
+fn sort_by_keys(keys: &Vec<SortKey>, mut records: Vec<Vec<Vec<u8>>>) -> Vec<Vec<Vec<u8>>> {
+    records.sort_by(|a, b| {
+        for key in keys {
+            let ordering = compare_fields(key.mode, field_at(a, key.field_index), field_at(b, key.field_index));
+            let ordering = match key.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            };
+
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        Ordering::Equal
+    });
+
+    records
+}
+
+fn dedup_and_sort(options: &Options, records: Vec<Vec<Vec<u8>>>) -> Vec<Vec<Vec<u8>>> {
+    let records = match &options.dedup_keys {
+        Some(keys) => dedup_by_keys(keys, records),
+        None => records,
+    };
+
+    match &options.sort_keys {
+        Some(keys) if !keys.is_empty() => sort_by_keys(keys, records),
+        _ => records,
+    }
+}
+
+/// Swaps rows for columns, padding ragged rows with empty cells so the result is rectangular.
+fn transpose(rows: &Vec<Vec<Vec<u8>>>) -> Vec<Vec<Vec<u8>>> {
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut columns = vec![vec![]; width];
+
+    for row in rows {
+        for i in 0..width {
+            columns[i].push(field_at(row, i).to_vec());
         }
     }
+
+    columns
 }
 
 /// Splits string data into parts according to the given separators.
@@ -69,7 +206,10 @@ fn split(separators: &ByteTrie, data: &Vec<u8>) -> Vec<Vec<u8>> {
     result
 }
 
-fn split_all(options: &Options, data: &Vec<u8>) -> Vec<Vec<u8>> {
+/// Splits raw data into fields according to `options`, without applying any index/regex/value
+/// filters. Exposed for diagnostic tooling that needs to reason about the pre-filter fields (see
+/// `explain_matches`).
+pub fn split_all(options: &Options, data: &Vec<u8>) -> Vec<Vec<u8>> {
     let result = match &options.separators {
         None => vec![data.clone()],
         Some(separators) => split(separators, data),
@@ -114,29 +254,79 @@ fn keep_regex_matches(regex: &Regex, data: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
         .collect()
 }
 
+/// Records, for a single row or column, which filter kinds applied and whether each matched, plus
+/// the final verdict once the And/Or combination was resolved. `None` means that filter kind
+/// wasn't configured (or, for value filters, none of them named this index) rather than that it
+/// failed to match.
+#[derive(Debug, Clone)]
+pub struct MatchDecision {
+    pub index_filter_matched: Option<bool>,
+    pub regex_filter_matched: Option<bool>,
+    pub value_filter_matched: Option<bool>,
+    pub kept: bool,
+}
+
+/// Like `decide`, but takes the field's bytes directly instead of indexing them out of a full
+/// `data` vec - lets `StreamSplitter` decide whether to keep a row as soon as it completes,
+/// without having collected every row first.
+fn decide_field(options: &Options, field: &[u8], i: usize) -> MatchDecision {
+    let index_filter_matched = options
+        .index_filters
+        .as_ref()
+        .map(|index_filters| index_filters.iter().any(|rule| rule.is_match(i)));
+    let regex_filter_matched = options
+        .regex_filter
+        .as_ref()
+        .map(|regex_filter| regex_filter.is_match(field));
+    let value_filter_matched = options.value_filters.as_ref().and_then(|value_filters| {
+        let filters_for_this_index: Vec<&ValueFilter> = value_filters
+            .iter()
+            .filter(|filter| filter.field_index == i)
+            .collect();
+
+        if filters_for_this_index.is_empty() {
+            None
+        } else {
+            Some(
+                filters_for_this_index
+                    .iter()
+                    .all(|filter| filter.is_match(field)),
+            )
+        }
+    });
+
+    let predicates: Vec<bool> = [
+        index_filter_matched,
+        regex_filter_matched,
+        value_filter_matched,
+    ]
+    .iter()
+    .filter_map(|predicate| *predicate)
+    .collect();
+
+    let kept = match (predicates.is_empty(), &options.filters_combination) {
+        (true, _) => true,
+        (false, Some(Combination::Or)) => predicates.iter().any(|&predicate| predicate),
+        (false, _) => predicates.iter().all(|&predicate| predicate),
+    };
+
+    MatchDecision {
+        index_filter_matched,
+        regex_filter_matched,
+        value_filter_matched,
+        kept,
+    }
+}
+
+fn decide(options: &Options, data: &Vec<Vec<u8>>, i: usize) -> MatchDecision {
+    decide_field(options, data[i].as_slice(), i)
+}
+
 fn keep_matches(options: &Options, data: &Vec<Vec<u8>>) -> Vec<Vec<u8>> {
     let mut result = vec![];
 
     for i in 0..data.len() {
-        let should_keep = match (
-            &options.index_filters,
-            &options.regex_filter,
-            &options.filters_combination,
-        ) {
-            (None, None, _) => true,
-            (Some(ref index_filters), None, _) => index_filters.iter().any(|rule| rule.is_match(i)),
-            (None, Some(ref regex_filter), _) => regex_filter.is_match(data[i].as_slice()),
-            (Some(ref index_filters), Some(ref regex_filter), Some(Combination::Or)) => {
-                index_filters.iter().any(|rule| rule.is_match(i))
-                    || regex_filter.is_match(data[i].as_slice())
-            }
-            (Some(ref index_filters), Some(ref regex_filter), _) => {
-                index_filters.iter().any(|rule| rule.is_match(i))
-                    && regex_filter.is_match(data[i].as_slice())
-            }
-        };
-
-        if should_keep {
+        if decide(options, data, i).kept {
             result.push(data[i].clone());
         }
     }
@@ -144,47 +334,312 @@ fn keep_matches(options: &Options, data: &Vec<Vec<u8>>) -> Vec<Vec<u8>> {
     result
 }
 
+/// Like `keep_matches`, but returns a decision record for every row/column instead of discarding
+/// the ones that didn't pass, so callers can explain why a row or column was kept or dropped.
+pub fn explain_matches(options: &Options, data: &Vec<Vec<u8>>) -> Vec<MatchDecision> {
+    (0..data.len()).map(|i| decide(options, data, i)).collect()
+}
+
 fn split_into_records(options: &Options, data: &Vec<u8>) -> Vec<Vec<u8>> {
     keep_matches(options, &split_all(options, data))
 }
 
-pub fn transform_output(
+/// Applies the sort/dedup stage and rectangular padding shared by `transform_rows` and
+/// `StreamSplitter::finish` to an already row-and-cell-split grid.
+fn finish_grid(
+    column_options: &Options,
+    row_options: &Options,
+    rows: Vec<Vec<Vec<u8>>>,
+) -> Vec<Vec<Vec<u8>>> {
+    // Row sort/dedup keys index into each row's cells; column sort/dedup keys index into
+    // each column's cells, so transpose, apply, and transpose back.
+    let rows = dedup_and_sort(row_options, rows);
+    let mut rows = transpose(&dedup_and_sort(column_options, transpose(&rows)));
+    let mut longest_number_of_cells = 0;
+
+    for row in &rows {
+        if row.len() > longest_number_of_cells {
+            longest_number_of_cells = row.len();
+        }
+    }
+
+    for row in &mut rows {
+        // Pad cells so the UI doesn't have to.
+        if row.len() < longest_number_of_cells {
+            for _ in 0..(longest_number_of_cells - row.len()) {
+                row.push(vec![]);
+            }
+        }
+    }
+
+    rows
+}
+
+/// Runs the full filter/sort/dedup pipeline and returns the resulting rectangular grid of cells,
+/// with every row padded out to the width of the widest row.
+///
+/// This is the expensive half of producing output; callers that need to serve a windowed view of
+/// the same input (e.g. a viewport over a large result set) should cache this and only re-run it
+/// when an option that affects filtering, sorting, or separators changes. Callers that can feed
+/// input incrementally as it arrives (e.g. reading stdin from a long-lived process) should use
+/// `StreamSplitter` instead of buffering the whole input for this function.
+pub fn transform_rows(
     column_options: &Options,
     row_options: &Options,
     data: &Vec<u8>,
-) -> io::Result<Vec<u8>> {
+) -> Vec<Vec<Vec<u8>>> {
+    let rows: Vec<Vec<Vec<u8>>> = split_into_records(row_options, data)
+        .iter_mut()
+        .map(|row_data| split_into_records(column_options, row_data))
+        .collect();
+
+    finish_grid(column_options, row_options, rows)
+}
+
+/// Splits a single already-delimited token the same way `split_all`'s second stage does: through
+/// `regex_separator` if one is configured, otherwise left as one piece.
+fn apply_regex_separator(options: &Options, token: Vec<u8>) -> Vec<Vec<u8>> {
+    match &options.regex_separator {
+        None => vec![token],
+        Some(regex_separator) => regex_separator
+            .split(&token)
+            .map(|field| field.to_vec())
+            .collect(),
+    }
+}
+
+/// Incrementally splits raw input into rows as bytes arrive, instead of requiring the whole input
+/// up front like `transform_rows`. Each completed row is immediately split into cells with
+/// `column_options` and folded into the growing grid, so a caller streaming stdin can show rows
+/// as they're parsed instead of waiting for EOF. `main`'s `--no-gui` path feeds it stdin in chunks.
+///
+/// Only a literal `row_options.separators` `ByteTrie` can be consulted incrementally - membership
+/// only needs to see one byte at a time, and a multi-byte separator can straddle a `feed` call's
+/// chunk boundary, so `current_separator` persists across calls exactly like the inner loop in
+/// `split`. When `row_options.separators` is `None`, there's no separator to consult a byte at a
+/// time, so input is buffered until `finish` and split there exactly as `split_all` would.
+///
+/// Holds its own `Options` (rather than borrowing), so a splitter carries no lifetime tied to the
+/// caller's options; `main`'s `--no-gui` path moves its options into one.
+pub struct StreamSplitter {
+    column_options: Options,
+    row_options: Options,
+    current_line: Vec<u8>,
+    current_separator: Vec<u8>,
+    /// The widest row produced so far, in cells. This is a running estimate for callers that want
+    /// to show a live width before `finish`/`rows_so_far` has run dedup/sort, which can only
+    /// shrink the row set, never widen a kept row.
+    longest_number_of_cells: usize,
+    next_row_index: usize,
+    rows: Vec<Vec<Vec<u8>>>,
+}
+
+impl StreamSplitter {
+    pub fn new(column_options: Options, row_options: Options) -> StreamSplitter {
+        StreamSplitter {
+            column_options,
+            row_options,
+            current_line: vec![],
+            current_separator: vec![],
+            longest_number_of_cells: 0,
+            next_row_index: 0,
+            rows: vec![],
+        }
+    }
+
+    /// The widest row produced so far - see the field doc comment for why this is an estimate
+    /// until `finish`/`rows_so_far` runs.
+    pub fn longest_number_of_cells(&self) -> usize {
+        self.longest_number_of_cells
+    }
+
+    fn complete_row(&mut self, token: Vec<u8>) {
+        for sub_token in apply_regex_separator(&self.row_options, token) {
+            let i = self.next_row_index;
+            self.next_row_index += 1;
+
+            if decide_field(&self.row_options, sub_token.as_slice(), i).kept {
+                let cells = split_into_records(&self.column_options, &sub_token);
+
+                if cells.len() > self.longest_number_of_cells {
+                    self.longest_number_of_cells = cells.len();
+                }
+
+                self.rows.push(cells);
+            }
+        }
+    }
+
+    /// A non-consuming snapshot of `finish`'s result, for a caller (e.g. a GUI connection) that
+    /// wants to poll the current state of an in-progress stream without ending it.
+    pub fn rows_so_far(&self) -> Vec<Vec<Vec<u8>>> {
+        let mut rows = self.rows.clone();
+
+        if !self.current_line.is_empty() {
+            // Peek at the trailing partial row without consuming it from `self`, by running it
+            // through the same decision/split steps `complete_row` would on a real flush.
+            for sub_token in apply_regex_separator(&self.row_options, self.current_line.clone()) {
+                let i = self.next_row_index;
+
+                if decide_field(&self.row_options, sub_token.as_slice(), i).kept {
+                    rows.push(split_into_records(&self.column_options, &sub_token));
+                }
+            }
+        }
+
+        finish_grid(&self.column_options, &self.row_options, rows)
+    }
+
+    /// Feeds the next chunk of raw input, flushing any rows it completes. Safe to call with
+    /// chunks of any size, including ones that split a separator in half.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        let separators = match &self.row_options.separators {
+            Some(separators) => separators,
+            None => {
+                self.current_line.extend_from_slice(chunk);
+                return;
+            }
+        };
+
+        for &byte in chunk {
+            self.current_separator.push(byte);
+            match separators.membership(self.current_separator.as_slice()) {
+                Membership::NotIncluded => {
+                    self.current_line.push(byte);
+                    self.current_separator.clear();
+                }
+                Membership::Included if !self.current_line.is_empty() => {
+                    let row = std::mem::take(&mut self.current_line);
+                    self.complete_row(row);
+                }
+                Membership::Included => (),
+                Membership::IncludedAndTerminal if !self.current_line.is_empty() => {
+                    let row = std::mem::take(&mut self.current_line);
+                    self.complete_row(row);
+                    self.current_separator.clear();
+                }
+                Membership::IncludedAndTerminal => {
+                    self.current_separator.clear();
+                }
+            }
+        }
+    }
+
+    /// Flushes any trailing partial row (or, if `row_options.separators` was never set, the
+    /// entire buffered input) and runs the same sort/dedup/padding pass `transform_rows` does.
+    pub fn finish(mut self) -> Vec<Vec<Vec<u8>>> {
+        if !self.current_line.is_empty() {
+            let row = std::mem::take(&mut self.current_line);
+            self.complete_row(row);
+        }
+
+        finish_grid(&self.column_options, &self.row_options, self.rows)
+    }
+}
+
+/// Encodes an already-transformed grid of cells (or a window of one) as CSV.
+pub fn encode_csv(rows: &[Vec<Vec<u8>>]) -> io::Result<Vec<u8>> {
     let mut inner = vec![];
     {
         // Scope so that inner does not get dropped when the writer does
         let mut writer = csv::WriterBuilder::new()
             .has_headers(false)
             .from_writer(&mut inner);
-        let rows: Vec<Vec<Vec<u8>>> = split_into_records(row_options, data)
-            .iter_mut()
-            .map(|row_data| split_into_records(column_options, row_data))
-            .collect();
-        let mut longest_number_of_cells = 0;
 
-        for row in &rows {
-            if row.len() > longest_number_of_cells {
-                longest_number_of_cells = row.len();
+        for row in rows {
+            writer.write_record(row)?;
+        }
+
+        writer.flush()?;
+    }
+    Ok(inner)
+}
+
+/// Encodes an already-transformed grid of cells (or a window of one) as tab-separated values.
+fn encode_tsv(rows: &[Vec<Vec<u8>>]) -> Vec<u8> {
+    let mut output = vec![];
+
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            if i > 0 {
+                output.push(b'\t');
             }
+            output.extend(cell);
         }
+        output.push(b'\n');
+    }
 
-        for mut row in rows {
-            // Pad cells so the UI doesn't have to.
-            if row.len() < longest_number_of_cells {
-                for _ in 0..(longest_number_of_cells - row.len()) {
-                    row.push(vec![]);
-                }
+    output
+}
+
+/// Encodes an already-transformed grid of cells (or a window of one) as a Markdown table, using
+/// the first row as the header.
+fn encode_markdown_table(rows: &[Vec<Vec<u8>>]) -> Vec<u8> {
+    if rows.is_empty() {
+        return vec![];
+    }
+
+    let escape = |cell: &Vec<u8>| String::from_utf8_lossy(cell).replace('|', "\\|");
+    let render_row = |row: &Vec<Vec<u8>>| {
+        format!(
+            "| {} |\n",
+            row.iter().map(escape).collect::<Vec<String>>().join(" | ")
+        )
+    };
+
+    let mut output = render_row(&rows[0]);
+    output.push_str(&format!("|{}\n", "---|".repeat(rows[0].len())));
+
+    for row in &rows[1..] {
+        output.push_str(&render_row(row));
+    }
+
+    output.into_bytes()
+}
+
+/// Encodes an already-transformed grid of cells (or a window of one) as a JSON array of row
+/// objects. Keys are the header row's values when `has_header` is set and the grid is non-empty,
+/// otherwise they are the stringified column index.
+fn encode_json(rows: &[Vec<Vec<u8>>], has_header: bool) -> io::Result<Vec<u8>> {
+    let (header, body) = if has_header && !rows.is_empty() {
+        (Some(&rows[0]), &rows[1..])
+    } else {
+        (None, rows)
+    };
+
+    let records: Vec<Value> = body
+        .iter()
+        .map(|row| {
+            let mut object = Map::new();
+
+            for (i, cell) in row.iter().enumerate() {
+                let key = match header {
+                    Some(header) => String::from_utf8_lossy(field_at(header, i)).into_owned(),
+                    None => i.to_string(),
+                };
+
+                object.insert(key, json!(String::from_utf8_lossy(cell)));
             }
 
-            writer.write_record(row)?;
-        }
+            Value::Object(object)
+        })
+        .collect();
 
-        writer.flush()?;
+    serde_json::to_vec(&records).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Encodes an already-transformed grid of cells (or a window of one) in the given format.
+pub fn encode_rows(
+    format: OutputFormat,
+    rows: &[Vec<Vec<u8>>],
+    has_header: bool,
+) -> io::Result<Vec<u8>> {
+    match format {
+        OutputFormat::Csv => encode_csv(rows),
+        OutputFormat::Tsv => Ok(encode_tsv(rows)),
+        OutputFormat::Json => encode_json(rows, has_header),
+        OutputFormat::MarkdownTable => Ok(encode_markdown_table(rows)),
     }
-    Ok(inner)
 }
 
 #[cfg(test)]
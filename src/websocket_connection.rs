@@ -1,32 +1,99 @@
 /// This module provides an opinionated Websocket actor, suited to this project.
 ///
 /// It provides:
-/// - Heartbeat handling (clients are expected to ping every HEARTBEAT_INTERVAL and are disconnected if they stop responding)
+/// - Heartbeat handling (clients are expected to ping every `heartbeat_interval` and are disconnected if they stop responding)
 /// - Continuation support (frames are collected and rolled into a single text or binary message, to reduce the number of handlers needed)
-/// - Actor shutdown on close messages
+/// - Session preservation on heartbeat timeout or abnormal disconnect, so a client that reconnects with the same session id resumes where it left off
+/// - Configurable shutdown behavior: a single-session process exits when its one client disconnects, while a persistent server keeps running for the next one
+/// - A self-describing, versioned JSON command protocol (see `crate::protocol`), rather than undocumented raw bytes
+/// - Panic isolation: a panic while processing one message is caught and reported back to the
+///   client as a diagnostic frame, rather than taking the connection (or the worker) down with it
+/// - Outbound batching: result rows are buffered and flushed as a single `Output` frame once a
+///   row/byte threshold is hit or a flush timer fires, rather than one frame per row
 ///
 /// For simplicity's sake, text messages are treated as binary.
+use crate::file_watch::{self, FileWatcher};
 use crate::parsers;
-use crate::protos::definitions::{
-    Combination_oneof_inner as CombinationInner, FromClient,
-    FromClient_oneof_inner as FromClientInner, FromServer,
-    FromServer_oneof_inner as FromServerInner, Initialize, SetColumnFilterCombination,
-    SetColumnIndexFilters, SetColumnRegexFilter, SetColumnRegexSeparator, SetColumnSeparators,
-    SetRowFilterCombination, SetRowIndexFilters, SetRowRegexFilter, SetRowRegexSeparator,
-    SetRowSeparators, UnexpectedError,
+use crate::protocol::{
+    ClientEnvelope, ClientMsg, Initialize, RowDecision, ServerEnvelope, ServerMsg,
+    PROTOCOL_VERSION,
 };
+use crate::sessions::{PersistedOptions, SessionState, SessionStore};
 use crate::transformers;
 
 use actix::prelude::*;
 use actix_http::ws::{CloseCode, CloseReason, Item};
+use actix_web::web;
 use actix_web_actors::ws;
+use backtrace::Backtrace;
 use bytes::{Bytes, BytesMut};
-use protobuf::{Message as ProtobufMessage, ProtobufError};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fmt;
 use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-struct MessageParseError(ProtobufError);
+/// The panicking location of the most recent panic caught on this thread, stashed by
+/// `install_panic_hook`'s hook so `handle_message`'s `catch_unwind` can attach it to the
+/// diagnostic it sends back, rather than just the bare panic payload. The backtrace itself is
+/// logged by the hook and isn't kept around; it's for the server's logs, not the client.
+thread_local! {
+    static LAST_PANIC_LOCATION: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Installs a panic hook that logs a backtrace and records the panicking location before
+/// `catch_unwind` unwinds past it, so `handle_message` can report where a client's program died
+/// instead of just "it panicked". Should be called once, at process startup.
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let location = info.location().map(|location| location.to_string());
+        let backtrace = Backtrace::new();
+
+        log::error!("A panic occurred while handling a client message at {:?}:\n{:?}", location, backtrace);
+
+        LAST_PANIC_LOCATION.with(|cell| {
+            *cell.borrow_mut() = location;
+        });
+    }));
+}
+
+fn describe_panic_payload(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "no further details are available".to_owned()
+    }
+}
+
+/// An error surfaced after `catch_unwind` recovers from a panic in message handling.
+struct PanicError {
+    message: String,
+    location: Option<String>,
+}
+
+impl fmt::Display for PanicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(
+                f,
+                "An internal error occurred while running the program ({}): {}",
+                location, self.message
+            ),
+            None => write!(
+                f,
+                "An internal error occurred while running the program: {}",
+                self.message
+            ),
+        }
+    }
+}
+
+struct MessageParseError(serde_json::Error);
 
 impl fmt::Display for MessageParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -38,6 +105,20 @@ impl fmt::Display for MessageParseError {
     }
 }
 
+struct ProtocolVersionMismatchError {
+    received: u32,
+}
+
+impl fmt::Display for ProtocolVersionMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "This server speaks protocol version {}, but the client sent version {}.",
+            PROTOCOL_VERSION, self.received
+        )
+    }
+}
+
 enum InitializeError {
     InvalidRowFieldSeparatorError(parsers::InvalidFieldSeparatorError),
     InvalidRowIndexFiltersError(parsers::InvalidIndexFiltersError),
@@ -80,54 +161,269 @@ impl fmt::Display for InitializeError {
     }
 }
 
-struct EmptyMessageError;
+fn to_sort_keys(keys: Vec<transformers::SortKey>) -> Option<Vec<transformers::SortKey>> {
+    if keys.is_empty() {
+        None
+    } else {
+        Some(keys)
+    }
+}
+
+fn to_dedup_keys(field_indices: Vec<usize>) -> Option<Vec<usize>> {
+    if field_indices.is_empty() {
+        None
+    } else {
+        Some(field_indices)
+    }
+}
 
-impl fmt::Display for EmptyMessageError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "A message was expected but was not present.")
+/// Reconstructs a `transformers::Options` from a session's persisted raw strings, re-parsing
+/// each one through the same `parsers` functions a live setter would use. Unlike a live setter,
+/// an unparsable value here doesn't have a client to report back to - the session was already
+/// accepted once - so it's logged and just dropped instead of failing the whole rehydrate.
+fn options_from_persisted(raw: &PersistedOptions) -> transformers::Options {
+    let mut options = transformers::Options::default();
+
+    match parsers::parse_field_separators(&raw.separators) {
+        Ok(parsed) => options.separators = Some(parsed),
+        Err(error) => log::warn!("Discarding an unparsable persisted separator:\n{}", error),
+    }
+    if !raw.regex_separator.is_empty() {
+        match parsers::parse_regex(&raw.regex_separator) {
+            Ok(parsed) => options.regex_separator = Some(parsed),
+            Err(error) => {
+                log::warn!("Discarding an unparsable persisted regex separator:\n{}", error)
+            }
+        }
     }
+    if !raw.regex_filter.is_empty() {
+        match parsers::parse_regex(&raw.regex_filter) {
+            Ok(parsed) => options.regex_filter = Some(parsed),
+            Err(error) => log::warn!("Discarding an unparsable persisted regex filter:\n{}", error),
+        }
+    }
+    if !raw.index_filters.is_empty() {
+        match parsers::parse_index_filters(&raw.index_filters) {
+            Ok(parsed) => options.index_filters = Some(parsed),
+            Err(error) => {
+                log::warn!("Discarding unparsable persisted index filters:\n{}", error)
+            }
+        }
+    }
+    if !raw.value_filters.is_empty() {
+        let mut parsed_filters = vec![];
+        for expression in &raw.value_filters {
+            match parsers::parse_value_filter(expression) {
+                Ok(parsed) => parsed_filters.push(parsed),
+                Err(error) => {
+                    log::warn!("Discarding an unparsable persisted value filter:\n{}", error)
+                }
+            }
+        }
+        if !parsed_filters.is_empty() {
+            options.value_filters = Some(parsed_filters);
+        }
+    }
+    options.filters_combination = raw.filters_combination.clone();
+    options.sort_keys = to_sort_keys(raw.sort_keys.clone());
+    options.dedup_keys = to_dedup_keys(raw.dedup_keys.clone());
+
+    options
 }
 
 #[derive(Debug)]
-enum SendCSVError {
-    TransformError(io::Error),
-    EncodeCommandError(ProtobufError),
-}
+struct SendCSVError(io::Error);
 
 impl fmt::Display for SendCSVError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::TransformError(error) => write!(f, "An error occurred while transforming command output:\n{}", error),
-            Self::EncodeCommandError(error) => write!(f, "An error occurred while encoding the current command output to be sent over the wire:\n{}", error),
-        }
+        write!(f, "An error occurred while transforming command output:\n{}", self.0)
     }
 }
 
-/// How often heartbeat pings are sent.
-pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
-/// How long before lack of client response causes a timeout.
-pub const CLIENT_TIMEOUT: Duration = Duration::from_millis(500);
+/// The default interval at which heartbeat pings are sent, used when the server is started
+/// without an explicit `WebsocketConnectionConfig`.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+/// The default duration of missing client response before a timeout, used when the server is
+/// started without an explicit `WebsocketConnectionConfig`.
+pub const DEFAULT_CLIENT_TIMEOUT: Duration = Duration::from_millis(500);
+/// The default maximum number of rows accumulated in the outbound buffer before it's flushed as
+/// an `Output` frame, used when the server is started without an explicit
+/// `WebsocketConnectionConfig`.
+pub const DEFAULT_OUTBOUND_BATCH_MAX_ROWS: usize = 1000;
+/// The default number of raw cell bytes (before encoding) accumulated in the outbound buffer before it's
+/// flushed as an `Output` frame, used when the server is started without an explicit
+/// `WebsocketConnectionConfig`.
+pub const DEFAULT_OUTBOUND_BATCH_MAX_BYTES: usize = 64 * 1024;
+/// The default delay between outbound buffer flushes while rows remain queued, used when the
+/// server is started without an explicit `WebsocketConnectionConfig`.
+pub const DEFAULT_OUTBOUND_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether this connection is the only thing keeping the process alive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShutdownMode {
+    /// The process was started to serve a single client (vawk's normal CLI usage); once that
+    /// client disconnects, there's nothing left to serve, so the whole `System` stops with it.
+    SingleSession,
+    /// The process serves multiple clients over its lifetime; one client disconnecting just
+    /// stops that client's actor, leaving the `System` running for everyone else.
+    Persistent,
+}
+
+/// Heartbeat timing, configurable so operators running behind a slow network can loosen it
+/// instead of the client being disconnected on every hiccup.
+#[derive(Debug, Clone, Copy)]
+pub struct WebsocketConnectionConfig {
+    pub heartbeat_interval: Duration,
+    pub client_timeout: Duration,
+    pub shutdown_mode: ShutdownMode,
+    /// Rows accumulated in the outbound buffer are flushed once there are this many of them,
+    /// even if `outbound_batch_max_bytes` hasn't been reached yet.
+    pub outbound_batch_max_rows: usize,
+    /// Rows accumulated in the outbound buffer are flushed once their cells' combined size,
+    /// before encoding, reaches this many bytes, even if `outbound_batch_max_rows` hasn't been
+    /// reached yet.
+    pub outbound_batch_max_bytes: usize,
+    /// How long to wait between consecutive flushes while rows remain queued. The first batch of
+    /// a result is flushed as soon as it's queued.
+    pub outbound_flush_interval: Duration,
+}
+
+impl WebsocketConnectionConfig {
+    pub fn default() -> Self {
+        Self {
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            client_timeout: DEFAULT_CLIENT_TIMEOUT,
+            shutdown_mode: ShutdownMode::SingleSession,
+            outbound_batch_max_rows: DEFAULT_OUTBOUND_BATCH_MAX_ROWS,
+            outbound_batch_max_bytes: DEFAULT_OUTBOUND_BATCH_MAX_BYTES,
+            outbound_flush_interval: DEFAULT_OUTBOUND_FLUSH_INTERVAL,
+        }
+    }
+}
 
 pub struct WebsocketConnection {
+    /// The process's raw command input, read incrementally by `main::spawn_stdin_reader` so this
+    /// connection can start serving before stdin has reached EOF. Shared (rather than owned)
+    /// so every tick of the heartbeat timer can notice it grew and re-render without the
+    /// connection having had to poll stdin itself.
+    shared_stdin: Arc<Mutex<Vec<u8>>>,
+    /// This connection's own snapshot of `shared_stdin`, taken at connect time and refreshed by
+    /// `refresh_stdin` whenever `shared_stdin` has grown. Kept as a plain `Vec<u8>` (rather than
+    /// locking `shared_stdin` on every read) so the rest of this actor can keep passing `&self.stdin`
+    /// to `transformers` exactly as before, and so a session can persist/restore an independent copy.
     stdin: Vec<u8>,
+    /// Set when `--file --watch` is in effect, so `started` can subscribe this connection with
+    /// `file_watch::Register` and receive a fresh `file_watch::ApplyStdin` push whenever the
+    /// watched file's contents change, instead of relying on `refresh_stdin`'s grown-since-last-poll
+    /// check (which can't tell a shrunk or rewritten file apart from an unchanged one).
+    file_watcher: Option<Addr<FileWatcher>>,
     column_options: transformers::Options,
     row_options: transformers::Options,
     last_seen_heartbeat: Instant,
     continuation_frame: Option<BytesMut>,
+    /// The result of the last `transformers::transform_rows` call. Cleared whenever an option
+    /// that affects filtering, sorting, deduping, or separators changes; a `SetViewport` message
+    /// alone leaves it in place so scrolling just re-slices the cached rows.
+    cached_rows: Option<Vec<Vec<Vec<u8>>>>,
+    viewport_row_offset: usize,
+    viewport_row_limit: usize,
+    output_format: transformers::OutputFormat,
+    has_header: bool,
+    explain: bool,
+    heartbeat_interval: Duration,
+    client_timeout: Duration,
+    shutdown_mode: ShutdownMode,
+    /// Where this connection's state is preserved under `session_id` if its heartbeat times out,
+    /// so a reconnecting client can rehydrate instead of re-sending every setter.
+    session_store: web::Data<SessionStore>,
+    session_id: Option<String>,
+    /// Raw-string mirrors of `column_options`/`row_options`, kept in lockstep by every setter so
+    /// `persist_session` has something `Serialize` to hand to the session store; `Options` itself
+    /// holds a compiled `Regex` and can't round-trip through JSON.
+    column_raw: PersistedOptions,
+    row_raw: PersistedOptions,
+    outbound_batch_max_rows: usize,
+    outbound_batch_max_bytes: usize,
+    outbound_flush_interval: Duration,
+    /// Rows waiting to be encoded and sent, queued by `send_csvs` and drained batch by batch by
+    /// `flush_outbound`.
+    pending_rows: VecDeque<Vec<Vec<u8>>>,
+    /// The total row count to report alongside every batch of the response currently being
+    /// flushed, i.e. the size of the whole result, not just the pending batch.
+    pending_total_row_count: u64,
+    /// Whether the next flushed batch is the first one for the response currently being sent, so
+    /// only it (not every later batch) is treated as carrying the client's requested header row.
+    pending_is_first_batch: bool,
+    /// Whether a flush has already been scheduled via `ctx.run_later` for the current backlog of
+    /// `pending_rows`, so a burst of setters doesn't stack up redundant timers.
+    flush_scheduled: bool,
 }
 
 impl WebsocketConnection {
     pub fn new(
-        stdin: Vec<u8>,
+        shared_stdin: Arc<Mutex<Vec<u8>>>,
+        file_watcher: Option<Addr<FileWatcher>>,
         column_options: transformers::Options,
         row_options: transformers::Options,
+        session_store: web::Data<SessionStore>,
+        config: WebsocketConnectionConfig,
     ) -> Self {
+        let stdin = shared_stdin.lock().unwrap().clone();
+
         Self {
+            shared_stdin,
             stdin,
+            file_watcher,
             column_options,
             row_options,
             last_seen_heartbeat: Instant::now(),
             continuation_frame: None,
+            cached_rows: None,
+            viewport_row_offset: 0,
+            viewport_row_limit: usize::MAX,
+            output_format: transformers::OutputFormat::Csv,
+            has_header: false,
+            explain: false,
+            heartbeat_interval: config.heartbeat_interval,
+            client_timeout: config.client_timeout,
+            shutdown_mode: config.shutdown_mode,
+            session_store,
+            session_id: None,
+            column_raw: PersistedOptions::default(),
+            row_raw: PersistedOptions::default(),
+            outbound_batch_max_rows: config.outbound_batch_max_rows,
+            outbound_batch_max_bytes: config.outbound_batch_max_bytes,
+            outbound_flush_interval: config.outbound_flush_interval,
+            pending_rows: VecDeque::new(),
+            pending_total_row_count: 0,
+            pending_is_first_batch: true,
+            flush_scheduled: false,
+        }
+    }
+
+    /// Drops the cached, transformed rows. Must be called whenever an option that affects
+    /// filtering, sorting, deduping, or separators changes, so the next `send_csvs` recomputes
+    /// rather than serving a stale view.
+    fn invalidate_cache(&mut self) {
+        self.cached_rows = None;
+    }
+
+    /// Re-clones `shared_stdin` into `self.stdin` and invalidates the cache if it grew since the
+    /// last poll, so a long-lived connection picks up rows from a still-running producer (e.g.
+    /// `tail -f`) instead of only ever seeing the snapshot taken at connect time.
+    fn refresh_stdin(&mut self) {
+        let grown = {
+            let shared = self.shared_stdin.lock().unwrap();
+            if shared.len() > self.stdin.len() {
+                Some(shared.clone())
+            } else {
+                None
+            }
+        };
+
+        if let Some(grown) = grown {
+            self.stdin = grown;
+            self.invalidate_cache();
         }
     }
 
@@ -136,111 +432,223 @@ impl WebsocketConnection {
         ctx: &mut ws::WebsocketContext<WebsocketConnection>,
         error: T,
     ) {
-        let mut error_response = FromServer::default();
-        let mut error_wrapper = UnexpectedError::default();
-        error_wrapper.set_description(format!("{}", error));
-        error_response.inner = Some(FromServerInner::unexpected_error(error_wrapper));
-
         log::error!("{}", error);
 
-        match error_response.write_to_bytes() {
-            Ok(encoded_error_response) => ctx.binary(encoded_error_response),
-            Err(error) => {
-                log::error!("{}", error);
+        self.send(
+            ctx,
+            ServerMsg::Error {
+                description: format!("{}", error),
+            },
+        );
+    }
+
+    /// Wraps `message` in a `ServerEnvelope`, serializes it to JSON, and sends it as a single
+    /// text frame. Encoding a `ServerMsg` we built ourselves should never fail; if it somehow
+    /// does, there's nothing more useful to do than log it, since calling `send_error` here would
+    /// just recurse into the same failure.
+    fn send(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, message: ServerMsg) {
+        match serde_json::to_string(&ServerEnvelope::new(message)) {
+            Ok(encoded) => ctx.text(encoded),
+            Err(error) => log::error!(
+                "An error occurred while encoding a response to be sent over the wire:\n{}",
+                error
+            ),
+        }
+    }
+
+    /// Computes (or reuses the cache of) the transformed, windowed rows and queues them in the
+    /// outbound buffer for `flush_outbound` to encode and send in batches, rather than handing it
+    /// the whole result as one frame.
+    fn send_csvs(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>) {
+        if self.cached_rows.is_none() {
+            self.cached_rows = Some(transformers::transform_rows(
+                &self.column_options,
+                &self.row_options,
+                &self.stdin,
+            ));
+        }
+
+        let rows = self.cached_rows.as_ref().unwrap();
+        let windowed_rows: Vec<Vec<Vec<u8>>> = rows
+            .iter()
+            .skip(self.viewport_row_offset)
+            .take(self.viewport_row_limit)
+            .cloned()
+            .collect();
+
+        self.pending_total_row_count = rows.len() as u64;
+        self.pending_rows = windowed_rows.into();
+        self.pending_is_first_batch = true;
+        self.flush_outbound(ctx);
+
+        if self.explain {
+            self.send_explanation(ctx);
+        }
+    }
+
+    /// Pops rows off the front of the outbound buffer until either `outbound_batch_max_rows` or
+    /// `outbound_batch_max_bytes` is reached (or the buffer runs dry), encodes that batch in the
+    /// client's chosen output format, and sends it as a single `Output` frame. If rows remain
+    /// queued afterward, schedules another flush after `outbound_flush_interval` rather than
+    /// blocking the actor until the whole result has gone out.
+    fn flush_outbound(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>) {
+        if self.pending_rows.is_empty() {
+            self.flush_scheduled = false;
+            return;
+        }
+
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0;
+
+        while let Some(row) = self.pending_rows.pop_front() {
+            batch_bytes += row.iter().map(|field| field.len()).sum::<usize>();
+            batch.push(row);
+
+            if batch.len() >= self.outbound_batch_max_rows || batch_bytes >= self.outbound_batch_max_bytes {
+                break;
             }
         }
+
+        let has_header = self.has_header && self.pending_is_first_batch;
+        self.pending_is_first_batch = false;
+
+        match transformers::encode_rows(self.output_format, &batch, has_header) {
+            Ok(encoded_csv) => self.send(
+                ctx,
+                ServerMsg::Output {
+                    csv: base64::encode(&encoded_csv),
+                    total_row_count: self.pending_total_row_count,
+                    is_last: self.pending_rows.is_empty(),
+                },
+            ),
+            Err(error) => self.send_error(ctx, SendCSVError(error)),
+        }
+
+        if self.pending_rows.is_empty() {
+            self.flush_scheduled = false;
+        } else if !self.flush_scheduled {
+            self.flush_scheduled = true;
+            ctx.run_later(self.outbound_flush_interval, |connection, ctx| {
+                connection.flush_outbound(ctx);
+            });
+        }
     }
 
-    fn send_csvs(
-        &mut self,
-        ctx: &mut ws::WebsocketContext<WebsocketConnection>,
-    ) -> Result<(), SendCSVError> {
-        let transformed =
-            transformers::transform_output(&self.column_options, &self.row_options, &self.stdin)
-                .map_err(|error| SendCSVError::TransformError(error))?;
+    fn send_explanation(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>) {
+        let split_rows = transformers::split_all(&self.row_options, &self.stdin);
+        let decisions = transformers::explain_matches(&self.row_options, &split_rows);
 
-        let mut output_response = FromServer::default();
-        output_response.inner = Some(FromServerInner::output(transformed));
-        let encoded_output_response = output_response
-            .write_to_bytes()
-            .map_err(|error| SendCSVError::EncodeCommandError(error))?;
+        let row_decisions = decisions
+            .into_iter()
+            .map(|decision| RowDecision {
+                has_index_filter: decision.index_filter_matched.is_some(),
+                index_filter_matched: decision.index_filter_matched.unwrap_or(false),
+                has_regex_filter: decision.regex_filter_matched.is_some(),
+                regex_filter_matched: decision.regex_filter_matched.unwrap_or(false),
+                has_value_filter: decision.value_filter_matched.is_some(),
+                value_filter_matched: decision.value_filter_matched.unwrap_or(false),
+                kept: decision.kept,
+            })
+            .collect();
 
-        ctx.binary(encoded_output_response);
+        self.send(ctx, ServerMsg::Explanation { row_decisions });
+    }
 
-        Ok(())
+    fn set_explain(&mut self, enabled: bool) {
+        self.explain = enabled;
     }
 
     fn initialize(&mut self, initial_values: Initialize) -> Result<(), InitializeError> {
+        self.invalidate_cache();
+        self.session_id = if initial_values.session_id.is_empty() {
+            None
+        } else {
+            Some(initial_values.session_id)
+        };
+
+        if !initial_values.prior_session_id.is_empty() {
+            if let Some(state) = self.session_store.take(&initial_values.prior_session_id) {
+                self.stdin = state.stdin;
+                self.column_options = options_from_persisted(&state.column_options);
+                self.row_options = options_from_persisted(&state.row_options);
+                self.column_raw = state.column_options;
+                self.row_raw = state.row_options;
+                return Ok(());
+            }
+        }
+
+        self.row_raw.separators = initial_values.row_separators.clone();
         self.row_options.separators = Some(
-            parsers::parse_field_separators(initial_values.get_row_separators())
+            parsers::parse_field_separators(&initial_values.row_separators)
                 .map_err(|error| InitializeError::InvalidRowFieldSeparatorError(error))?,
         );
-        if initial_values.get_row_regex_separator() != "" {
+        self.row_raw.regex_separator = initial_values.row_regex_separator.clone();
+        if !initial_values.row_regex_separator.is_empty() {
             self.row_options.regex_separator = Some(
-                parsers::parse_regex(initial_values.get_row_regex_separator())
+                parsers::parse_regex(&initial_values.row_regex_separator)
                     .map_err(|error| InitializeError::InvalidRowRegexSeparatorError(error))?,
             );
         }
-        if initial_values.get_row_index_filters() != "" {
+        self.row_raw.index_filters = initial_values.row_index_filters.clone();
+        if !initial_values.row_index_filters.is_empty() {
             self.row_options.index_filters = Some(
-                parsers::parse_index_filters(initial_values.get_row_index_filters())
+                parsers::parse_index_filters(&initial_values.row_index_filters)
                     .map_err(|error| InitializeError::InvalidRowIndexFiltersError(error))?,
             );
         }
-        if initial_values.get_row_regex_filter() != "" {
+        self.row_raw.regex_filter = initial_values.row_regex_filter.clone();
+        if !initial_values.row_regex_filter.is_empty() {
             self.row_options.regex_filter = Some(
-                parsers::parse_regex(initial_values.get_row_regex_filter())
+                parsers::parse_regex(&initial_values.row_regex_filter)
                     .map_err(|error| InitializeError::InvalidRowRegexFilterError(error))?,
             );
         }
-        self.row_options.filters_combination =
-            match initial_values.get_row_filter_combination().inner {
-                Some(CombinationInner::and(_)) => Some(transformers::Combination::And),
-                Some(CombinationInner::or(_)) => Some(transformers::Combination::Or),
-                None => None,
-            };
+        self.row_raw.filters_combination = initial_values.row_filter_combination.clone();
+        self.row_options.filters_combination = initial_values.row_filter_combination;
+        self.column_raw.separators = initial_values.column_separators.clone();
         self.column_options.separators = Some(
-            parsers::parse_field_separators(initial_values.get_column_separators())
+            parsers::parse_field_separators(&initial_values.column_separators)
                 .map_err(|error| InitializeError::InvalidColumnFieldSeparatorError(error))?,
         );
-        if initial_values.get_column_regex_separator() != "" {
+        self.column_raw.regex_separator = initial_values.column_regex_separator.clone();
+        if !initial_values.column_regex_separator.is_empty() {
             self.column_options.regex_separator = Some(
-                parsers::parse_regex(initial_values.get_column_regex_separator())
+                parsers::parse_regex(&initial_values.column_regex_separator)
                     .map_err(|error| InitializeError::InvalidColumnRegexSeparatorError(error))?,
             );
         }
-        if initial_values.get_column_index_filters() != "" {
+        self.column_raw.index_filters = initial_values.column_index_filters.clone();
+        if !initial_values.column_index_filters.is_empty() {
             self.column_options.index_filters = Some(
-                parsers::parse_index_filters(initial_values.get_column_index_filters())
+                parsers::parse_index_filters(&initial_values.column_index_filters)
                     .map_err(|error| InitializeError::InvalidColumnIndexFiltersError(error))?,
             );
         }
-        if initial_values.get_column_regex_filter() != "" {
+        self.column_raw.regex_filter = initial_values.column_regex_filter.clone();
+        if !initial_values.column_regex_filter.is_empty() {
             self.column_options.regex_filter = Some(
-                parsers::parse_regex(initial_values.get_column_regex_filter())
+                parsers::parse_regex(&initial_values.column_regex_filter)
                     .map_err(|error| InitializeError::InvalidColumnRegexFilterError(error))?,
             );
         }
-        self.column_options.filters_combination =
-            match initial_values.get_column_filter_combination().inner {
-                Some(CombinationInner::and(_)) => Some(transformers::Combination::And),
-                Some(CombinationInner::or(_)) => Some(transformers::Combination::Or),
-                None => None,
-            };
+        self.column_raw.filters_combination = initial_values.column_filter_combination.clone();
+        self.column_options.filters_combination = initial_values.column_filter_combination;
 
         Ok(())
     }
 
     fn set_column_index_filters(
         &mut self,
-        filters: SetColumnIndexFilters,
+        filters: String,
     ) -> Result<(), parsers::InvalidIndexFiltersError> {
-        if filters.get_filters() == "" {
+        self.invalidate_cache();
+        self.column_raw.index_filters = filters.clone();
+        if filters.is_empty() {
             self.column_options.index_filters = None;
             return Ok(());
         }
 
-        match parsers::parse_index_filters(filters.get_filters()) {
+        match parsers::parse_index_filters(&filters) {
             Ok(parsed_filters) => {
                 self.column_options.index_filters = Some(parsed_filters);
                 Ok(())
@@ -254,14 +662,16 @@ impl WebsocketConnection {
 
     fn set_column_regex_filter(
         &mut self,
-        filter: SetColumnRegexFilter,
+        filter: String,
     ) -> Result<(), parsers::InvalidRegexError> {
-        if filter.get_filter() == "" {
+        self.invalidate_cache();
+        self.column_raw.regex_filter = filter.clone();
+        if filter.is_empty() {
             self.column_options.regex_filter = None;
             return Ok(());
         }
 
-        match parsers::parse_regex(filter.get_filter()) {
+        match parsers::parse_regex(&filter) {
             Ok(parsed_filter) => {
                 self.column_options.regex_filter = Some(parsed_filter);
                 Ok(())
@@ -273,19 +683,19 @@ impl WebsocketConnection {
         }
     }
 
-    fn set_column_filter_combination(&mut self, combination: SetColumnFilterCombination) {
-        self.column_options.filters_combination = match combination.get_combination().inner {
-            Some(CombinationInner::and(_)) => Some(transformers::Combination::And),
-            Some(CombinationInner::or(_)) => Some(transformers::Combination::Or),
-            None => None,
-        }
+    fn set_column_filter_combination(&mut self, combination: Option<transformers::Combination>) {
+        self.invalidate_cache();
+        self.column_raw.filters_combination = combination.clone();
+        self.column_options.filters_combination = combination;
     }
 
     fn set_column_separators(
         &mut self,
-        separators: SetColumnSeparators,
+        separators: String,
     ) -> Result<(), parsers::InvalidFieldSeparatorError> {
-        match parsers::parse_field_separators(separators.get_separators()) {
+        self.invalidate_cache();
+        self.column_raw.separators = separators.clone();
+        match parsers::parse_field_separators(&separators) {
             Ok(parsed_separators) => {
                 self.column_options.separators = Some(parsed_separators);
                 Ok(())
@@ -299,14 +709,16 @@ impl WebsocketConnection {
 
     fn set_column_regex_separator(
         &mut self,
-        separator: SetColumnRegexSeparator,
+        separator: String,
     ) -> Result<(), parsers::InvalidRegexError> {
-        if separator.get_separator() == "" {
+        self.invalidate_cache();
+        self.column_raw.regex_separator = separator.clone();
+        if separator.is_empty() {
             self.column_options.regex_separator = None;
             return Ok(());
         }
 
-        match parsers::parse_regex(separator.get_separator()) {
+        match parsers::parse_regex(&separator) {
             Ok(parsed_separator) => {
                 self.column_options.regex_separator = Some(parsed_separator);
                 Ok(())
@@ -320,14 +732,16 @@ impl WebsocketConnection {
 
     fn set_row_index_filters(
         &mut self,
-        filters: SetRowIndexFilters,
+        filters: String,
     ) -> Result<(), parsers::InvalidIndexFiltersError> {
-        if filters.get_filters() == "" {
+        self.invalidate_cache();
+        self.row_raw.index_filters = filters.clone();
+        if filters.is_empty() {
             self.row_options.index_filters = None;
             return Ok(());
         }
 
-        match parsers::parse_index_filters(filters.get_filters()) {
+        match parsers::parse_index_filters(&filters) {
             Ok(parsed_filters) => {
                 self.row_options.index_filters = Some(parsed_filters);
                 Ok(())
@@ -341,14 +755,16 @@ impl WebsocketConnection {
 
     fn set_row_regex_filter(
         &mut self,
-        filter: SetRowRegexFilter,
+        filter: String,
     ) -> Result<(), parsers::InvalidRegexError> {
-        if filter.get_filter() == "" {
+        self.invalidate_cache();
+        self.row_raw.regex_filter = filter.clone();
+        if filter.is_empty() {
             self.row_options.regex_filter = None;
             return Ok(());
         }
 
-        match parsers::parse_regex(filter.get_filter()) {
+        match parsers::parse_regex(&filter) {
             Ok(parsed_filter) => {
                 self.row_options.regex_filter = Some(parsed_filter);
                 Ok(())
@@ -360,19 +776,19 @@ impl WebsocketConnection {
         }
     }
 
-    fn set_row_filter_combination(&mut self, combination: SetRowFilterCombination) {
-        self.row_options.filters_combination = match combination.get_combination().inner {
-            Some(CombinationInner::and(_)) => Some(transformers::Combination::And),
-            Some(CombinationInner::or(_)) => Some(transformers::Combination::Or),
-            None => None,
-        }
+    fn set_row_filter_combination(&mut self, combination: Option<transformers::Combination>) {
+        self.invalidate_cache();
+        self.row_raw.filters_combination = combination.clone();
+        self.row_options.filters_combination = combination;
     }
 
     fn set_row_separators(
         &mut self,
-        separators: SetRowSeparators,
+        separators: String,
     ) -> Result<(), parsers::InvalidFieldSeparatorError> {
-        match parsers::parse_field_separators(separators.get_separators()) {
+        self.invalidate_cache();
+        self.row_raw.separators = separators.clone();
+        match parsers::parse_field_separators(&separators) {
             Ok(parsed_separators) => {
                 self.row_options.separators = Some(parsed_separators);
                 Ok(())
@@ -386,14 +802,16 @@ impl WebsocketConnection {
 
     fn set_row_regex_separator(
         &mut self,
-        separator: SetRowRegexSeparator,
+        separator: String,
     ) -> Result<(), parsers::InvalidRegexError> {
-        if separator.get_separator() == "" {
+        self.invalidate_cache();
+        self.row_raw.regex_separator = separator.clone();
+        if separator.is_empty() {
             self.row_options.regex_separator = None;
             return Ok(());
         }
 
-        match parsers::parse_regex(separator.get_separator()) {
+        match parsers::parse_regex(&separator) {
             Ok(parsed_separator) => {
                 self.row_options.regex_separator = Some(parsed_separator);
                 Ok(())
@@ -405,121 +823,248 @@ impl WebsocketConnection {
         }
     }
 
+    fn set_column_sort(&mut self, keys: Vec<transformers::SortKey>) {
+        self.invalidate_cache();
+        self.column_raw.sort_keys = keys.clone();
+        self.column_options.sort_keys = to_sort_keys(keys);
+    }
+
+    fn set_row_sort(&mut self, keys: Vec<transformers::SortKey>) {
+        self.invalidate_cache();
+        self.row_raw.sort_keys = keys.clone();
+        self.row_options.sort_keys = to_sort_keys(keys);
+    }
+
+    fn set_column_dedup(&mut self, field_indices: Vec<usize>) {
+        self.invalidate_cache();
+        self.column_raw.dedup_keys = field_indices.clone();
+        self.column_options.dedup_keys = to_dedup_keys(field_indices);
+    }
+
+    fn set_row_dedup(&mut self, field_indices: Vec<usize>) {
+        self.invalidate_cache();
+        self.row_raw.dedup_keys = field_indices.clone();
+        self.row_options.dedup_keys = to_dedup_keys(field_indices);
+    }
+
+    fn set_viewport(&mut self, row_offset: usize, row_limit: usize) {
+        self.viewport_row_offset = row_offset;
+        self.viewport_row_limit = row_limit;
+    }
+
+    fn set_output_format(&mut self, format: transformers::OutputFormat, has_header: bool) {
+        self.output_format = format;
+        self.has_header = has_header;
+    }
+
+    fn set_column_value_filter(
+        &mut self,
+        expressions: Vec<String>,
+    ) -> Result<(), parsers::InvalidValueFilterError> {
+        self.invalidate_cache();
+        self.column_raw.value_filters = expressions.clone();
+        if expressions.is_empty() {
+            self.column_options.value_filters = None;
+            return Ok(());
+        }
+
+        let mut parsed_filters = vec![];
+        for expression in &expressions {
+            parsed_filters.push(parsers::parse_value_filter(expression)?);
+        }
+
+        self.column_options.value_filters = Some(parsed_filters);
+        Ok(())
+    }
+
+    fn set_row_value_filter(
+        &mut self,
+        expressions: Vec<String>,
+    ) -> Result<(), parsers::InvalidValueFilterError> {
+        self.invalidate_cache();
+        self.row_raw.value_filters = expressions.clone();
+        if expressions.is_empty() {
+            self.row_options.value_filters = None;
+            return Ok(());
+        }
+
+        let mut parsed_filters = vec![];
+        for expression in &expressions {
+            parsed_filters.push(parsers::parse_value_filter(expression)?);
+        }
+
+        self.row_options.value_filters = Some(parsed_filters);
+        Ok(())
+    }
+
+    /// Dispatches a single client message, catching any panic raised while doing so. A panic
+    /// inside program parsing/evaluation would otherwise propagate out of the actor's message
+    /// handler and poison the whole connection (or worker); here it's reported back to the
+    /// client as a diagnostic frame instead.
     fn handle_message(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, data: Bytes) {
-        match FromClient::parse_from_bytes(&data.to_vec()) {
-            Ok(message) => match message.inner {
-                Some(FromClientInner::initialize(initial_values)) => {
-                    match self.initialize(initial_values) {
-                        Err(error) => self.send_error(ctx, error),
-                        Ok(()) => {
-                            if let Err(error) = self.send_csvs(ctx) {
-                                self.send_error(ctx, error);
-                            }
-                        }
-                    }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.dispatch_message(ctx, data);
+        }));
+
+        if let Err(payload) = result {
+            let location = LAST_PANIC_LOCATION.with(|cell| cell.borrow_mut().take());
+            let error = PanicError {
+                message: describe_panic_payload(&payload),
+                location,
+            };
+
+            self.send_error(ctx, error);
+        }
+    }
+
+    fn dispatch_message(&mut self, ctx: &mut ws::WebsocketContext<WebsocketConnection>, data: Bytes) {
+        let envelope: ClientEnvelope = match serde_json::from_slice(&data) {
+            Ok(envelope) => envelope,
+            Err(error) => {
+                self.send_error(ctx, MessageParseError(error));
+                return;
+            }
+        };
+
+        if envelope.protocol_version != PROTOCOL_VERSION {
+            self.send_error(
+                ctx,
+                ProtocolVersionMismatchError {
+                    received: envelope.protocol_version,
+                },
+            );
+            return;
+        }
+
+        match envelope.message {
+            ClientMsg::Initialize(initial_values) => match self.initialize(initial_values) {
+                Err(error) => self.send_error(ctx, error),
+                Ok(()) => {
+                    self.send_csvs(ctx);
                 }
-                Some(FromClientInner::set_column_index_filters(set_column_index_filters)) => {
-                    match self.set_column_index_filters(set_column_index_filters) {
-                        Err(error) => self.send_error(ctx, error),
-                        Ok(()) => {
-                            if let Err(error) = self.send_csvs(ctx) {
-                                self.send_error(ctx, error);
-                            }
-                        }
+            },
+            ClientMsg::SetColumnIndexFilters { filters } => {
+                match self.set_column_index_filters(filters) {
+                    Err(error) => self.send_error(ctx, error),
+                    Ok(()) => {
+                        self.send_csvs(ctx);
                     }
                 }
-                Some(FromClientInner::set_column_regex_filter(set_column_regex_filter)) => {
-                    match self.set_column_regex_filter(set_column_regex_filter) {
-                        Err(error) => self.send_error(ctx, error),
-                        Ok(()) => {
-                            if let Err(error) = self.send_csvs(ctx) {
-                                self.send_error(ctx, error);
-                            }
-                        }
+            }
+            ClientMsg::SetColumnRegexFilter { filter } => {
+                match self.set_column_regex_filter(filter) {
+                    Err(error) => self.send_error(ctx, error),
+                    Ok(()) => {
+                        self.send_csvs(ctx);
                     }
                 }
-                Some(FromClientInner::set_column_filter_combination(
-                    set_column_filter_combination,
-                )) => {
-                    self.set_column_filter_combination(set_column_filter_combination);
+            }
+            ClientMsg::SetColumnFilterCombination { combination } => {
+                self.set_column_filter_combination(combination);
 
-                    if let Err(error) = self.send_csvs(ctx) {
-                        self.send_error(ctx, error);
-                    }
-                }
-                Some(FromClientInner::set_column_separators(set_column_separators)) => {
-                    match self.set_column_separators(set_column_separators) {
-                        Err(error) => self.send_error(ctx, error),
-                        Ok(()) => {
-                            if let Err(error) = self.send_csvs(ctx) {
-                                self.send_error(ctx, error);
-                            }
-                        }
+                self.send_csvs(ctx);
+            }
+            ClientMsg::SetColumnSeparators { separators } => {
+                match self.set_column_separators(separators) {
+                    Err(error) => self.send_error(ctx, error),
+                    Ok(()) => {
+                        self.send_csvs(ctx);
                     }
                 }
-                Some(FromClientInner::set_column_regex_separator(set_column_regex_separator)) => {
-                    match self.set_column_regex_separator(set_column_regex_separator) {
-                        Err(error) => self.send_error(ctx, error),
-                        Ok(()) => {
-                            if let Err(error) = self.send_csvs(ctx) {
-                                self.send_error(ctx, error);
-                            }
-                        }
+            }
+            ClientMsg::SetColumnRegexSeparator { separator } => {
+                match self.set_column_regex_separator(separator) {
+                    Err(error) => self.send_error(ctx, error),
+                    Ok(()) => {
+                        self.send_csvs(ctx);
                     }
                 }
-                Some(FromClientInner::set_row_index_filters(set_row_index_filters)) => {
-                    match self.set_row_index_filters(set_row_index_filters) {
-                        Err(error) => self.send_error(ctx, error),
-                        Ok(()) => {
-                            if let Err(error) = self.send_csvs(ctx) {
-                                self.send_error(ctx, error);
-                            }
-                        }
+            }
+            ClientMsg::SetRowIndexFilters { filters } => {
+                match self.set_row_index_filters(filters) {
+                    Err(error) => self.send_error(ctx, error),
+                    Ok(()) => {
+                        self.send_csvs(ctx);
                     }
                 }
-                Some(FromClientInner::set_row_regex_filter(set_row_regex_filter)) => {
-                    match self.set_row_regex_filter(set_row_regex_filter) {
-                        Err(error) => self.send_error(ctx, error),
-                        Ok(()) => {
-                            if let Err(error) = self.send_csvs(ctx) {
-                                self.send_error(ctx, error);
-                            }
-                        }
-                    }
+            }
+            ClientMsg::SetRowRegexFilter { filter } => match self.set_row_regex_filter(filter) {
+                Err(error) => self.send_error(ctx, error),
+                Ok(()) => {
+                    self.send_csvs(ctx);
                 }
-                Some(FromClientInner::set_row_filter_combination(set_row_filter_combination)) => {
-                    self.set_row_filter_combination(set_row_filter_combination);
+            },
+            ClientMsg::SetRowFilterCombination { combination } => {
+                self.set_row_filter_combination(combination);
 
-                    if let Err(error) = self.send_csvs(ctx) {
-                        self.send_error(ctx, error);
+                self.send_csvs(ctx);
+            }
+            ClientMsg::SetRowSeparators { separators } => {
+                match self.set_row_separators(separators) {
+                    Err(error) => self.send_error(ctx, error),
+                    Ok(()) => {
+                        self.send_csvs(ctx);
                     }
                 }
-                Some(FromClientInner::set_row_separators(set_row_separators)) => {
-                    match self.set_row_separators(set_row_separators) {
-                        Err(error) => self.send_error(ctx, error),
-                        Ok(()) => {
-                            if let Err(error) = self.send_csvs(ctx) {
-                                self.send_error(ctx, error);
-                            }
-                        }
+            }
+            ClientMsg::SetRowRegexSeparator { separator } => {
+                match self.set_row_regex_separator(separator) {
+                    Err(error) => self.send_error(ctx, error),
+                    Ok(()) => {
+                        self.send_csvs(ctx);
                     }
                 }
-                Some(FromClientInner::set_row_regex_separator(set_row_regex_separator)) => {
-                    match self.set_row_regex_separator(set_row_regex_separator) {
-                        Err(error) => self.send_error(ctx, error),
-                        Ok(()) => {
-                            if let Err(error) = self.send_csvs(ctx) {
-                                self.send_error(ctx, error);
-                            }
-                        }
+            }
+            ClientMsg::SetColumnSort { keys } => {
+                self.set_column_sort(keys);
+
+                self.send_csvs(ctx);
+            }
+            ClientMsg::SetRowSort { keys } => {
+                self.set_row_sort(keys);
+
+                self.send_csvs(ctx);
+            }
+            ClientMsg::SetColumnDedup { field_indices } => {
+                self.set_column_dedup(field_indices);
+
+                self.send_csvs(ctx);
+            }
+            ClientMsg::SetRowDedup { field_indices } => {
+                self.set_row_dedup(field_indices);
+
+                self.send_csvs(ctx);
+            }
+            ClientMsg::SetColumnValueFilter { expressions } => {
+                match self.set_column_value_filter(expressions) {
+                    Err(error) => self.send_error(ctx, error),
+                    Ok(()) => {
+                        self.send_csvs(ctx);
                     }
                 }
-                None => {
-                    self.send_error(ctx, EmptyMessageError);
+            }
+            ClientMsg::SetRowValueFilter { expressions } => {
+                match self.set_row_value_filter(expressions) {
+                    Err(error) => self.send_error(ctx, error),
+                    Ok(()) => {
+                        self.send_csvs(ctx);
+                    }
                 }
-            },
-            Err(error) => {
-                self.send_error(ctx, MessageParseError(error));
+            }
+            ClientMsg::SetViewport { row_offset, row_limit } => {
+                self.set_viewport(row_offset, row_limit);
+
+                self.send_csvs(ctx);
+            }
+            ClientMsg::SetOutputFormat { format, has_header } => {
+                self.set_output_format(format, has_header);
+
+                self.send_csvs(ctx);
+            }
+            ClientMsg::SetExplain { enabled } => {
+                self.set_explain(enabled);
+
+                self.send_csvs(ctx);
             }
         }
     }
@@ -561,6 +1106,56 @@ impl WebsocketConnection {
             self.handle_message(ctx, frozen_data);
         }
     }
+
+    /// Logs the peer's `CloseReason` (or lack of one), preserving the session if the close looks
+    /// abnormal rather than a plain, expected `Normal` close, then stops this connection's actor.
+    /// Whether that also tears down the whole `System` depends on `shutdown_mode`.
+    fn handle_close(
+        &mut self,
+        ctx: &mut ws::WebsocketContext<WebsocketConnection>,
+        reason: Option<CloseReason>,
+    ) {
+        match &reason {
+            Some(r) if r.code == CloseCode::Normal => {
+                log::info!("Client closed the connection normally: {:?}", r.description);
+            }
+            Some(r) => {
+                log::warn!(
+                    "Client closed the connection abnormally: code={:?} description={:?}",
+                    r.code,
+                    r.description
+                );
+                self.persist_session();
+            }
+            None => {
+                log::warn!("Client closed the connection without a reason.");
+                self.persist_session();
+            }
+        }
+
+        ctx.close(reason);
+        ctx.stop();
+
+        if self.shutdown_mode == ShutdownMode::SingleSession {
+            System::current().stop();
+        }
+    }
+
+    /// Preserves this connection's state in `session_store` under `session_id`, if the client
+    /// provided one in `Initialize`. A no-op otherwise, since there'd be nothing for a
+    /// reconnecting client to ask for.
+    fn persist_session(&mut self) {
+        if let Some(session_id) = self.session_id.take() {
+            self.session_store.save(
+                &session_id,
+                &SessionState {
+                    stdin: std::mem::replace(&mut self.stdin, vec![]),
+                    column_options: std::mem::replace(&mut self.column_raw, PersistedOptions::default()),
+                    row_options: std::mem::replace(&mut self.row_raw, PersistedOptions::default()),
+                },
+            );
+        }
+    }
 }
 
 impl Actor for WebsocketConnection {
@@ -568,16 +1163,48 @@ impl Actor for WebsocketConnection {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         // Begin watching for the client's heartbeat messages.
-        ctx.run_interval(HEARTBEAT_INTERVAL, |connection, ctx| {
-            // Have we timed out?  If so, close this connection.
-            if Instant::now().duration_since(connection.last_seen_heartbeat) > CLIENT_TIMEOUT {
+        ctx.run_interval(self.heartbeat_interval, |connection, ctx| {
+            // Have we timed out? If so, preserve our state for a reconnect, tell the client why
+            // we're closing, and stop this actor, without tearing down the rest of the process.
+            if Instant::now().duration_since(connection.last_seen_heartbeat) > connection.client_timeout {
+                connection.persist_session();
+                ctx.close(Some(CloseReason::from(CloseCode::Away)));
                 ctx.stop();
-                System::current().stop();
                 return;
             }
 
+            let stdin_len_before = connection.stdin.len();
+            connection.refresh_stdin();
+            if connection.stdin.len() != stdin_len_before {
+                connection.send_csvs(ctx);
+            }
+
             ctx.ping(b"");
         });
+
+        if let Some(file_watcher) = &self.file_watcher {
+            file_watcher.do_send(file_watch::Register(ctx.address()));
+        }
+    }
+
+    /// Drains any rows left in the outbound buffer before the actor actually stops, so a client
+    /// doesn't miss the trailing rows of a result just because a flush timer hadn't fired yet.
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        while !self.pending_rows.is_empty() {
+            self.flush_outbound(ctx);
+        }
+
+        Running::Stop
+    }
+}
+
+impl Handler<file_watch::ApplyStdin> for WebsocketConnection {
+    type Result = ();
+
+    fn handle(&mut self, file_watch::ApplyStdin(stdin): file_watch::ApplyStdin, ctx: &mut Self::Context) {
+        self.stdin = stdin;
+        self.invalidate_cache();
+        self.send_csvs(ctx);
     }
 }
 
@@ -614,9 +1241,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketConnecti
                 self.handle_message(ctx, data);
             }
             Ok(ws::Message::Close(reason)) => {
-                ctx.close(reason);
-                ctx.stop();
-                System::current().stop();
+                self.handle_close(ctx, reason);
             }
             Err(error) => {
                 log::error!("{}", error);